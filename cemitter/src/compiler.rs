@@ -0,0 +1,149 @@
+use crate::error::EmitError;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// tried in this order when $CC isn't set
+const COMPILERS: [&str; 3] = ["cc", "gcc", "clang"];
+
+/// The system C compiler, for turning the generated C into an executable.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CCompiler {
+    pub program: PathBuf,
+    /// arguments that always come first, e.g. from `CC="gcc -m32"`
+    pub args: Vec<String>,
+}
+
+impl CCompiler {
+    /// Find a C compiler: `$CC` if it is set, otherwise the first of cc, gcc and clang on
+    /// the PATH.
+    pub fn locate() -> Result<Self, EmitError> {
+        Self::locate_in(env::var("CC").ok(), env::var_os("PATH"))
+    }
+
+    fn locate_in(cc: Option<String>, path: Option<OsString>) -> Result<Self, EmitError> {
+        // $CC can carry its own flags, split them off the way make does
+        if let Some(cc) = cc.filter(|cc| !cc.trim().is_empty()) {
+            let mut words = cc.split_whitespace().map(String::from);
+            let program = words.next().unwrap_or_default();
+            return Ok(CCompiler {
+                program: find_program(&program, path.as_ref()).unwrap_or(program.into()),
+                args: words.collect(),
+            });
+        }
+        COMPILERS
+            .iter()
+            .find_map(|name| find_program(name, path.as_ref()))
+            .map(|program| CCompiler {
+                program,
+                args: Vec::new(),
+            })
+            .ok_or_else(|| EmitError::NoCompiler {
+                tried: COMPILERS.iter().map(|name| name.to_string()).collect(),
+            })
+    }
+
+    /// Compile `source` into the executable `output`, passing `flags` after our own. Hands
+    /// back anything the compiler printed, which will be warnings.
+    pub fn compile(
+        &self,
+        source: &Path,
+        output: &Path,
+        flags: &[String],
+    ) -> Result<String, EmitError> {
+        let result = Command::new(&self.program)
+            .args(&self.args)
+            .arg(source)
+            .arg("-o")
+            .arg(output)
+            .args(flags)
+            // pow and fmod live in libm
+            .arg("-lm")
+            .output()
+            .map_err(|error| EmitError::CompilerIo {
+                compiler: self.program.display().to_string(),
+                error,
+            })?;
+        let stderr = String::from_utf8_lossy(&result.stderr).into_owned();
+        if result.status.success() {
+            return Ok(stderr);
+        }
+        Err(EmitError::CompilerFailed {
+            compiler: self.program.display().to_string(),
+            status: result.status.code(),
+            stderr,
+        })
+    }
+}
+
+// look for an executable on the PATH, names with a slash in them are used as they are
+fn find_program(name: &str, path: Option<&OsString>) -> Option<PathBuf> {
+    if name.contains(std::path::MAIN_SEPARATOR) || name.contains('/') {
+        let program = PathBuf::from(name);
+        return program.is_file().then_some(program);
+    }
+    env::split_paths(path?).find_map(|dir| {
+        let program = dir.join(name);
+        if program.is_file() {
+            return Some(program);
+        }
+        let program = program.with_extension(env::consts::EXE_EXTENSION);
+        program.is_file().then_some(program)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate() {
+        let dir = env::temp_dir().join(format!("tt_locate_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("gcc"), "").unwrap();
+        let path = Some(dir.clone().into_os_string());
+
+        // cc isn't there so gcc is next
+        let found = CCompiler::locate_in(None, path.clone()).unwrap();
+        assert_eq!(found.program, dir.join("gcc"));
+
+        // $CC wins, flags and all
+        let found = CCompiler::locate_in(Some("gcc -m32 -g".to_string()), path).unwrap();
+        assert_eq!(found.program, dir.join("gcc"));
+        assert_eq!(found.args, vec!["-m32", "-g"]);
+
+        let error = CCompiler::locate_in(None, Some(OsString::new())).unwrap_err();
+        assert!(matches!(error, EmitError::NoCompiler { .. }));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_compiler_failure() {
+        // `false` ignores its arguments and fails, like a compiler that didn't like our C
+        let compiler = CCompiler {
+            program: "false".into(),
+            args: Vec::new(),
+        };
+        let error = compiler
+            .compile(Path::new("out.c"), Path::new("out"), &[])
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            EmitError::CompilerFailed {
+                status: Some(1),
+                ..
+            }
+        ));
+
+        let compiler = CCompiler {
+            program: "/no/such/cc".into(),
+            args: Vec::new(),
+        };
+        let error = compiler
+            .compile(Path::new("out.c"), Path::new("out"), &[])
+            .unwrap_err();
+        assert!(matches!(error, EmitError::CompilerIo { .. }));
+    }
+}
@@ -0,0 +1,53 @@
+use std::{error::Error, fmt, io};
+
+/// Failures while writing the generated C out or compiling it.
+#[derive(Debug)]
+pub enum EmitError {
+    /// the output file couldn't be created or written to
+    Io { path: String, error: io::Error },
+    /// none of the usual C compilers are installed
+    NoCompiler { tried: Vec<String> },
+    /// the C compiler couldn't be started
+    CompilerIo { compiler: String, error: io::Error },
+    /// the C compiler ran but rejected the generated C, `status` is None if it was killed
+    CompilerFailed {
+        compiler: String,
+        status: Option<i32>,
+        stderr: String,
+    },
+}
+
+impl fmt::Display for EmitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmitError::Io { path, error } => {
+                write!(f, "Could not write to file '{}': {}", path, error)
+            }
+            EmitError::NoCompiler { tried } => {
+                write!(f, "Could not find a C compiler, tried {}", tried.join(", "))
+            }
+            EmitError::CompilerIo { compiler, error } => {
+                write!(f, "Could not run the C compiler '{}': {}", compiler, error)
+            }
+            EmitError::CompilerFailed {
+                compiler, status, ..
+            } => match status {
+                Some(status) => write!(
+                    f,
+                    "The C compiler '{}' failed with exit code {}",
+                    compiler, status
+                ),
+                None => write!(f, "The C compiler '{}' was killed", compiler),
+            },
+        }
+    }
+}
+
+impl Error for EmitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EmitError::Io { error, .. } | EmitError::CompilerIo { error, .. } => Some(error),
+            EmitError::NoCompiler { .. } | EmitError::CompilerFailed { .. } => None,
+        }
+    }
+}
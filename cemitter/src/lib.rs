@@ -1,9 +1,51 @@
+pub mod compiler;
+pub mod error;
+mod runtime;
+
+use error::EmitError;
+use parser::ast::{
+    self, BinaryOp, Builtin, Comparison, Condition, Expr, Function, Program, Statement,
+    StatementKind, Type, DEFAULT_MAX_DEPTH,
+};
+use runtime::RUNTIME;
+use std::collections::{HashMap, HashSet};
 use std::{fs::File, io::Write};
 
 pub struct Emitter {
     full_path: String,
     header: String,
     code: String,
+    // variables that already have a `float var_x = 0;` in the header, or are parameters
+    symbols: HashSet<String>,
+    // the .tt file to point #line directives at, if we're emitting them
+    source_file: Option<String>,
+    // whether anything needs <math.h>
+    uses_math: bool,
+    // how many temporaries comparison chains have declared
+    temporaries: usize,
+    // how deep GOSUBs can nest, the size of the return stack
+    max_depth: usize,
+    // how many GOSUBs there are, each has a number to RETURN to it by
+    gosubs: usize,
+    // whether anything needs the return stack
+    uses_gosub: bool,
+    // whether we're emitting a FUNCTION rather than main
+    in_function: bool,
+    // whether anything needs <string.h>
+    uses_strings: bool,
+    // the parts of RUNTIME the program calls
+    runtime: HashSet<&'static str>,
+    // what main or the FUNCTION being emitted frees before it returns, as C statements
+    frees: Vec<String>,
+    // whether the FUNCTION being emitted has strings or arrays to tidy up, which its
+    // RETURNs have to go through rather than just returning
+    tidy_up: bool,
+    // whether the FUNCTION being emitted has a RETURN, which jumps to its tidying up
+    returns: bool,
+    // the sizes of the arrays of main or the FUNCTION being emitted
+    arrays: HashMap<String, Vec<usize>>,
+    // the .tt line of the statement being emitted, for runtime errors
+    line: usize,
 }
 
 impl Emitter {
@@ -12,9 +54,36 @@ impl Emitter {
             full_path,
             header: "".to_string(),
             code: "".to_string(),
+            symbols: HashSet::new(),
+            source_file: None,
+            uses_math: false,
+            temporaries: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            gosubs: 0,
+            uses_gosub: false,
+            in_function: false,
+            uses_strings: false,
+            runtime: HashSet::new(),
+            frees: Vec::new(),
+            tidy_up: false,
+            returns: false,
+            arrays: HashMap::new(),
+            line: 0,
         }
     }
 
+    /// Make the program stop with an error when GOSUBs, or function calls, nest deeper than
+    /// this.
+    pub fn max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// Put a `#line` directive before each statement so C compiler messages, debuggers and
+    /// sanitizers point at the line of `source_file` the statement came from.
+    pub fn line_directives(&mut self, source_file: &str) {
+        self.source_file = Some(source_file.to_string());
+    }
+
     pub fn emit(&mut self, code: String) {
         self.code.push_str(&code);
     }
@@ -29,15 +98,1267 @@ impl Emitter {
         self.header.push('\n');
     }
 
-    pub fn write_file(&mut self) {
+    /// The generated C so far, header first.
+    pub fn output(&self) -> String {
+        format!("{}{}", self.header, self.code)
+    }
+
+    pub fn write_file(&mut self) -> Result<(), EmitError> {
         // 'borrowed' from sgmarz's implementation
-        if let Ok(mut f) = File::create(&self.full_path) {
-            // unwraps are kind of okay because error checking on outside
-            f.write_all(self.header.as_bytes()).unwrap();
-            f.write_all(self.code.as_bytes()).unwrap();
-            f.sync_all().unwrap();
+        let write = || -> std::io::Result<()> {
+            let mut f = File::create(&self.full_path)?;
+            f.write_all(self.header.as_bytes())?;
+            f.write_all(self.code.as_bytes())?;
+            f.sync_all()
+        };
+        write().map_err(|error| EmitError::Io {
+            path: self.full_path.clone(),
+            error,
+        })
+    }
+
+    /// Generate the C for a whole program.
+    pub fn program(&mut self, program: &Program) {
+        let functions: Vec<&Statement> = program
+            .statements
+            .iter()
+            .filter(|statement| matches!(statement.kind, StatementKind::Function(_)))
+            .collect();
+        if !functions.is_empty() {
+            // declared up front so they can call each other whatever order they're in
+            for statement in &functions {
+                if let StatementKind::Function(function) = &statement.kind {
+                    self.header_line(format!("{};", prototype(function)));
+                }
+            }
+            for statement in &functions {
+                if let StatementKind::Function(function) = &statement.kind {
+                    self.function(function, statement.span.line);
+                }
+            }
+        }
+        self.header_line("int main(void) {".to_string());
+        for (name, sizes) in ast::arrays(&program.statements) {
+            self.declare_array(name, sizes);
+        }
+
+        for statement in &program.statements {
+            if !matches!(statement.kind, StatementKind::Function(_)) {
+                self.statement(statement);
+            }
+        }
+
+        // by this point program is done
+        for free in std::mem::take(&mut self.frees) {
+            self.emit_line(free);
+        }
+        self.emit_line("return 0;".to_string());
+        if self.uses_gosub {
+            // C can't goto a label stored in a variable, so RETURN comes here and picks the
+            // label after the GOSUB by its number
+            self.emit_line("gosub_return:".to_string());
+            self.emit_line("switch (gosub_stack[--gosub_depth]) {".to_string());
+            for gosub in 1..=self.gosubs {
+                self.emit_line(format!("case {}: goto gosub_{};", gosub, gosub));
+            }
+            self.emit_line("}".to_string());
+        }
+        self.emit_line("}".to_string());
+        self.header
+            .insert_str(0, &self.prelude(!functions.is_empty()));
+    }
+
+    // the includes and the parts of the string runtime the program turned out to need.
+    // Only what's used goes in, so -Wall has no unused functions to warn about
+    fn prelude(&self, has_functions: bool) -> String {
+        let mut used = self.runtime.clone();
+        // everything only needs what comes before it
+        for (name, needs, _) in RUNTIME.iter().rev() {
+            if used.contains(name) {
+                used.extend(needs.iter());
+            }
+        }
+        let mut prelude = String::new();
+        if self.uses_math {
+            prelude.push_str("#include <math.h>\n");
+        }
+        prelude.push_str("#include <stdio.h>\n");
+        // for exit(), a runtime error in a function can't just return from main, and for
+        // the string runtime's memory
+        if has_functions || !used.is_empty() {
+            prelude.push_str("#include <stdlib.h>\n");
+        }
+        if self.uses_strings {
+            prelude.push_str("#include <string.h>\n");
+        }
+        if used.contains("tt_input") {
+            prelude.push_str("#include <ctype.h>\n");
+        }
+        for (name, _, code) in RUNTIME {
+            if used.contains(name) {
+                prelude.push_str(&code.text());
+            }
+        }
+        prelude
+    }
+
+    // call one of the runtime's functions
+    fn use_runtime(&mut self, name: &'static str) {
+        self.uses_strings = true;
+        self.runtime.insert(name);
+    }
+
+    // A FUNCTION becomes a C function that takes how deep the calls are as well as its
+    // parameters, and checks that first. Its variables are declared at the top of it, so
+    // while its body is emitted it gets a header, code and variables of its own.
+    //
+    // One with strings or arrays in it owns copies of its string parameters and variables,
+    // and keeps track of where the temporaries it makes start. Every RETURN goes through the
+    // end of it, where all of those are freed and a string result is handed to the caller
+    // as one of its temporaries.
+    fn function(&mut self, function: &Function, line: usize) {
+        let header = std::mem::take(&mut self.header);
+        let code = std::mem::take(&mut self.code);
+        let symbols =
+            std::mem::replace(&mut self.symbols, function.params.iter().cloned().collect());
+        let params: Vec<String> = function
+            .params
+            .iter()
+            .filter(|param| is_string(param))
+            .cloned()
+            .collect();
+        let frees = params
+            .iter()
+            .map(|param| format!("free({});", c_name(param)))
+            .collect();
+        let frees = std::mem::replace(&mut self.frees, frees);
+        let arrays = std::mem::take(&mut self.arrays);
+        self.in_function = true;
+        let result = Type::of_name(&function.name);
+        let dims = ast::arrays(&function.body);
+        self.tidy_up = result == Type::String
+            || !params.is_empty()
+            || !dims.is_empty()
+            || has_strings(&function.body);
+        if self.tidy_up {
+            self.use_runtime("tt_temps");
+            self.header_line("size_t tt_mark = tt_temp_count;".to_string());
+            self.header_line(format!("{};", declaration(CType::of(result), "tt_result")));
+        }
+        for (name, sizes) in dims {
+            self.declare_array(name, sizes);
+        }
+
+        self.line_directive(line);
+        self.emit_line(format!("if (call_depth > {}) {{", self.max_depth));
+        self.runtime_error(format!(
+            "Function calls nested more than {} deep at line {}",
+            self.max_depth, line
+        ));
+        // the caller's strings could go away while this runs, so it keeps its own
+        for param in params {
+            self.use_runtime("tt_copy");
+            self.emit_line(format!("{} = tt_copy({});", c_name(&param), c_name(&param)));
+        }
+        for statement in &function.body {
+            self.statement(statement);
+        }
+        if self.tidy_up {
+            // running off the end gives back 0 or ""
+            if result == Type::String {
+                self.use_runtime("tt_copy");
+                self.emit_line("tt_result = tt_copy(\"\");".to_string());
+            } else {
+                self.emit_line("tt_result = 0;".to_string());
+            }
+            if self.returns {
+                self.emit_line("tt_return:".to_string());
+            }
+            self.emit_line("tt_free_temps(tt_mark);".to_string());
+            for free in std::mem::take(&mut self.frees) {
+                self.emit_line(free);
+            }
+            if result == Type::String {
+                self.use_runtime("tt_temp");
+                self.emit_line("return tt_temp(tt_result);".to_string());
+            } else {
+                self.emit_line("return tt_result;".to_string());
+            }
+        } else {
+            // running off the end gives back 0
+            self.emit_line("return 0;".to_string());
+        }
+
+        let body = self.output();
+        self.header = header;
+        self.code = code;
+        self.symbols = symbols;
+        self.frees = frees;
+        self.arrays = arrays;
+        self.in_function = false;
+        self.tidy_up = false;
+        self.returns = false;
+        self.header_line(format!("{} {{", prototype(function)));
+        self.header.push_str(&body);
+        self.header_line("}".to_string());
+    }
+
+    // every variable is a float or a string declared at the top of main, or of its
+    // function. A float starts as 0, like in the interpreter, and a string variable always
+    // points at its own copy, starting with ""
+    fn declare(&mut self, name: &str) {
+        if self.symbols.insert(name.to_string()) {
+            if is_string(name) {
+                self.use_runtime("tt_copy");
+                self.header_line(format!("char *{} = tt_copy(\"\");", c_name(name)));
+                self.frees.push(format!("free({});", c_name(name)));
+            } else {
+                self.header_line(format!("float {} = 0;", c_name(name)));
+            }
+        }
+    }
+
+    // An array is made when main or its FUNCTION starts, `dim_` and its name in the C.
+    // Numbers in main are static, so big ones don't use up the C stack and start as 0.
+    // Anywhere else they're made afresh each call, and strings always start as copies of ""
+    fn declare_array(&mut self, name: &str, sizes: &[usize]) {
+        let count: usize = sizes.iter().product();
+        let array = format!("dim_{}", identifier(name));
+        if is_string(name) {
+            self.use_runtime("tt_strings");
+            self.header_line(format!("char **{} = tt_strings({});", array, count));
+            self.frees
+                .push(format!("tt_free_strings({}, {});", array, count));
+        } else if self.in_function {
+            self.use_runtime("tt_numbers");
+            self.header_line(format!("float *{} = tt_numbers({});", array, count));
+            self.frees.push(format!("free({});", array));
         } else {
-            panic!("Could not open file for writing: '{}'", self.full_path);
+            self.header_line(format!("static float {}[{}];", array, count));
+        }
+        self.arrays.insert(name.to_string(), sizes.to_vec());
+    }
+
+    // free the temporary strings made since main, or the current function, started
+    fn free_temps(&mut self) {
+        self.use_runtime("tt_temps");
+        let mark = if self.in_function { "tt_mark" } else { "0" };
+        self.emit_line(format!("tt_free_temps({});", mark));
+    }
+
+    // point what comes next at a line of the .tt file, if we're doing that
+    fn line_directive(&mut self, line: usize) {
+        if let Some(file) = &self.source_file {
+            // the file name is a C string, so escape it like one
+            let file = file.replace('\\', "\\\\").replace('"', "\\\"");
+            self.emit_line(format!("#line {} \"{}\"", line, file));
+        }
+    }
+
+    // a statement inside another one puts the line back when it's done, for the conditions
+    // of the ELSEIFs after it
+    fn statement(&mut self, statement: &Statement) {
+        let line = std::mem::replace(&mut self.line, statement.span.line);
+        self.line_directive(statement.span.line);
+        self.statement_kind(statement);
+        self.line = line;
+    }
+
+    fn statement_kind(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::PrintString(text) => {
+                self.emit_line(format!("printf(\"{}\\n\");", text));
+            }
+            StatementKind::PrintExpr(expr) if expr.ty() == Type::String => {
+                self.emit("printf(\"%s\\n\", ".to_string());
+                self.expression(expr);
+                self.emit_line(");".to_string());
+                self.free_temps_after(expr);
+            }
+            StatementKind::PrintExpr(expr) => {
+                // printf("%.2f\n", (float)(EXPRESSION))
+                self.emit("printf(\"%.2f\\n\", (float)(".to_string());
+                self.expression(expr);
+                self.emit_line("));".to_string()); // one bracket to close expression and 1 for printf
+                self.free_temps_after(expr);
+            }
+            StatementKind::If {
+                branches,
+                else_body,
+            } => {
+                // temporaries the conditions make are freed once whichever arm runs starts,
+                // and again after the lot in case none of them did
+                let temps = branches
+                    .iter()
+                    .any(|(condition, _)| condition_makes_temps(condition));
+                for (index, (condition, body)) in branches.iter().enumerate() {
+                    // each ELSEIF hangs off the closing brace of the arm before it
+                    self.emit(if index == 0 { "if (" } else { "else if (" }.to_string());
+                    self.condition(condition);
+                    self.emit_line("){".to_string()); // closing if comparison and opening block
+                    self.block(body, temps);
+                }
+                if let Some(body) = else_body {
+                    self.emit_line("else {".to_string());
+                    self.block(body, temps);
+                }
+                if temps {
+                    self.free_temps();
+                }
+            }
+            StatementKind::While { condition, body } => {
+                let temps = condition_makes_temps(condition);
+                self.emit("while(".to_string());
+                self.condition(condition);
+                self.emit_line("){".to_string());
+                self.block(body, temps);
+                if temps {
+                    self.free_temps();
+                }
+            }
+            StatementKind::For {
+                variable,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                // for (i = START; STEP >= 0 ? i <= END : i >= END; i += STEP){
+                self.declare(variable);
+                let variable = c_name(variable);
+                self.emit(format!("for ({} = ", variable));
+                self.expression(start);
+                self.emit("; ".to_string());
+                match step {
+                    Some(step) => {
+                        self.expression(step);
+                        self.emit(format!(" >= 0 ? {} <= ", variable));
+                        self.expression(end);
+                        self.emit(format!(" : {} >= ", variable));
+                        self.expression(end);
+                        self.emit(format!("; {} += ", variable));
+                        self.expression(step);
+                    }
+                    None => {
+                        self.emit(format!("{} <= ", variable));
+                        self.expression(end);
+                        self.emit(format!("; {} += 1", variable));
+                    }
+                }
+                self.emit_line("){".to_string());
+                let temps = [Some(start), Some(end), step.as_ref()]
+                    .into_iter()
+                    .flatten()
+                    .any(makes_temps);
+                self.block(body, temps);
+                if temps {
+                    self.free_temps();
+                }
+            }
+            StatementKind::Break => self.emit_line("break;".to_string()),
+            StatementKind::Continue => self.emit_line("continue;".to_string()),
+            StatementKind::Label(name) => self.emit_line(format!("{}:", name)),
+            StatementKind::Goto(name) => self.emit_line(format!("goto {};", name)),
+            StatementKind::Gosub(name) => {
+                self.return_stack();
+                self.gosubs += 1;
+                self.emit_line(format!("if (gosub_depth == {}) {{", self.max_depth));
+                self.runtime_error(format!(
+                    "GOSUB nested more than {} deep at line {}",
+                    self.max_depth, statement.span.line
+                ));
+                self.emit_line(format!("gosub_stack[gosub_depth++] = {};", self.gosubs));
+                self.emit_line(format!("goto {};", name));
+                self.emit_line(format!("gosub_{}:;", self.gosubs));
+            }
+            StatementKind::Return => {
+                self.return_stack();
+                self.emit_line("if (gosub_depth == 0) {".to_string());
+                self.runtime_error(format!(
+                    "RETURN without GOSUB at line {}",
+                    statement.span.line
+                ));
+                self.emit_line("goto gosub_return;".to_string());
+            }
+            // emitted before main, the parser keeps them out of everything else
+            StatementKind::Function(_) => {}
+            // the result has to be kept hold of while everything else is freed
+            StatementKind::ReturnValue(value) if self.tidy_up => {
+                self.returns = true;
+                if value.ty() == Type::String {
+                    self.use_runtime("tt_copy");
+                    self.emit("tt_result = tt_copy(".to_string());
+                    self.expression(value);
+                    self.emit_line(");".to_string());
+                } else {
+                    self.emit("tt_result = ".to_string());
+                    self.expression(value);
+                    self.emit_line(";".to_string());
+                }
+                self.emit_line("goto tt_return;".to_string());
+            }
+            StatementKind::ReturnValue(value) => {
+                self.emit("return ".to_string());
+                self.expression(value);
+                self.emit_line(";".to_string());
+            }
+            StatementKind::Let { name, value } if is_string(name) => {
+                self.declare(name);
+                self.use_runtime("tt_set");
+                self.emit(format!("tt_set(&{}, ", c_name(name)));
+                self.expression(value);
+                self.emit_line(");".to_string());
+                self.free_temps_after(value);
+            }
+            StatementKind::Let { name, value } => {
+                self.declare(name);
+                self.emit(format!("{} = ", c_name(name)));
+                self.expression(value);
+                self.emit_line(";".to_string());
+                self.free_temps_after(value);
+            }
+            StatementKind::LetElement {
+                name,
+                indices,
+                value,
+            } => {
+                if is_string(name) {
+                    self.use_runtime("tt_set");
+                    self.emit("tt_set(&".to_string());
+                    self.element(name, indices);
+                    self.emit(", ".to_string());
+                    self.expression(value);
+                    self.emit_line(");".to_string());
+                } else {
+                    self.element(name, indices);
+                    self.emit(" = ".to_string());
+                    self.expression(value);
+                    self.emit_line(";".to_string());
+                }
+                if indices.iter().chain([value]).any(makes_temps) {
+                    self.free_temps();
+                }
+            }
+            // made when main or the FUNCTION starts
+            StatementKind::Dim { .. } => {}
+            StatementKind::Input(name) if is_string(name) => {
+                self.declare(name);
+                self.use_runtime("tt_input");
+                self.emit_line(format!("tt_input(&{});", c_name(name)));
+            }
+            StatementKind::Input(name) => {
+                self.declare(name);
+                // emit scanf and validate input. If invalid set to 0 and clear input
+                let name = c_name(name);
+                self.emit_line(format!("if (0 == scanf(\"%f\", &{})) {{", name)); // if (0 == scanf("%f", &example_float));
+                self.emit_line(format!("{} = 0.0;", name)); // example_float = 0;
+                self.emit("scanf(\"%".to_string()); // scanf("%*s)
+                self.emit_line("*s\");".to_string());
+                self.emit_line("}".to_string());
+            }
+        }
+    }
+
+    // the body of a block, and its closing brace. `temps` frees what the condition made
+    // before anything else runs
+    fn block(&mut self, body: &[Statement], temps: bool) {
+        if temps {
+            self.free_temps();
+        }
+        for statement in body {
+            self.statement(statement);
+        }
+        self.emit_line("}".to_string());
+    }
+
+    fn free_temps_after(&mut self, expr: &Expr) {
+        if makes_temps(expr) {
+            self.free_temps();
+        }
+    }
+
+    // declare the GOSUB return stack the first time it's needed. It's static so a big
+    // --max-depth doesn't use up the C stack
+    fn return_stack(&mut self) {
+        if !self.uses_gosub {
+            self.uses_gosub = true;
+            // a zero length array isn't allowed, the depth check stops a 0 from using it
+            let size = self.max_depth.max(1);
+            self.header_line(format!("static int gosub_stack[{}];", size));
+            self.header_line("int gosub_depth = 0;".to_string());
+        }
+    }
+
+    // finish an `if (...) {` that stops the program with the same message the interpreter
+    // would give
+    fn runtime_error(&mut self, message: String) {
+        self.emit_line(format!("fprintf(stderr, \"Error: {}\\n\");", message));
+        self.emit_line(
+            if self.in_function {
+                "exit(1);"
+            } else {
+                "return 1;"
+            }
+            .to_string(),
+        );
+        self.emit_line("}".to_string());
+    }
+
+    // C's !, && and || bind the same way as NOT, AND and OR, and short circuit the same way.
+    // NOT needs brackets, to keep it off the first operand of the comparison, and so does an
+    // OR that was bracketed to go under an AND
+    fn condition(&mut self, condition: &Condition) {
+        match condition {
+            Condition::Comparison(comparison) => self.comparison(comparison),
+            Condition::Not(operand) => {
+                self.emit("!(".to_string());
+                self.condition(operand);
+                self.emit(")".to_string());
+            }
+            Condition::And(lhs, rhs) => {
+                self.and_operand(lhs);
+                self.emit(" && ".to_string());
+                self.and_operand(rhs);
+            }
+            Condition::Or(lhs, rhs) => {
+                self.condition(lhs);
+                self.emit(" || ".to_string());
+                self.condition(rhs);
+            }
+        }
+    }
+
+    // `(a OR b) AND c`
+    fn and_operand(&mut self, condition: &Condition) {
+        if let Condition::Or(..) = condition {
+            self.emit("(".to_string());
+            self.condition(condition);
+            self.emit(")".to_string());
+        } else {
+            self.condition(condition);
+        }
+    }
+
+    // `a < b < c` means `a < b && b < c`. Every operand in the middle is saved in a
+    // temporary as it's worked out, `a<(cmp_1 = b) && cmp_1<c`, so it only runs once and a
+    // false comparison stops the rest from running at all. Strings are compared with
+    // strcmp, `strcmp(a, b)==0`
+    fn comparison(&mut self, comparison: &Comparison) {
+        let strings = comparison.first.ty() == Type::String;
+        if strings {
+            self.uses_strings = true;
+        }
+        let last = comparison.rest.len() - 1;
+        let mut previous: Option<String> = None;
+        for (index, (op, expr)) in comparison.rest.iter().enumerate() {
+            if index > 0 {
+                self.emit(" && ".to_string());
+            }
+            if strings {
+                self.emit("strcmp(".to_string());
+            }
+            match previous.take() {
+                Some(temporary) => self.emit(temporary),
+                None => self.expression(&comparison.first),
+            }
+            self.emit(if strings { ", " } else { op.symbol() }.to_string());
+            if index == last {
+                self.expression(expr);
+            } else {
+                let temporary = self.temporary(c_type(expr));
+                self.emit(format!("({} = ", temporary));
+                self.expression(expr);
+                self.emit(")".to_string());
+                previous = Some(temporary);
+            }
+            if strings {
+                self.emit(format!("){}0", op.symbol()));
+            }
+        }
+    }
+
+    // declare a new variable of the given type. Teeny Tiny names can't have an underscore
+    // in them, so it can't clash with one
+    fn temporary(&mut self, c_type: CType) -> String {
+        self.temporaries += 1;
+        let name = format!("cmp_{}", self.temporaries);
+        self.header_line(format!("{};", declaration(c_type, &name)));
+        name
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Number(text) => self.emit(text.clone()),
+            Expr::Variable(name) => self.emit(c_name(name)),
+            // the lexer keeps quotes and backslashes out of strings, so it's already valid C
+            Expr::String(text) => self.emit(format!("\"{}\"", text)),
+            Expr::Unary(op, operand) => {
+                self.emit(op.symbol().to_string());
+                // -(a+b) needs its brackets back, and - -a mustn't become --a
+                let bracket = infix(operand).is_some() || matches!(**operand, Expr::Unary(..));
+                self.operand(operand, bracket);
+            }
+            Expr::Binary(op, lhs, rhs) if infix(expr).is_none() => {
+                // C has no operator for these, they're calls into the maths library or the
+                // string runtime
+                let function = match (op, c_type(expr)) {
+                    (_, CType::String) => "tt_concat",
+                    (BinaryOp::Pow, _) => "pow",
+                    (_, CType::Float) => "fmodf",
+                    _ => "fmod",
+                };
+                if function == "tt_concat" {
+                    self.use_runtime(function);
+                } else {
+                    self.uses_math = true;
+                }
+                self.emit(format!("{}(", function));
+                self.expression(lhs);
+                self.emit(", ".to_string());
+                self.expression(rhs);
+                self.emit(")".to_string());
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                // brackets go back in wherever C would read the tree differently, and around
+                // unary operands so that a-(-b) doesn't come out as a--b
+                let lhs_bracket = match lhs.as_ref() {
+                    Expr::Unary(..) => true,
+                    lhs => infix(lhs).is_some_and(|lhs_op| lhs_op.precedence() < op.precedence()),
+                };
+                let rhs_bracket = match rhs.as_ref() {
+                    Expr::Unary(..) => true,
+                    rhs => infix(rhs).is_some_and(|rhs_op| rhs_op.precedence() <= op.precedence()),
+                };
+                self.operand(lhs, lhs_bracket);
+                self.emit(op.symbol().to_string());
+                self.operand(rhs, rhs_bracket);
+            }
+            Expr::Call(name, args) => {
+                // one deeper than whatever called this
+                let depth = if self.in_function {
+                    "call_depth + 1"
+                } else {
+                    "1"
+                };
+                self.emit(format!("fn_{}({}", identifier(name), depth));
+                for arg in args {
+                    self.emit(", ".to_string());
+                    self.expression(arg);
+                }
+                self.emit(")".to_string());
+            }
+            Expr::Builtin(builtin, args) => {
+                let function = match c_function(*builtin) {
+                    CFunction::Math(function) => {
+                        self.uses_math = true;
+                        function
+                    }
+                    CFunction::Runtime(function) => {
+                        self.use_runtime(function);
+                        function
+                    }
+                };
+                self.emit(format!("{}(", function));
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        self.emit(", ".to_string());
+                    }
+                    // the compiler warns about fabs(-7), and it's the same value either way
+                    if let (CFunction::Math(_), CType::Int | CType::Long) =
+                        (c_function(*builtin), c_type(arg))
+                    {
+                        self.emit("(double)".to_string());
+                        self.operand(arg, infix(arg).is_some());
+                    } else {
+                        self.expression(arg);
+                    }
+                }
+                self.emit(")".to_string());
+            }
+            Expr::Index(name, indices) => self.element(name, indices),
+        }
+    }
+
+    // `dim_grid[tt_bound(i, 3, "...")*4 + tt_bound(j, 4, "...")]`, where tt_bound stops the
+    // program with the interpreter's error if an index is out of bounds
+    fn element(&mut self, name: &str, indices: &[Expr]) {
+        let sizes = self.arrays[name].clone();
+        self.runtime.insert("tt_bound");
+        let message = format!(
+            "Index out of bounds for array {}({}) at line {}",
+            name,
+            sizes
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+            self.line
+        );
+        self.emit(format!("dim_{}[", identifier(name)));
+        for (i, index) in indices.iter().enumerate() {
+            if i > 0 {
+                self.emit(" + ".to_string());
+            }
+            self.emit("tt_bound(".to_string());
+            self.expression(index);
+            self.emit(format!(", {}, \"{}\")", sizes[i], message));
+            let stride: usize = sizes[i + 1..].iter().product();
+            if stride > 1 {
+                self.emit(format!("*{}", stride));
+            }
+        }
+        self.emit("]".to_string());
+    }
+
+    fn operand(&mut self, expr: &Expr, bracket: bool) {
+        if bracket {
+            self.emit("(".to_string());
+            self.expression(expr);
+            self.emit(")".to_string());
+        } else {
+            self.expression(expr);
+        }
+    }
+}
+
+// `float fn_name(int call_depth, float var_a, char *var_b_)`. The prefixes keep functions
+// and variables apart from each other and from the C library.
+fn prototype(function: &Function) -> String {
+    let name = format!("fn_{}(int call_depth", identifier(&function.name));
+    let mut prototype = declaration(CType::of(Type::of_name(&function.name)), &name);
+    for param in &function.params {
+        let param = declaration(CType::of(Type::of_name(param)), &c_name(param));
+        prototype.push_str(&format!(", {}", param));
+    }
+    prototype.push(')');
+    prototype
+}
+
+// What a variable or parameter is called in the C. The prefix stops one called `exit`,
+// `pow` or `free` hiding the C function the generated code calls.
+fn c_name(name: &str) -> String {
+    format!("var_{}", identifier(name))
+}
+
+// A name as it goes after a prefix. The `$` on the end of a string name becomes an
+// underscore, which Teeny Tiny names can't have so nothing else is called that.
+fn identifier(name: &str) -> String {
+    name.replace('$', "_")
+}
+
+// Where the C function a built in function calls comes from.
+enum CFunction {
+    Math(&'static str),
+    // one of RUNTIME's
+    Runtime(&'static str),
+}
+
+fn c_function(builtin: Builtin) -> CFunction {
+    match builtin {
+        Builtin::Len => CFunction::Runtime("tt_len"),
+        Builtin::Left => CFunction::Runtime("tt_left"),
+        Builtin::Right => CFunction::Runtime("tt_right"),
+        Builtin::Mid => CFunction::Runtime("tt_mid"),
+        Builtin::Upper => CFunction::Runtime("tt_upper"),
+        Builtin::Lower => CFunction::Runtime("tt_lower"),
+        Builtin::Str => CFunction::Runtime("tt_str"),
+        Builtin::Val => CFunction::Runtime("tt_val"),
+        Builtin::Sqrt => CFunction::Math("sqrt"),
+        Builtin::Abs => CFunction::Math("fabs"),
+        Builtin::Int => CFunction::Math("trunc"),
+        Builtin::Floor => CFunction::Math("floor"),
+        Builtin::Ceil => CFunction::Math("ceil"),
+        Builtin::Sin => CFunction::Math("sin"),
+        Builtin::Cos => CFunction::Math("cos"),
+        Builtin::Tan => CFunction::Math("tan"),
+        Builtin::Atn => CFunction::Math("atan"),
+        Builtin::Log => CFunction::Math("log"),
+        Builtin::Exp => CFunction::Math("exp"),
+        Builtin::Min => CFunction::Math("fmin"),
+        Builtin::Max => CFunction::Math("fmax"),
+    }
+}
+
+fn is_string(name: &str) -> bool {
+    Type::of_name(name) == Type::String
+}
+
+// Whether any of the statements, or anything in them, is a string. A FUNCTION with none
+// has nothing to tidy up before it returns.
+fn has_strings(statements: &[Statement]) -> bool {
+    statements.iter().any(|statement| match &statement.kind {
+        StatementKind::PrintExpr(expr) | StatementKind::ReturnValue(expr) => expr_has_strings(expr),
+        StatementKind::Let { value, .. } => expr_has_strings(value),
+        StatementKind::LetElement { indices, value, .. } => {
+            indices.iter().chain([value]).any(expr_has_strings)
+        }
+        StatementKind::Input(name) | StatementKind::Dim { name, .. } => is_string(name),
+        StatementKind::If {
+            branches,
+            else_body,
+        } => {
+            branches
+                .iter()
+                .any(|(condition, body)| condition_has_strings(condition) || has_strings(body))
+                || else_body.as_ref().is_some_and(|body| has_strings(body))
+        }
+        StatementKind::While { condition, body } => {
+            condition_has_strings(condition) || has_strings(body)
+        }
+        StatementKind::For {
+            start,
+            end,
+            step,
+            body,
+            ..
+        } => {
+            expr_has_strings(start)
+                || expr_has_strings(end)
+                || step.as_ref().is_some_and(expr_has_strings)
+                || has_strings(body)
+        }
+        _ => false,
+    })
+}
+
+fn condition_has_strings(condition: &Condition) -> bool {
+    match condition {
+        Condition::Comparison(comparison) => {
+            expr_has_strings(&comparison.first)
+                || comparison
+                    .rest
+                    .iter()
+                    .any(|(_, expr)| expr_has_strings(expr))
         }
+        Condition::Not(operand) => condition_has_strings(operand),
+        Condition::And(lhs, rhs) | Condition::Or(lhs, rhs) => {
+            condition_has_strings(lhs) || condition_has_strings(rhs)
+        }
+    }
+}
+
+fn expr_has_strings(expr: &Expr) -> bool {
+    expr.ty() == Type::String
+        || match expr {
+            Expr::Unary(_, operand) => expr_has_strings(operand),
+            Expr::Binary(_, lhs, rhs) => expr_has_strings(lhs) || expr_has_strings(rhs),
+            Expr::Call(_, args) | Expr::Builtin(_, args) | Expr::Index(_, args) => {
+                args.iter().any(expr_has_strings)
+            }
+            _ => false,
+        }
+}
+
+// Whether working the expression out makes temporary strings, which have to be freed once
+// the statement is done with them. Joining strings and string functions make them.
+fn makes_temps(expr: &Expr) -> bool {
+    match expr {
+        Expr::Unary(_, operand) => makes_temps(operand),
+        Expr::Binary(_, lhs, rhs) => {
+            expr.ty() == Type::String || makes_temps(lhs) || makes_temps(rhs)
+        }
+        Expr::Call(name, args) => is_string(name) || args.iter().any(makes_temps),
+        Expr::Builtin(builtin, args) => {
+            builtin.ty() == Type::String || args.iter().any(makes_temps)
+        }
+        // an element of a string array belongs to the array, like a variable's text
+        Expr::Index(_, indices) => indices.iter().any(makes_temps),
+        _ => false,
+    }
+}
+
+fn condition_makes_temps(condition: &Condition) -> bool {
+    match condition {
+        Condition::Comparison(comparison) => {
+            makes_temps(&comparison.first)
+                || comparison.rest.iter().any(|(_, expr)| makes_temps(expr))
+        }
+        Condition::Not(operand) => condition_makes_temps(operand),
+        Condition::And(lhs, rhs) | Condition::Or(lhs, rhs) => {
+            condition_makes_temps(lhs) || condition_makes_temps(rhs)
+        }
+    }
+}
+
+// The operator of an expression C writes as `lhs op rhs`. `^` is always a call to pow, and
+// so is `%` unless both sides are integers. Joining strings is a call to tt_concat.
+fn infix(expr: &Expr) -> Option<BinaryOp> {
+    match expr {
+        Expr::Binary(..) if c_type(expr) == CType::String => None,
+        Expr::Binary(BinaryOp::Pow, _, _) => None,
+        Expr::Binary(BinaryOp::Mod, _, _) if c_type(expr) >= CType::Float => None,
+        Expr::Binary(op, _, _) => Some(*op),
+        _ => None,
+    }
+}
+
+// The C types an expression can have, the numbers in the order C's arithmetic conversions
+// rank them.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum CType {
+    Int,
+    Long,
+    Float,
+    Double,
+    String,
+}
+
+impl CType {
+    // how variables and results of a Teeny Tiny type are held
+    fn of(ty: Type) -> Self {
+        match ty {
+            Type::Number => CType::Float,
+            Type::String => CType::String,
+        }
+    }
+}
+
+// `float name`, or `char *name` with the star where C programmers put it
+fn declaration(c_type: CType, name: &str) -> String {
+    match c_type {
+        CType::Int => format!("int {}", name),
+        CType::Long => format!("long {}", name),
+        CType::Float => format!("float {}", name),
+        CType::Double => format!("double {}", name),
+        CType::String => format!("char *{}", name),
+    }
+}
+
+fn c_type(expr: &Expr) -> CType {
+    match expr {
+        // literals too big for a long are doubles
+        Expr::Number(text) if text.contains('.') => CType::Double,
+        Expr::Number(text) if text.parse::<i32>().is_ok() => CType::Int,
+        Expr::Number(text) if text.parse::<i64>().is_ok() => CType::Long,
+        Expr::Number(_) => CType::Double,
+        Expr::String(_) => CType::String,
+        Expr::Variable(name) | Expr::Call(name, _) | Expr::Index(name, _) => {
+            CType::of(Type::of_name(name))
+        }
+        Expr::Unary(_, operand) => c_type(operand),
+        // LEN counts, VAL reads a float like INPUT does and <math.h> works in doubles
+        Expr::Builtin(Builtin::Len, _) => CType::Int,
+        Expr::Builtin(builtin, _) => match c_function(*builtin) {
+            CFunction::Math(_) => CType::Double,
+            CFunction::Runtime(_) => CType::of(builtin.ty()),
+        },
+        Expr::Binary(BinaryOp::Pow, _, _) => CType::Double,
+        Expr::Binary(_, lhs, rhs) => {
+            let (lhs, rhs) = (c_type(lhs), c_type(rhs));
+            if lhs > rhs {
+                lhs
+            } else {
+                rhs
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn emit(source: &str) -> String {
+        let program = Parser::new(Lexer::new(source)).program().unwrap();
+        let mut emitter = Emitter::new("out.c".to_string());
+        emitter.program(&program);
+        emitter.output()
+    }
+
+    #[test]
+    fn test_declarations_in_header() {
+        assert_eq!(
+            emit("INPUT a\nLET b = a * 2\nLET a = b\n"),
+            "#include <stdio.h>
+int main(void) {
+float var_a = 0;
+float var_b = 0;
+if (0 == scanf(\"%f\", &var_a)) {
+var_a = 0.0;
+scanf(\"%*s\");
+}
+var_b = var_a*2;
+var_a = var_b;
+return 0;
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_negated_operand() {
+        assert!(emit("LET a = 1\nPRINT a - -a * 2\n").contains("(float)(var_a-(-var_a)*2)"));
+    }
+
+    #[test]
+    fn test_comparison_chain() {
+        let c = emit("LET a = 1\nIF 1 < a + 1 <= 2.5 != 7 THEN\nENDIF\n");
+        assert!(c.contains("float var_a = 0;\nfloat cmp_1;\ndouble cmp_2;\n"));
+        assert!(c.contains("if (1<(cmp_1 = var_a+1) && cmp_1<=(cmp_2 = 2.5) && cmp_2!=7){"));
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        assert!(
+            emit("LET a = 1\nIF NOT a > 1 AND a < 5 OR a == 9 THEN\nENDIF\n")
+                .contains("if (!(var_a>1) && var_a<5 || var_a==9){")
+        );
+        assert!(
+            emit("LET a = 1\nIF (a > 1 OR (a + 1) < 5) AND NOT (a == 9) THEN\nENDIF\n")
+                .contains("if ((var_a>1 || var_a+1<5) && !(var_a==9)){")
+        );
+    }
+
+    #[test]
+    fn test_else() {
+        let c = emit("LET a = 1\nIF a == 1 THEN\nPRINT 1\nELSEIF a == 2 THEN\nPRINT 2\nELSE\nPRINT 3\nENDIF\n");
+        assert!(c.contains(
+            "if (var_a==1){\nprintf(\"%.2f\\n\", (float)(1));\n}\nelse if (var_a==2){\nprintf(\"%.2f\\n\", (float)(2));\n}\nelse {\nprintf(\"%.2f\\n\", (float)(3));\n}\n"
+        ));
+    }
+
+    #[test]
+    fn test_for() {
+        let c = emit("FOR i = 1 TO 10\nNEXT\nFOR j = i TO 0 STEP -0.5 * i\nNEXT\n");
+        assert!(c.contains("float var_i = 0;\nfloat var_j = 0;\n"));
+        assert!(c.contains("for (var_i = 1; var_i <= 10; var_i += 1){\n}\n"));
+        assert!(c.contains("for (var_j = var_i; (-0.5)*var_i >= 0 ? var_j <= 0 : var_j >= 0; var_j += (-0.5)*var_i){\n}\n"));
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        assert!(
+            emit("FOR i = 1 TO 2\nBREAK\nNEXT\nWHILE 1 < 2 REPEAT\nCONTINUE\nENDWHILE\n").contains(
+                "for (var_i = 1; var_i <= 2; var_i += 1){\nbreak;\n}\nwhile(1<2){\ncontinue;\n}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_gosub() {
+        let program = Parser::new(Lexer::new("GOSUB sub\nLABEL sub\nRETURN\n"))
+            .program()
+            .unwrap();
+        let mut emitter = Emitter::new("out.c".to_string());
+        emitter.max_depth(10);
+        emitter.program(&program);
+        assert_eq!(
+            emitter.output(),
+            "#include <stdio.h>
+int main(void) {
+static int gosub_stack[10];
+int gosub_depth = 0;
+if (gosub_depth == 10) {
+fprintf(stderr, \"Error: GOSUB nested more than 10 deep at line 1\\n\");
+return 1;
+}
+gosub_stack[gosub_depth++] = 1;
+goto sub;
+gosub_1:;
+sub:
+if (gosub_depth == 0) {
+fprintf(stderr, \"Error: RETURN without GOSUB at line 3\\n\");
+return 1;
+}
+goto gosub_return;
+return 0;
+gosub_return:
+switch (gosub_stack[--gosub_depth]) {
+case 1: goto gosub_1;
+}
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        let program = Parser::new(Lexer::new(
+            "PRINT add(1, 2)\nFUNCTION add(a, b)\nLET c = a + b\nRETURN add(c, 0)\nENDFUNCTION\n",
+        ))
+        .program()
+        .unwrap();
+        let mut emitter = Emitter::new("out.c".to_string());
+        emitter.max_depth(10);
+        emitter.program(&program);
+        assert_eq!(
+            emitter.output(),
+            "#include <stdio.h>
+#include <stdlib.h>
+float fn_add(int call_depth, float var_a, float var_b);
+float fn_add(int call_depth, float var_a, float var_b) {
+float var_c = 0;
+if (call_depth > 10) {
+fprintf(stderr, \"Error: Function calls nested more than 10 deep at line 2\\n\");
+exit(1);
+}
+var_c = var_a+var_b;
+return fn_add(call_depth + 1, var_c, 0);
+return 0;
+}
+int main(void) {
+printf(\"%.2f\\n\", (float)(fn_add(1, 1, 2)));
+return 0;
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        let c = emit(
+            "INPUT a$\nLET b$ = a$ + \"!\"\nIF b$ == \"x!\" != a$ + \"\" THEN\nPRINT b$\nENDIF\n",
+        );
+        assert!(c.starts_with("#include <stdio.h>\n#include <stdlib.h>\n#include <string.h>\n#include <ctype.h>\nstatic void *tt_alloc("));
+        assert!(c.contains("static void tt_set("));
+        assert!(c.ends_with(
+            "int main(void) {
+char *var_a_ = tt_copy(\"\");
+char *var_b_ = tt_copy(\"\");
+char *cmp_1;
+tt_input(&var_a_);
+tt_set(&var_b_, tt_concat(var_a_, \"!\"));
+tt_free_temps(0);
+if (strcmp(var_b_, (cmp_1 = \"x!\"))==0 && strcmp(cmp_1, tt_concat(var_a_, \"\"))!=0){
+tt_free_temps(0);
+printf(\"%s\\n\", var_b_);
+}
+tt_free_temps(0);
+free(var_a_);
+free(var_b_);
+return 0;
+}
+"
+        ));
+
+        // a function frees its strings and temporaries on the way out, and hands its
+        // result to the caller as a temporary
+        let c = emit("FUNCTION twice$(s$)\nRETURN s$ + s$\nENDFUNCTION\nPRINT twice$(\"a\")\n");
+        // only the parts of the runtime that are used
+        assert!(!c.contains("<ctype.h>") && !c.contains("tt_input") && !c.contains("tt_set"));
+        assert!(c.contains(
+            "char *fn_twice_(int call_depth, char *var_s_) {
+size_t tt_mark = tt_temp_count;
+char *tt_result;
+if (call_depth > 1000) {
+fprintf(stderr, \"Error: Function calls nested more than 1000 deep at line 1\\n\");
+exit(1);
+}
+var_s_ = tt_copy(var_s_);
+tt_result = tt_copy(tt_concat(var_s_, var_s_));
+goto tt_return;
+tt_result = tt_copy(\"\");
+tt_return:
+tt_free_temps(tt_mark);
+free(var_s_);
+return tt_temp(tt_result);
+}
+int main(void) {
+printf(\"%s\\n\", fn_twice_(1, \"a\"));
+tt_free_temps(0);
+"
+        ));
+    }
+
+    #[test]
+    fn test_builtins() {
+        let c = emit("LET a$ = MID$(\"abc\", 2, 1)\nPRINT LEN(a$) / 2 + VAL(STR$(1))\n");
+        assert!(c.contains(
+            "tt_set(&var_a_, tt_mid(\"abc\", 2, 1));
+tt_free_temps(0);
+printf(\"%.2f\\n\", (float)(tt_len(var_a_)/2+tt_val(tt_str(1))));
+tt_free_temps(0);
+"
+        ));
+        // LEN and MID$ are counted in characters
+        assert!(c.contains("static size_t tt_chars(") && c.contains("static char *tt_slice("));
+        // the case tables only go in when they're used
+        assert!(!c.contains("tt_case"));
+        assert!(emit("PRINT UPPER$(\"a\")\n").contains("{0xDF, \"SS\"},"));
+    }
+
+    #[test]
+    fn test_arrays() {
+        let c = emit("DIM grid(3, 4)\nLET grid(1, 2) = grid(0, 3) + 1\nDIM n$(2)\nLET n$(1) = n$(0) + \"!\"\n");
+        assert!(c.contains("static size_t tt_bound(") && c.contains("static char **tt_strings("));
+        assert!(c.ends_with(
+            "int main(void) {
+static float dim_grid[12];
+char **dim_n_ = tt_strings(2);
+dim_grid[tt_bound(1, 3, \"Index out of bounds for array grid(3, 4) at line 2\")*4 + tt_bound(2, 4, \"Index out of bounds for array grid(3, 4) at line 2\")] = dim_grid[tt_bound(0, 3, \"Index out of bounds for array grid(3, 4) at line 2\")*4 + tt_bound(3, 4, \"Index out of bounds for array grid(3, 4) at line 2\")]+1;
+tt_set(&dim_n_[tt_bound(1, 2, \"Index out of bounds for array n$(2) at line 4\")], tt_concat(dim_n_[tt_bound(0, 2, \"Index out of bounds for array n$(2) at line 4\")], \"!\"));
+tt_free_temps(0);
+tt_free_strings(dim_n_, 2);
+return 0;
+}
+"
+        ));
+
+        // a function's arrays are made for each call and freed on the way out
+        let c = emit("PRINT f(1)\nFUNCTION f(i)\nDIM a(2)\nRETURN a(i)\nENDFUNCTION\n");
+        assert!(c.contains("float *dim_a = tt_numbers(2);\n"));
+        assert!(c.contains(
+            "tt_result = dim_a[tt_bound(var_i, 2, \"Index out of bounds for array a(2) at line 4\")];
+goto tt_return;
+tt_result = 0;
+tt_return:
+tt_free_temps(tt_mark);
+free(dim_a);
+return tt_result;
+"
+        ));
+    }
+
+    #[test]
+    fn test_maths() {
+        let c = emit("LET a = 1\nPRINT -(a + 1) ^ 2 * (7 % 2) - a % 2 % 0.5\n");
+        assert!(c.starts_with("#include <math.h>\n#include <stdio.h>\n"));
+        assert!(c.contains("(float)((-pow(var_a+1, 2))*(7%2)-fmod(fmodf(var_a, 2), 0.5))"));
+
+        // the maths functions are <math.h>'s, which take doubles
+        let c = emit("LET a = 1\nPRINT ABS(-7) / 2 + SQRT(a) * MAX(1 + 2, INT(a))\n");
+        assert!(c.starts_with("#include <math.h>\n#include <stdio.h>\nint main(void) {\n"));
+        assert!(
+            c.contains("(float)(fabs((double)-7)/2+sqrt(var_a)*fmax((double)(1+2), trunc(var_a)))")
+        );
+    }
+
+    #[test]
+    fn test_line_directives() {
+        let program = Parser::new(Lexer::new(
+            "LET a = 1\n\nWHILE a < 3 REPEAT\nLET a = a + 1\nENDWHILE\n",
+        ))
+        .program()
+        .unwrap();
+        let mut emitter = Emitter::new("out.c".to_string());
+        emitter.line_directives("dir\\my \"prog\".tt");
+        emitter.program(&program);
+        assert_eq!(
+            emitter.output(),
+            "#include <stdio.h>
+int main(void) {
+float var_a = 0;
+#line 1 \"dir\\\\my \\\"prog\\\".tt\"
+var_a = 1;
+#line 3 \"dir\\\\my \\\"prog\\\".tt\"
+while(var_a<3){
+#line 4 \"dir\\\\my \\\"prog\\\".tt\"
+var_a = var_a+1;
+}
+return 0;
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_write_error() {
+        let mut emitter = Emitter::new("no/such/dir/out.c".to_string());
+        let error = emitter.write_file().unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Could not write to file 'no/such/dir/out.c'"));
     }
 }
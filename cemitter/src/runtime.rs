@@ -0,0 +1,533 @@
+//! The C behind strings, the string functions and arrays, each part with the parts it needs.
+
+// A string variable always owns its own copy of its text. Anything made while an
+// expression is worked out is a temporary, remembered in tt_temps and freed once the
+// statement that made it is done.
+pub(crate) const RUNTIME: [(&str, &[&str], Code); 23] = [
+    (
+        "tt_alloc",
+        &[],
+        Code::Text(
+            "static void *tt_alloc(void *memory, size_t size) {
+    memory = realloc(memory, size);
+    if (memory == NULL) {
+        fprintf(stderr, \"Error: out of memory\\n\");
+        exit(1);
+    }
+    return memory;
+}
+",
+        ),
+    ),
+    (
+        "tt_copy",
+        &["tt_alloc"],
+        Code::Text(
+            "static char *tt_copy(const char *text) {
+    size_t size = strlen(text) + 1;
+    return memcpy(tt_alloc(NULL, size), text, size);
+}
+",
+        ),
+    ),
+    (
+        "tt_temps",
+        &[],
+        Code::Text(
+            "static char **tt_temps;
+static size_t tt_temp_count, tt_temp_size;
+static void tt_free_temps(size_t mark) {
+    while (tt_temp_count > mark) {
+        free(tt_temps[--tt_temp_count]);
+    }
+}
+",
+        ),
+    ),
+    (
+        "tt_temp",
+        &["tt_alloc", "tt_temps"],
+        Code::Text(
+            "static char *tt_temp(char *text) {
+    if (tt_temp_count == tt_temp_size) {
+        tt_temp_size = tt_temp_size ? tt_temp_size * 2 : 16;
+        tt_temps = tt_alloc(tt_temps, tt_temp_size * sizeof *tt_temps);
+    }
+    tt_temps[tt_temp_count++] = text;
+    return text;
+}
+",
+        ),
+    ),
+    (
+        "tt_concat",
+        &["tt_alloc", "tt_temp"],
+        Code::Text(
+            "static char *tt_concat(const char *a, const char *b) {
+    size_t length = strlen(a);
+    char *joined = tt_alloc(NULL, length + strlen(b) + 1);
+    memcpy(joined, a, length);
+    strcpy(joined + length, b);
+    return tt_temp(joined);
+}
+",
+        ),
+    ),
+    (
+        "tt_set",
+        &["tt_copy"],
+        Code::Text(
+            "static void tt_set(char **variable, const char *text) {
+    char *copy = tt_copy(text);
+    free(*variable);
+    *variable = copy;
+}
+",
+        ),
+    ),
+    (
+        // skips whitespace, blank lines included, then reads the rest of the line. At the
+        // end of the input the variable keeps what it had
+        "tt_input",
+        &["tt_alloc"],
+        Code::Text(
+            "static void tt_input(char **variable) {
+    size_t length = 0, size = 16;
+    char *line;
+    int c;
+    do {
+        c = getchar();
+    } while (c != EOF && isspace(c));
+    if (c == EOF) {
+        return;
+    }
+    line = tt_alloc(NULL, size);
+    for (; c != EOF && c != '\\n'; c = getchar()) {
+        if (length + 1 == size) {
+            size *= 2;
+            line = tt_alloc(line, size);
+        }
+        line[length++] = (char)c;
+    }
+    if (length > 0 && line[length - 1] == '\\r') {
+        length--;
+    }
+    line[length] = '\\0';
+    free(*variable);
+    *variable = line;
+}
+",
+        ),
+    ),
+    // The string functions count and cut strings in characters rather than bytes, the same
+    // as the interpreter. A byte that isn't part of a proper UTF-8 character counts as one
+    // on its own
+    (
+        "tt_chars",
+        &[],
+        Code::Text(
+            "static size_t tt_chars(const char *text) {
+    size_t count = 0;
+    for (; *text; text++) {
+        count += ((unsigned char)*text & 0xC0) != 0x80;
+    }
+    return count;
+}
+",
+        ),
+    ),
+    (
+        // where the character `index` characters in starts, or the end of the string
+        "tt_skip",
+        &[],
+        Code::Text(
+            "static const char *tt_skip(const char *text, size_t index) {
+    for (; *text; text++) {
+        if (((unsigned char)*text & 0xC0) != 0x80 && index-- == 0) {
+            break;
+        }
+    }
+    return text;
+}
+",
+        ),
+    ),
+    (
+        // a position or count cut to a whole number between 0 and `limit`, NaN is 0
+        "tt_index",
+        &[],
+        Code::Text(
+            "static size_t tt_index(double value, size_t limit) {
+    if (!(value > 0)) {
+        return 0;
+    }
+    return value >= (double)limit ? limit : (size_t)value;
+}
+",
+        ),
+    ),
+    (
+        "tt_slice",
+        &["tt_alloc", "tt_temp", "tt_skip"],
+        Code::Text(
+            "static char *tt_slice(const char *text, size_t skip, size_t take) {
+    const char *start = tt_skip(text, skip);
+    size_t size = (size_t)(tt_skip(start, take) - start);
+    char *slice = tt_alloc(NULL, size + 1);
+    memcpy(slice, start, size);
+    slice[size] = '\\0';
+    return tt_temp(slice);
+}
+",
+        ),
+    ),
+    (
+        "tt_len",
+        &["tt_chars"],
+        Code::Text(
+            "static int tt_len(const char *text) {
+    return (int)tt_chars(text);
+}
+",
+        ),
+    ),
+    (
+        "tt_left",
+        &["tt_chars", "tt_index", "tt_slice"],
+        Code::Text(
+            "static char *tt_left(const char *text, double count) {
+    return tt_slice(text, 0, tt_index(count, tt_chars(text)));
+}
+",
+        ),
+    ),
+    (
+        "tt_right",
+        &["tt_chars", "tt_index", "tt_slice"],
+        Code::Text(
+            "static char *tt_right(const char *text, double count) {
+    size_t length = tt_chars(text), take = tt_index(count, length);
+    return tt_slice(text, length - take, take);
+}
+",
+        ),
+    ),
+    (
+        "tt_mid",
+        &["tt_chars", "tt_index", "tt_slice"],
+        Code::Text(
+            "static char *tt_mid(const char *text, double start, double count) {
+    size_t length = tt_chars(text), skip = tt_index(start - 1, length);
+    return tt_slice(text, skip, tt_index(count, length - skip));
+}
+",
+        ),
+    ),
+    (
+        "tt_str",
+        &["tt_alloc", "tt_temp"],
+        Code::Text(
+            "static char *tt_str(float value) {
+    int size = snprintf(NULL, 0, \"%.2f\", value) + 1;
+    char *text = tt_alloc(NULL, (size_t)size);
+    snprintf(text, (size_t)size, \"%.2f\", value);
+    return tt_temp(text);
+}
+",
+        ),
+    ),
+    (
+        // reads a number the way INPUT does, which unlike scanf doesn't know about hex
+        "tt_val",
+        &[],
+        Code::Text(
+            "static float tt_val(const char *text) {
+    float value = 0;
+    const char *start = text + strspn(text, \" \\t\\n\\v\\f\\r\");
+    const char *digits = start + (*start == '+' || *start == '-');
+    if (digits[0] == '0' && (digits[1] == 'x' || digits[1] == 'X')) {
+        return *start == '-' ? -0.0f : 0.0f;
+    }
+    sscanf(start, \"%f\", &value);
+    return value;
+}
+",
+        ),
+    ),
+    (
+        // changes the case of a character at a time, from tables of the characters that
+        // change. A run of them moves by the same amount, every character or every other
+        // one. The few that become more than one character are spelled out
+        "tt_case",
+        &["tt_alloc", "tt_temp"],
+        Code::Text(
+            "struct tt_run {
+    unsigned first, last;
+    int delta;
+    unsigned step;
+};
+struct tt_special {
+    unsigned code;
+    const char *text;
+};
+static char *tt_case(const char *text, const struct tt_run *runs, size_t run_count,
+                     const struct tt_special *specials, size_t special_count) {
+    char *changed = tt_alloc(NULL, 3 * strlen(text) + 1), *end = changed;
+    const unsigned char *at = (const unsigned char *)text;
+    while (*at) {
+        unsigned code = *at;
+        size_t length = code >= 0xF0 ? 4 : code >= 0xE0 ? 3 : code >= 0xC0 ? 2 : 1;
+        size_t i, low = 0, high = run_count;
+        const char *special = NULL;
+        for (i = 1; i < length; i++) {
+            if ((at[i] & 0xC0) != 0x80) {
+                break;
+            }
+        }
+        if (i < length || code >= 0xF8) {
+            /* not UTF-8, left as it is */
+            *end++ = (char)*at++;
+            continue;
+        }
+        if (length > 1) {
+            code &= 0x3F >> (length - 1);
+            for (i = 1; i < length; i++) {
+                code = code << 6 | (at[i] & 0x3F);
+            }
+        }
+        at += length;
+        for (i = 0; special == NULL && i < special_count; i++) {
+            if (specials[i].code == code) {
+                special = specials[i].text;
+            }
+        }
+        if (special != NULL) {
+            strcpy(end, special);
+            end += strlen(special);
+            continue;
+        }
+        while (low < high) {
+            size_t middle = low + (high - low) / 2;
+            if (code < runs[middle].first) {
+                high = middle;
+            } else if (code > runs[middle].last) {
+                low = middle + 1;
+            } else {
+                if ((code - runs[middle].first) % runs[middle].step == 0) {
+                    code = (unsigned)((int)code + runs[middle].delta);
+                }
+                break;
+            }
+        }
+        if (code < 0x80) {
+            *end++ = (char)code;
+        } else if (code < 0x800) {
+            *end++ = (char)(0xC0 | code >> 6);
+            *end++ = (char)(0x80 | (code & 0x3F));
+        } else if (code < 0x10000) {
+            *end++ = (char)(0xE0 | code >> 12);
+            *end++ = (char)(0x80 | (code >> 6 & 0x3F));
+            *end++ = (char)(0x80 | (code & 0x3F));
+        } else {
+            *end++ = (char)(0xF0 | code >> 18);
+            *end++ = (char)(0x80 | (code >> 12 & 0x3F));
+            *end++ = (char)(0x80 | (code >> 6 & 0x3F));
+            *end++ = (char)(0x80 | (code & 0x3F));
+        }
+    }
+    *end = '\\0';
+    return tt_temp(changed);
+}
+",
+        ),
+    ),
+    ("tt_upper", &["tt_case"], Code::Generated(upper)),
+    ("tt_lower", &["tt_case"], Code::Generated(lower)),
+    // An index is cut to a whole number like any C cast, once it's known to be in bounds.
+    // NaN fails both comparisons
+    (
+        "tt_bound",
+        &[],
+        Code::Text(
+            "static size_t tt_bound(double index, size_t size, const char *message) {
+    if (!(index > -1 && index < (double)size)) {
+        fprintf(stderr, \"Error: %s\\n\", message);
+        exit(1);
+    }
+    return (size_t)index;
+}
+",
+        ),
+    ),
+    (
+        "tt_numbers",
+        &["tt_alloc"],
+        Code::Text(
+            "static float *tt_numbers(size_t count) {
+    return memset(tt_alloc(NULL, count * sizeof(float)), 0, count * sizeof(float));
+}
+",
+        ),
+    ),
+    (
+        "tt_strings",
+        &["tt_alloc", "tt_copy"],
+        Code::Text(
+            "static char **tt_strings(size_t count) {
+    char **strings = tt_alloc(NULL, count * sizeof *strings);
+    size_t i;
+    for (i = 0; i < count; i++) {
+        strings[i] = tt_copy(\"\");
+    }
+    return strings;
+}
+static void tt_free_strings(char **strings, size_t count) {
+    while (count > 0) {
+        free(strings[--count]);
+    }
+    free(strings);
+}
+",
+        ),
+    ),
+];
+
+/// The C of a part of the runtime, written out or worked out when it's needed.
+pub(crate) enum Code {
+    Text(&'static str),
+    Generated(fn() -> String),
+}
+
+impl Code {
+    pub(crate) fn text(&self) -> String {
+        match self {
+            Code::Text(text) => text.to_string(),
+            Code::Generated(generate) => generate(),
+        }
+    }
+}
+
+fn upper() -> String {
+    case_tables("tt_upper", |c| c.to_uppercase().collect())
+}
+
+fn lower() -> String {
+    case_tables("tt_lower", |c| c.to_lowercase().collect())
+}
+
+// The tables tt_case needs to change case the way `change` does, and a function `name`
+// that calls it with them. They come from Rust's own tables, so the C gives the same
+// answers as the interpreter.
+fn case_tables(name: &str, change: fn(char) -> String) -> String {
+    let (runs, specials) = case_changes(change);
+    let mut code = format!("static const struct tt_run {}_runs[] = {{\n", name);
+    for line in runs.chunks(4) {
+        let line: Vec<String> = line
+            .iter()
+            .map(|(first, last, delta, step)| {
+                format!("{{0x{:X}, 0x{:X}, {}, {}}}", first, last, delta, step)
+            })
+            .collect();
+        code.push_str(&format!("    {},\n", line.join(", ")));
+    }
+    code.push_str(&format!(
+        "}};\nstatic const struct tt_special {}_specials[] = {{\n",
+        name
+    ));
+    for (c, text) in specials {
+        code.push_str(&format!(
+            "    {{0x{:X}, \"{}\"}},\n",
+            c as u32,
+            c_string(&text)
+        ));
+    }
+    code.push_str(&format!(
+        "}};
+static char *{name}(const char *text) {{
+    return tt_case(text, {name}_runs, sizeof {name}_runs / sizeof *{name}_runs,
+                   {name}_specials, sizeof {name}_specials / sizeof *{name}_specials);
+}}
+"
+    ));
+    code
+}
+
+// The characters `change` turns into one other character, as runs of (first, last, how
+// far they move, step), and the ones it turns into more than one.
+#[allow(clippy::type_complexity)]
+fn case_changes(change: fn(char) -> String) -> (Vec<(u32, u32, i64, u32)>, Vec<(char, String)>) {
+    let mut runs: Vec<(u32, u32, i64, u32)> = Vec::new();
+    let mut specials = Vec::new();
+    for c in (0..=char::MAX as u32).filter_map(char::from_u32) {
+        let changed = change(c);
+        let mut chars = changed.chars();
+        match (chars.next(), chars.next()) {
+            (Some(single), None) if single == c => {}
+            (Some(single), None) => {
+                let code = c as u32;
+                let delta = single as i64 - code as i64;
+                match runs.last_mut() {
+                    // a run of one can go either way, after that it keeps its step
+                    Some((first, last, run_delta, step))
+                        if *run_delta == delta
+                            && (code - *last == *step || (first == last && code - *last == 2)) =>
+                    {
+                        *step = code - *last;
+                        *last = code;
+                    }
+                    _ => runs.push((code, code, delta, 1)),
+                }
+            }
+            _ => specials.push((c, changed)),
+        }
+    }
+    (runs, specials)
+}
+
+// a C string literal of the text, anything but letters and digits as octal escapes
+fn c_string(text: &str) -> String {
+    text.bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() {
+                (b as char).to_string()
+            } else {
+                format!("\\{:03o}", b)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_case_changes_fit() {
+        // tt_case makes room for three bytes out for every byte in
+        for change in [upper_case as fn(char) -> String, lower_case] {
+            for c in (0..=char::MAX as u32).filter_map(char::from_u32) {
+                assert!(change(c).len() <= 3 * c.len_utf8(), "{:?}", c);
+            }
+        }
+    }
+
+    #[test]
+    fn test_case_tables() {
+        let (runs, specials) = case_changes(upper_case);
+        assert_eq!(runs[0], (0x61, 0x7A, -32, 1));
+        assert!(runs.contains(&(0x101, 0x12F, -1, 2)));
+        assert!(specials.contains(&('ß', "SS".to_string())));
+        // the C finds them by binary search
+        assert!(runs.windows(2).all(|pair| pair[0].1 < pair[1].0));
+        assert_eq!(c_string("Aé1"), "A\\303\\2511");
+        assert!(upper().contains("{0xDF, \"SS\"},"));
+    }
+
+    fn upper_case(c: char) -> String {
+        c.to_uppercase().collect()
+    }
+
+    fn lower_case(c: char) -> String {
+        c.to_lowercase().collect()
+    }
+}
@@ -0,0 +1,98 @@
+use crate::{Diagnostic, Label};
+use lexer::tokens::Span;
+
+// quote a string the way JSON wants it
+fn string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn span(span: &Span) -> String {
+    format!(
+        "\"start\":{},\"end\":{},\"line\":{},\"column\":{}",
+        span.start, span.end, span.line, span.column
+    )
+}
+
+fn label(label: &Label) -> String {
+    format!(
+        "{{{},\"label\":{}}}",
+        span(&label.span),
+        string(&label.message)
+    )
+}
+
+fn strings(items: &[String]) -> String {
+    let items: Vec<String> = items.iter().map(|item| string(item)).collect();
+    format!("[{}]", items.join(","))
+}
+
+/// One diagnostic as a single line of JSON, for CI and other tools.
+///
+/// ```text
+/// {"severity":"error","code":"E0203","message":"...","file":"x.tt",
+///  "span":{"start":5,"end":12,"line":1,"column":6,"label":"..."},
+///  "related":[...],"suggestions":["..."],"notes":[]}
+/// ```
+///
+/// Offsets are bytes with `end` exclusive, `line` and `column` count from 1. `span` is
+/// `null` and `code` may be `null` when the problem isn't tied to the source.
+pub fn to_json(diagnostic: &Diagnostic, file: &str) -> String {
+    let related: Vec<String> = diagnostic.secondary.iter().map(label).collect();
+    format!(
+        "{{\"severity\":{},\"code\":{},\"message\":{},\"file\":{},\"span\":{},\"related\":[{}],\"suggestions\":{},\"notes\":{}}}",
+        string(&diagnostic.severity.to_string()),
+        diagnostic.code.as_deref().map_or("null".to_string(), string),
+        string(&diagnostic.message),
+        string(file),
+        diagnostic.primary.as_ref().map_or("null".to_string(), label),
+        related.join(","),
+        strings(&diagnostic.help),
+        strings(&diagnostic.notes)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    #[test]
+    fn test_json_related() {
+        let errors = Parser::new(Lexer::new("LABEL a\nLABEL a\n"))
+            .program()
+            .unwrap_err();
+        assert_eq!(
+            to_json(&(&errors[0]).into(), "dir\\test.tt"),
+            "{\"severity\":\"error\",\"code\":\"E0202\",\"message\":\"Label already exists: a\",\
+             \"file\":\"dir\\\\test.tt\",\
+             \"span\":{\"start\":14,\"end\":15,\"line\":2,\"column\":7,\"label\":\"declared again here\"},\
+             \"related\":[{\"start\":6,\"end\":7,\"line\":1,\"column\":7,\"label\":\"label first declared here\"}],\
+             \"suggestions\":[\"each LABEL needs its own name\"],\"notes\":[]}"
+        );
+    }
+
+    #[test]
+    fn test_json_without_span() {
+        let diagnostic = Diagnostic::error("bad \"input\"\n\u{1}".to_string());
+        assert_eq!(
+            to_json(&diagnostic, "test.tt"),
+            "{\"severity\":\"error\",\"code\":null,\"message\":\"bad \\\"input\\\"\\n\\u0001\",\
+             \"file\":\"test.tt\",\"span\":null,\"related\":[],\"suggestions\":[],\"notes\":[]}"
+        );
+    }
+}
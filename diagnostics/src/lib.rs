@@ -0,0 +1,393 @@
+mod json;
+mod render;
+
+pub use json::to_json;
+pub use render::render;
+
+use cemitter::error::EmitError;
+use interpreter::error::RuntimeError;
+use lexer::error::LexError;
+use lexer::tokens::{Span, TokenType};
+use lexer::Lexer;
+use parser::ast::Builtin;
+use parser::error::ParseError;
+use std::fmt;
+use vm::error::{CompileError, LoadError};
+
+/// How bad a diagnostic is.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// A span of source with something to say about it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// A problem found in a program, ready to be shown to a person or a tool.
+///
+/// Codes are grouped by where the problem is found: `E00xx` by the lexer, `E01xx` for
+/// syntax, `E02xx` for variables, labels and functions, `E03xx` while generating or building
+/// code and `E04xx` while running.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// stable identifier like `E0202`, so tools don't have to match on the message
+    pub code: Option<String>,
+    pub message: String,
+    /// where the problem is, if it is anywhere in the source at all
+    pub primary: Option<Label>,
+    /// other places worth pointing at, e.g. an earlier declaration
+    pub secondary: Vec<Label>,
+    /// suggestions for fixing it
+    pub help: Vec<String>,
+    /// background that doesn't fit in the message
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: String) -> Self {
+        Diagnostic {
+            severity,
+            code: None,
+            message,
+            primary: None,
+            secondary: Vec::new(),
+            help: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(message: String) -> Self {
+        Diagnostic::new(Severity::Error, message)
+    }
+
+    pub fn with_code(mut self, code: &str) -> Self {
+        self.code = Some(code.to_string());
+        self
+    }
+
+    pub fn with_primary(mut self, span: Span, message: &str) -> Self {
+        self.primary = Some(Label {
+            span,
+            message: message.to_string(),
+        });
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: &str) -> Self {
+        self.secondary.push(Label {
+            span,
+            message: message.to_string(),
+        });
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.help.push(help);
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+}
+
+impl From<&LexError> for Diagnostic {
+    fn from(error: &LexError) -> Self {
+        let diagnostic = Diagnostic::error(error.message());
+        match error {
+            LexError::ExpectedNotEq { span, .. } => diagnostic
+                .with_code("E0001")
+                .with_primary(*span, "not part of !=")
+                .with_help("use != for \"not equal\"".to_string()),
+            LexError::IllegalStringChar { span, .. } => diagnostic
+                .with_code("E0002")
+                .with_primary(*span, "not allowed here")
+                .with_note(
+                    "strings can't contain tabs, carriage returns, backslashes or percent signs"
+                        .to_string(),
+                ),
+            LexError::UnterminatedString { span } => diagnostic
+                .with_code("E0003")
+                .with_primary(*span, "string starts here")
+                .with_help("close the string with \" before the end of the line".to_string()),
+            LexError::ExpectedDecimalDigit { span, .. } => diagnostic
+                .with_code("E0004")
+                .with_primary(*span, "expected a digit after this")
+                .with_help(
+                    "write whole numbers without a point, e.g. 1 rather than 1.".to_string(),
+                ),
+            LexError::UnknownCharacter { span, .. } => diagnostic
+                .with_code("E0005")
+                .with_primary(*span, "no token starts with this"),
+        }
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        let diagnostic = Diagnostic::error(error.message());
+        match error {
+            ParseError::Lex(error) => error.into(),
+            ParseError::UnexpectedToken { expected, found } => diagnostic
+                .with_code("E0101")
+                .with_primary(found.span, &format!("expected {:?}", expected)),
+            ParseError::InvalidStatement { found } => diagnostic
+                .with_code("E0102")
+                .with_primary(found.span, "not a statement")
+                .with_help(
+                    concat!(
+                        "statements start with PRINT, IF, WHILE, FOR, BREAK, CONTINUE, LABEL, ",
+                        "GOTO, GOSUB, RETURN, FUNCTION, LET, DIM or INPUT"
+                    )
+                    .to_string(),
+                ),
+            ParseError::ExpectedComparison { found } => diagnostic
+                .with_code("E0103")
+                .with_primary(found.span, "expected a comparison operator")
+                .with_help("compare with ==, !=, <, <=, > or >=".to_string()),
+            ParseError::ExpectedExpression { found } => diagnostic
+                .with_code("E0104")
+                .with_primary(found.span, "expected an expression"),
+            ParseError::OutsideLoop { found } => diagnostic
+                .with_code("E0105")
+                .with_primary(found.span, "not inside a WHILE or FOR")
+                .with_help("to leave the program early, GOTO a LABEL at the end".to_string()),
+            ParseError::OutsideFunction { found } => diagnostic
+                .with_code("E0106")
+                .with_primary(found.span, "not inside a FUNCTION")
+                .with_help("a RETURN back from a GOSUB doesn't take a value".to_string()),
+            ParseError::Nested { found, .. } => {
+                let diagnostic = diagnostic
+                    .with_code("E0107")
+                    .with_primary(found.span, "not allowed here");
+                if found.token_type == TokenType::GOSUB {
+                    diagnostic.with_help("call a FUNCTION instead".to_string())
+                } else {
+                    diagnostic.with_help(
+                        "declare the FUNCTION at the top level, outside any other".to_string(),
+                    )
+                }
+            }
+            ParseError::UndeclaredVariable { name, span } => diagnostic
+                .with_code("E0201")
+                .with_primary(*span, "used before it has a value")
+                .with_help(format!(
+                    "give {} a value with LET or INPUT before using it",
+                    name
+                )),
+            ParseError::DuplicateLabel { span, first, .. } => diagnostic
+                .with_code("E0202")
+                .with_primary(*span, "declared again here")
+                .with_secondary(*first, "label first declared here")
+                .with_help("each LABEL needs its own name".to_string()),
+            ParseError::UndeclaredLabel { name, span } => diagnostic
+                .with_code("E0203")
+                .with_primary(*span, "no LABEL with this name")
+                .with_help(format!("declare it with LABEL {}", name)),
+            ParseError::UndeclaredFunction { name, span } => {
+                let diagnostic = diagnostic
+                    .with_code("E0204")
+                    .with_primary(*span, "no FUNCTION with this name");
+                // e.g. len(a$) or MID(a$, 1, 2)
+                let upper = name.to_uppercase();
+                match Builtin::lookup(&upper).or_else(|| Builtin::lookup(&format!("{}$", upper))) {
+                    Some(builtin) => diagnostic
+                        .with_help(format!("did you mean the built in {}?", builtin.name())),
+                    None => diagnostic.with_help(format!("declare it with FUNCTION {}(...)", name)),
+                }
+            }
+            ParseError::DuplicateFunction { span, first, .. } => diagnostic
+                .with_code("E0205")
+                .with_primary(*span, "declared again here")
+                .with_secondary(*first, "function first declared here")
+                .with_help("each FUNCTION needs its own name".to_string()),
+            ParseError::BuiltinName { span, .. } => diagnostic
+                .with_code("E0209")
+                .with_primary(*span, "a built in function has this name")
+                .with_help("pick another name for the FUNCTION or array".to_string()),
+            ParseError::DuplicateParameter { span, first, .. } => diagnostic
+                .with_code("E0206")
+                .with_primary(*span, "declared again here")
+                .with_secondary(*first, "parameter first declared here")
+                .with_help("each parameter needs its own name".to_string()),
+            ParseError::WrongArgumentCount { expected, .. } => diagnostic
+                .with_code("E0207")
+                .with_primary(error.span(), &format!("expected {}", expected)),
+            ParseError::DuplicateArray { span, first, .. } => diagnostic
+                .with_code("E0210")
+                .with_primary(*span, "declared again here")
+                .with_secondary(*first, "array first declared here")
+                .with_help("each array needs its own name".to_string()),
+            ParseError::UndeclaredArray { name, span } => diagnostic
+                .with_code("E0211")
+                .with_primary(*span, "no DIM with this name")
+                .with_help(format!("declare it first with DIM {}(size)", name)),
+            ParseError::WrongIndexCount { expected, .. } => diagnostic
+                .with_code("E0212")
+                .with_primary(error.span(), &format!("expected {}", expected))
+                .with_note("an array takes an index for each size its DIM gave it".to_string()),
+            ParseError::ArraySize { span } => diagnostic
+                .with_code("E0213")
+                .with_primary(*span, "not a size an array can have")
+                .with_note("an array of size 10 has elements 0 to 9".to_string()),
+            ParseError::WrongType { expected, .. } => diagnostic
+                .with_code("E0208")
+                .with_primary(error.span(), &format!("expected a {}", expected.name()))
+                .with_help(
+                    "strings only go with strings: + joins them, == and != compare them"
+                        .to_string(),
+                )
+                .with_note("names ending in $ hold strings, the rest hold numbers".to_string()),
+        }
+    }
+}
+
+impl From<&EmitError> for Diagnostic {
+    fn from(error: &EmitError) -> Self {
+        let diagnostic = Diagnostic::error(error.to_string());
+        match error {
+            EmitError::Io { .. } => diagnostic.with_code("E0301"),
+            EmitError::NoCompiler { .. } => diagnostic
+                .with_code("E0302")
+                .with_help("install one, or point the CC environment variable at one".to_string()),
+            EmitError::CompilerIo { .. } => diagnostic
+                .with_code("E0303")
+                .with_help("check CC names a C compiler you can run".to_string()),
+            EmitError::CompilerFailed { stderr, .. } => {
+                // the C comes from a program that parsed, so this is on us or the flags
+                let mut diagnostic = diagnostic.with_code("E0304").with_help(
+                    "check any extra flags, otherwise please report this as a bug".to_string(),
+                );
+                let output = stderr.trim_end();
+                if !output.is_empty() {
+                    diagnostic = diagnostic.with_note(format!("the C compiler said:\n{}", output));
+                }
+                diagnostic
+            }
+        }
+    }
+}
+
+impl From<&CompileError> for Diagnostic {
+    fn from(error: &CompileError) -> Self {
+        match error {
+            CompileError::TooLarge { .. } => Diagnostic::error(error.to_string())
+                .with_code("E0305")
+                .with_help(
+                    "run it with the interpreter or build it with the C compiler".to_string(),
+                ),
+        }
+    }
+}
+
+impl From<&LoadError> for Diagnostic {
+    fn from(error: &LoadError) -> Self {
+        let diagnostic = Diagnostic::error(error.to_string()).with_code("E0306");
+        match error {
+            LoadError::NotBytecode | LoadError::UnsupportedVersion { .. } => diagnostic
+                .with_help("compile the .tt file again with `build --bytecode`".to_string()),
+            // nothing we write fails these, so the file has been damaged or hand made
+            _ => diagnostic.with_note(
+                "the file was rejected before running anything, it may be corrupted".to_string(),
+            ),
+        }
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(error: &RuntimeError) -> Self {
+        let diagnostic = Diagnostic::error(error.to_string());
+        match error {
+            RuntimeError::DivisionByZero { .. } => diagnostic
+                .with_code("E0401")
+                .with_note("both sides were whole numbers, so C divides them as ints".to_string())
+                .with_help("write one side with a decimal point, e.g. 1.0 / 0".to_string()),
+            RuntimeError::Io(_) => diagnostic.with_code("E0402"),
+            RuntimeError::TooDeep { .. } => diagnostic.with_code("E0403").with_help(
+                "make sure every GOSUB reaches a RETURN, or raise --max-depth".to_string(),
+            ),
+            RuntimeError::CallsTooDeep { .. } => diagnostic.with_code("E0405").with_help(
+                "make sure a FUNCTION that calls itself stops doing so, or raise --max-depth"
+                    .to_string(),
+            ),
+            RuntimeError::IndexOutOfBounds { .. } => diagnostic.with_code("E0406").with_note(
+                "an array of size 10 has elements 0 to 9, indices are cut to whole numbers"
+                    .to_string(),
+            ),
+            RuntimeError::ReturnWithoutGosub { .. } => diagnostic
+                .with_code("E0404")
+                .with_note(
+                    "a subroutine's statements run on into the next ones, like any others"
+                        .to_string(),
+                )
+                .with_help("put a GOTO before the subroutine's LABEL to skip over it".to_string()),
+        }
+    }
+}
+
+/// A runtime error pointed at the statement on its line of `source`. Bytecode run without
+/// its source has nothing to point at, so gets no span.
+pub fn runtime(error: &RuntimeError, source: &str) -> Diagnostic {
+    let diagnostic = Diagnostic::from(error);
+    let (line, label) = match error {
+        RuntimeError::Io(_) => return diagnostic,
+        RuntimeError::DivisionByZero { line } => (line, "divides by zero"),
+        RuntimeError::TooDeep { line, .. } => (line, "one GOSUB too many"),
+        RuntimeError::ReturnWithoutGosub { line } => (line, "no GOSUB to go back to"),
+        RuntimeError::CallsTooDeep { line, .. } => (line, "called one time too many"),
+        RuntimeError::IndexOutOfBounds { line, .. } => (line, "index outside the array"),
+    };
+    match statement_span(source, *line) {
+        Some(span) => diagnostic.with_primary(span, label),
+        None => diagnostic,
+    }
+}
+
+// The statement on a line of the source, from its first token to its last, so neither the
+// indentation nor a comment after it
+fn statement_span(source: &str, line: usize) -> Option<Span> {
+    let start: usize = source
+        .split('\n')
+        .take(line.saturating_sub(1))
+        .map(|text| text.len() + 1)
+        .sum();
+    let text = source.get(start..)?.split('\n').next()?;
+    let mut lexer = Lexer::new(text);
+    let mut statement: Option<Span> = None;
+    loop {
+        match lexer.get_token() {
+            Ok(token) if matches!(token.token_type, TokenType::EOF | TokenType::NEWLINE) => {
+                return statement;
+            }
+            Ok(token) => {
+                let span = Span {
+                    start: start + token.span.start,
+                    end: start + token.span.end,
+                    line,
+                    ..token.span
+                };
+                statement = Some(statement.map_or(span, |statement| statement.to(span)));
+            }
+            // the program parsed, so the lexer has no complaints about it
+            Err(_) => {}
+        }
+    }
+}
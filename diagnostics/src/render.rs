@@ -0,0 +1,288 @@
+use crate::{Diagnostic, Label, Severity};
+
+// ANSI escape codes
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const GREEN: &str = "\x1b[1;32m";
+const BLUE: &str = "\x1b[1;34m";
+const CYAN: &str = "\x1b[1;36m";
+
+// tabs are drawn as this many spaces so the carets line up
+const TAB_WIDTH: usize = 4;
+
+fn paint(text: &str, style: &str, colour: bool) -> String {
+    if colour {
+        format!("{}{}{}", style, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+fn severity_style(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => RED,
+        Severity::Warning => YELLOW,
+        Severity::Note => GREEN,
+    }
+}
+
+fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum()
+}
+
+/// Draw a diagnostic the way rustc does: the message, then each line of source it talks
+/// about with the interesting parts underlined, then any help and notes.
+///
+/// ```text
+/// error[E0202]: Label already exists: loop
+///  --> example.tt:3:7
+///   |
+/// 1 | LABEL loop
+///   |       ---- label first declared here
+/// 2 | PRINT "hi"
+/// 3 | LABEL loop
+///   |       ^^^^ declared again here
+///   |
+///   = help: each LABEL needs its own name
+/// ```
+///
+/// `file` is only used for the `-->` line. Pass `colour` to get ANSI colours for a terminal.
+pub fn render(diagnostic: &Diagnostic, file: &str, source: &str, colour: bool) -> String {
+    let style = severity_style(diagnostic.severity);
+    let severity = match &diagnostic.code {
+        Some(code) => format!("{}[{}]", diagnostic.severity, code),
+        None => diagnostic.severity.to_string(),
+    };
+    let mut out = format!(
+        "{}{}\n",
+        paint(&severity, style, colour),
+        paint(&format!(": {}", diagnostic.message), BOLD, colour)
+    );
+
+    // every label with whether it's the primary one, in source order
+    let mut labels: Vec<(&Label, bool)> = diagnostic
+        .primary
+        .iter()
+        .map(|label| (label, true))
+        .chain(diagnostic.secondary.iter().map(|label| (label, false)))
+        .collect();
+    labels.sort_by_key(|(label, _)| label.span.start);
+
+    let width = labels
+        .iter()
+        .map(|(label, _)| label.span.line.to_string().len())
+        .max()
+        .unwrap_or(0);
+    let pad = " ".repeat(width);
+    let gutter = paint("|", BLUE, colour);
+
+    if let Some(primary) = &diagnostic.primary {
+        out.push_str(&format!(
+            "{}{} {}:{}:{}\n",
+            pad,
+            paint("-->", BLUE, colour),
+            file,
+            primary.span.line,
+            primary.span.column
+        ));
+    }
+
+    if !labels.is_empty() {
+        // byte offset each line starts at, and its text
+        let mut lines = Vec::new();
+        let mut start = 0;
+        for text in source.split('\n') {
+            lines.push((start, text.strip_suffix('\r').unwrap_or(text)));
+            start += text.len() + 1;
+        }
+
+        out.push_str(&format!("{} {}\n", pad, gutter));
+        let mut last_line: Option<usize> = None;
+        for (label, primary) in &labels {
+            let line = label.span.line;
+            let Some((line_start, text)) = lines.get(line - 1) else {
+                continue;
+            };
+
+            // show the line itself once, with a gap marker if we skipped any
+            if last_line != Some(line) {
+                if last_line.is_some_and(|last| line > last + 1) {
+                    out.push_str(&format!("{}\n", paint("...", BLUE, colour)));
+                }
+                let shown = format!(
+                    "{} {} {}",
+                    paint(&format!("{:>width$}", line), BLUE, colour),
+                    gutter,
+                    text.replace('\t', &" ".repeat(TAB_WIDTH))
+                );
+                out.push_str(shown.trim_end());
+                out.push('\n');
+                last_line = Some(line);
+            }
+
+            // underline from the start of the span to its end or the end of the line,
+            // whichever is first, always at least one character
+            let line_end = line_start + text.len();
+            let from = label.span.start.clamp(*line_start, line_end);
+            let to = label.span.end.clamp(from, line_end);
+            let indent = display_width(&text[..from - line_start]);
+            let length = display_width(&text[from - line_start..to - line_start]).max(1);
+            let (mark, mark_style) = if *primary { ("^", style) } else { ("-", BLUE) };
+            let underline = format!("{} {}", mark.repeat(length), label.message);
+            out.push_str(&format!(
+                "{} {} {}{}\n",
+                pad,
+                gutter,
+                " ".repeat(indent),
+                paint(underline.trim_end(), mark_style, colour)
+            ));
+        }
+    }
+
+    if !diagnostic.help.is_empty() || !diagnostic.notes.is_empty() {
+        if !labels.is_empty() {
+            out.push_str(&format!("{} {}\n", pad, gutter));
+        }
+        for (kind, messages) in [("help", &diagnostic.help), ("note", &diagnostic.notes)] {
+            for message in messages {
+                // later lines of a long message line up under the first
+                let indent = format!("\n{}   {}", pad, " ".repeat(kind.len() + 2));
+                out.push_str(&format!(
+                    "{} {} {} {}\n",
+                    pad,
+                    paint("=", BLUE, colour),
+                    paint(&format!("{}:", kind), CYAN, colour),
+                    message.replace('\n', &indent)
+                ));
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interpreter::error::RuntimeError;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    // render every error in a program without colour
+    fn render_errors(source: &str) -> Vec<String> {
+        Parser::new(Lexer::new(source))
+            .program()
+            .unwrap_err()
+            .iter()
+            .map(|error| render(&error.into(), "test.tt", source, false))
+            .collect()
+    }
+
+    #[test]
+    fn test_render_primary() {
+        assert_eq!(
+            render_errors("LET foo = bar * 3 + 2\n"),
+            vec![
+                "error[E0201]: Referencing variable before assignment: bar
+ --> test.tt:1:11
+  |
+1 | LET foo = bar * 3 + 2
+  |           ^^^ used before it has a value
+  |
+  = help: give bar a value with LET or INPUT before using it
+"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_secondary() {
+        let mut source = "LABEL loop\n".to_string();
+        source.push_str(&"PRINT \"hi\"\n".repeat(9));
+        source.push_str("\tLABEL loop\n");
+        assert_eq!(
+            render_errors(&source),
+            vec![
+                "error[E0202]: Label already exists: loop
+  --> test.tt:11:8
+   |
+ 1 | LABEL loop
+   |       ---- label first declared here
+...
+11 |     LABEL loop
+   |           ^^^^ declared again here
+   |
+   = help: each LABEL needs its own name
+"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_end_of_input() {
+        assert_eq!(
+            render_errors("IF 1 < 2 THEN\n"),
+            vec![
+                "error[E0101]: Expected token ENDIF, got end of input
+ --> test.tt:2:1
+  |
+2 |
+  | ^ expected ENDIF
+"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_without_span() {
+        let diagnostic = Diagnostic::error("Could not write to file 'out.c'".to_string())
+            .with_note("the directory is read only".to_string());
+        assert_eq!(
+            render(&diagnostic, "test.tt", "", false),
+            "error: Could not write to file 'out.c'\n = note: the directory is read only\n"
+        );
+
+        let diagnostic = Diagnostic::error("The C compiler 'cc' failed".to_string())
+            .with_note("the C compiler said:\nout.c:1: oops".to_string());
+        assert_eq!(
+            render(&diagnostic, "test.tt", "", false),
+            "error: The C compiler 'cc' failed\n = note: the C compiler said:\n         out.c:1: oops\n"
+        );
+    }
+
+    #[test]
+    fn test_render_colour() {
+        let rendered = render_errors("GOTO nowhere\n");
+        assert!(!rendered[0].contains('\x1b'));
+        let error = Parser::new(Lexer::new("GOTO nowhere\n"))
+            .program()
+            .unwrap_err();
+        let coloured = render(&(&error[0]).into(), "test.tt", "GOTO nowhere\n", true);
+        assert!(coloured.starts_with("\x1b[1;31merror[E0203]\x1b[0m"));
+    }
+
+    #[test]
+    fn test_render_runtime() {
+        let error = RuntimeError::DivisionByZero { line: 2 };
+        let source = "PRINT 1\n\tPRINT 5 / 0 # oops\n";
+        assert_eq!(
+            render(&crate::runtime(&error, source), "test.tt", source, false),
+            "error[E0401]: Integer division by zero at line 2
+ --> test.tt:2:2
+  |
+2 |     PRINT 5 / 0 # oops
+  |     ^^^^^^^^^^^ divides by zero
+  |
+  = help: write one side with a decimal point, e.g. 1.0 / 0
+  = note: both sides were whole numbers, so C divides them as ints
+"
+        );
+        let span = crate::runtime(&error, source).primary.unwrap().span;
+        assert_eq!((span.start, span.end), (9, 20));
+        // bytecode that ran without its source
+        assert!(crate::runtime(&error, "").primary.is_none());
+    }
+}
@@ -0,0 +1,173 @@
+//! The built in functions, shared by the interpreter and the VM. The C runtime does the
+//! same things the same way: strings are counted and cut in characters rather than bytes,
+//! and UPPER$ and LOWER$ change a character at a time. The maths functions work in doubles
+//! like their <math.h> namesakes in the C.
+
+use crate::scan::{self, Scanned};
+use crate::value::{self, Number, Value};
+use parser::ast::Builtin;
+
+/// Call a built in function. The parser has checked there are the right number of
+/// arguments, of the right types.
+pub fn call(builtin: Builtin, args: &[Value]) -> Value {
+    let text = || match &args[0] {
+        Value::String(text) => text,
+        Value::Number(_) => panic!("a number where the parser only allows a string"),
+    };
+    let number = |index: usize| args[index].as_number().as_f64();
+    let string = |text: String| Value::String(text.into());
+    let double = |value: f64| Value::Number(Number::Double(value));
+    match builtin {
+        Builtin::Len => Value::Number(Number::Int(text().chars().count() as i32)),
+        Builtin::Left => string(text().chars().take(index(number(1), usize::MAX)).collect()),
+        Builtin::Right => {
+            let length = text().chars().count();
+            let take = index(number(1), length);
+            string(text().chars().skip(length - take).collect())
+        }
+        Builtin::Mid => {
+            let length = text().chars().count();
+            let skip = index(number(1) - 1.0, length);
+            let take = index(number(2), length - skip);
+            string(text().chars().skip(skip).take(take).collect())
+        }
+        Builtin::Upper => string(text().chars().flat_map(char::to_uppercase).collect()),
+        Builtin::Lower => string(text().chars().flat_map(char::to_lowercase).collect()),
+        Builtin::Str => string(value::format_float(args[0].as_number().as_f32())),
+        Builtin::Val => {
+            let value = match scan::scan_float(&mut text().as_bytes()) {
+                Ok(Scanned::Value(value)) => value,
+                _ => 0.0,
+            };
+            Value::Number(Number::Float(value))
+        }
+        Builtin::Sqrt => double(number(0).sqrt()),
+        Builtin::Abs => double(number(0).abs()),
+        Builtin::Int => double(number(0).trunc()),
+        Builtin::Floor => double(number(0).floor()),
+        Builtin::Ceil => double(number(0).ceil()),
+        Builtin::Sin => double(number(0).sin()),
+        Builtin::Cos => double(number(0).cos()),
+        Builtin::Tan => double(number(0).tan()),
+        Builtin::Atn => double(number(0).atan()),
+        Builtin::Log => double(number(0).ln()),
+        Builtin::Exp => double(number(0).exp()),
+        // like fmin and fmax, a NaN only comes out if both are
+        Builtin::Min => double(number(0).min(number(1))),
+        Builtin::Max => double(number(0).max(number(1))),
+    }
+}
+
+// A position or count given as a number, cut to a whole one and kept between 0 and
+// `limit`. Anything that isn't a number at all is 0.
+fn index(value: f64, limit: usize) -> usize {
+    if value.is_nan() || value <= 0.0 {
+        0
+    } else if value >= limit as f64 {
+        limit
+    } else {
+        value as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Value {
+        Value::String(text.into())
+    }
+
+    fn number(value: f64) -> Value {
+        Value::Number(Number::Double(value))
+    }
+
+    #[test]
+    fn test_characters_not_bytes() {
+        let word = text("héllo wörld");
+        assert_eq!(
+            call(Builtin::Len, std::slice::from_ref(&word)),
+            Value::Number(Number::Int(11))
+        );
+        assert_eq!(
+            call(Builtin::Left, &[word.clone(), number(2.9)]),
+            text("hé")
+        );
+        assert_eq!(
+            call(Builtin::Right, &[word.clone(), number(4.0)]),
+            text("örld")
+        );
+        assert_eq!(
+            call(Builtin::Mid, &[word.clone(), number(2.0), number(3.0)]),
+            text("éll")
+        );
+        // out of range positions and counts are cut back to the string
+        assert_eq!(call(Builtin::Left, &[word.clone(), number(-1.0)]), text(""));
+        assert_eq!(call(Builtin::Right, &[word.clone(), number(99.0)]), word);
+        assert_eq!(
+            call(Builtin::Mid, &[word.clone(), number(-5.0), number(1.0)]),
+            text("h")
+        );
+        assert_eq!(
+            call(
+                Builtin::Mid,
+                &[word.clone(), number(11.0), number(f64::NAN)]
+            ),
+            text("")
+        );
+        assert_eq!(
+            call(Builtin::Mid, &[word, number(11.0), number(f64::INFINITY)]),
+            text("d")
+        );
+    }
+
+    #[test]
+    fn test_case() {
+        assert_eq!(call(Builtin::Upper, &[text("straße ǆ")]), text("STRASSE Ǆ"));
+        // a letter at a time, so a final sigma isn't told apart
+        assert_eq!(
+            call(Builtin::Lower, &[text("ΟΔΟΣ İ")]),
+            text("οδοσ i\u{307}")
+        );
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(call(Builtin::Str, &[number(2.0 / 3.0)]), text("0.67"));
+        assert_eq!(
+            call(Builtin::Val, &[text("  -1.5e1 apples")]),
+            Value::Number(Number::Float(-15.0))
+        );
+        assert_eq!(
+            call(Builtin::Val, &[text("apples")]),
+            Value::Number(Number::Float(0.0))
+        );
+    }
+
+    #[test]
+    fn test_maths() {
+        let maths = |builtin, args: &[f64]| {
+            let args: Vec<Value> = args.iter().map(|arg| number(*arg)).collect();
+            call(builtin, &args)
+        };
+        assert_eq!(maths(Builtin::Sqrt, &[16.0]), number(4.0));
+        assert_eq!(maths(Builtin::Abs, &[-2.5]), number(2.5));
+        // INT cuts towards 0, FLOOR and CEIL go down and up
+        assert_eq!(maths(Builtin::Int, &[-7.5]), number(-7.0));
+        assert_eq!(maths(Builtin::Floor, &[-7.5]), number(-8.0));
+        assert_eq!(maths(Builtin::Ceil, &[7.25]), number(8.0));
+        assert_eq!(
+            maths(Builtin::Atn, &[1.0]),
+            number(std::f64::consts::FRAC_PI_4)
+        );
+        assert_eq!(maths(Builtin::Log, &[1.0]), number(0.0));
+        assert_eq!(maths(Builtin::Exp, &[0.0]), number(1.0));
+        assert_eq!(maths(Builtin::Min, &[3.0, -1.0]), number(-1.0));
+        assert_eq!(maths(Builtin::Max, &[f64::NAN, 2.0]), number(2.0));
+        // whole numbers come back as doubles, like from <math.h>
+        assert_eq!(
+            call(Builtin::Abs, &[Value::Number(Number::Int(-7))]),
+            number(7.0)
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use std::{error::Error, fmt, io};
+
+/// Things that stop a running program. The compiled C would crash or misbehave in the same
+/// places.
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// reading stdin or writing stdout failed
+    Io(io::Error),
+    /// an integer division by zero, which is SIGFPE in C
+    DivisionByZero { line: usize },
+    /// a GOSUB with `limit` GOSUBs already waiting for their RETURN
+    TooDeep { limit: usize, line: usize },
+    /// a RETURN with no GOSUB to go back to
+    ReturnWithoutGosub { line: usize },
+    /// a call with `limit` calls already running, `line` is the FUNCTION's
+    CallsTooDeep { limit: usize, line: usize },
+    /// an index outside the sizes an array was DIMed with
+    IndexOutOfBounds {
+        name: String,
+        sizes: Vec<usize>,
+        line: usize,
+    },
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::Io(error) => write!(f, "I/O error: {}", error),
+            RuntimeError::DivisionByZero { line } => {
+                write!(f, "Integer division by zero at line {}", line)
+            }
+            // the C prints these too, so they have to read the same
+            RuntimeError::TooDeep { limit, line } => {
+                write!(f, "GOSUB nested more than {} deep at line {}", limit, line)
+            }
+            RuntimeError::ReturnWithoutGosub { line } => {
+                write!(f, "RETURN without GOSUB at line {}", line)
+            }
+            RuntimeError::CallsTooDeep { limit, line } => write!(
+                f,
+                "Function calls nested more than {} deep at line {}",
+                limit, line
+            ),
+            RuntimeError::IndexOutOfBounds { name, sizes, line } => {
+                let sizes: Vec<String> = sizes.iter().map(usize::to_string).collect();
+                write!(
+                    f,
+                    "Index out of bounds for array {}({}) at line {}",
+                    name,
+                    sizes.join(", "),
+                    line
+                )
+            }
+        }
+    }
+}
+
+impl Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RuntimeError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RuntimeError {
+    fn from(error: io::Error) -> Self {
+        RuntimeError::Io(error)
+    }
+}
@@ -0,0 +1,974 @@
+pub mod builtins;
+pub mod error;
+pub mod scan;
+pub mod value;
+
+use error::RuntimeError;
+use parser::ast::{
+    self, BinaryOp, Comparison, ComparisonOp, Condition, Expr, Program, Statement, StatementKind,
+    Type, DEFAULT_MAX_DEPTH,
+};
+use scan::Scanned;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+use value::{Array, Number, Value};
+
+// What to do after running a statement or block.
+enum Flow {
+    Next,
+    Goto(String),
+    // out of, or on to the next time round, the innermost loop
+    Break,
+    Continue,
+    // the label, and the path to the GOSUB, filled in on the way out of each block
+    Gosub(String, Vec<usize>),
+    Return,
+    // out of the FUNCTION, with the value it gives back
+    ReturnValue(Value),
+}
+
+impl Flow {
+    // put the index of the statement (or IF arm) this came out of on the front of a GOSUB's
+    // path
+    fn within(self, index: usize) -> Flow {
+        match self {
+            Flow::Gosub(label, mut path) => {
+                path.insert(0, index);
+                Flow::Gosub(label, path)
+            }
+            flow => flow,
+        }
+    }
+}
+
+// where each label lives, as the index of every enclosing statement from the top down
+type Labels = HashMap<String, Vec<usize>>;
+
+// A FUNCTION ready to be called.
+struct Callable {
+    params: Vec<String>,
+    body: Vec<Statement>,
+    labels: Labels,
+    // the arrays its DIMs declare, made afresh for every call
+    arrays: Vec<(String, Vec<usize>)>,
+    // where the FUNCTION is, for errors
+    line: usize,
+}
+
+/// Runs Teeny Tiny programs directly, printing exactly what the generated C would.
+pub struct Interpreter<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    // every number variable is a float in the generated C, the rest are strings
+    variables: HashMap<String, Value>,
+    // the arrays, which are named apart from the variables. Like the variables these are
+    // the running FUNCTION's, or the ones outside any
+    arrays: HashMap<String, Array>,
+    // the line of the statement running, for errors
+    line: usize,
+    // where each RETURN goes back to, as paths like the labels have
+    returns: Vec<Vec<usize>>,
+    functions: HashMap<String, Rc<Callable>>,
+    // how many calls are running
+    calls: usize,
+    max_depth: usize,
+}
+
+impl<R: BufRead, W: Write> Interpreter<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Interpreter {
+            input,
+            output,
+            variables: HashMap::new(),
+            arrays: HashMap::new(),
+            line: 0,
+            returns: Vec::new(),
+            functions: HashMap::new(),
+            calls: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+
+    /// Stop with an error when GOSUBs, or function calls, nest deeper than this.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Start with variables left over from an earlier run, so the REPL can keep them.
+    pub fn with_variables(mut self, variables: HashMap<String, Value>) -> Self {
+        self.variables = variables;
+        self
+    }
+
+    /// Start with arrays left over from an earlier run.
+    pub fn with_arrays(mut self, arrays: HashMap<String, Array>) -> Self {
+        self.arrays = arrays;
+        self
+    }
+
+    /// Know about the FUNCTIONs among these statements, e.g. ones declared by an earlier
+    /// REPL entry.
+    pub fn with_functions(mut self, statements: &[Statement]) -> Self {
+        self.define(statements);
+        self
+    }
+
+    /// The variables and arrays as the program left them.
+    pub fn into_variables(self) -> (HashMap<String, Value>, HashMap<String, Array>) {
+        (self.variables, self.arrays)
+    }
+
+    /// Run a parsed program to completion.
+    pub fn run(&mut self, program: &Program) -> Result<(), RuntimeError> {
+        let program = &program.statements;
+        self.define(program);
+        // every array is there from the start, whether or not its DIM has run
+        for (name, sizes) in ast::arrays(program) {
+            self.arrays
+                .insert(name.to_string(), Array::new(name, sizes));
+        }
+
+        let mut labels = HashMap::new();
+        find_labels(program, &mut Vec::new(), &mut labels);
+
+        self.returns.clear();
+        self.calls = 0;
+        let result = self.run_block(program, &labels);
+        // whatever was printed comes out before the error is reported, like it does from the C
+        let flushed = self.output.flush();
+        result?;
+        Ok(flushed?)
+    }
+
+    // remember every FUNCTION in a block so it can be called from anywhere
+    fn define(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            if let StatementKind::Function(function) = &statement.kind {
+                let mut labels = HashMap::new();
+                find_labels(&function.body, &mut Vec::new(), &mut labels);
+                let callable = Callable {
+                    params: function.params.clone(),
+                    body: function.body.clone(),
+                    labels,
+                    arrays: ast::arrays(&function.body)
+                        .into_iter()
+                        .map(|(name, sizes)| (name.to_string(), sizes.to_vec()))
+                        .collect(),
+                    line: statement.span.line,
+                };
+                self.functions
+                    .insert(function.name.clone(), Rc::new(callable));
+            }
+        }
+    }
+
+    // Run the whole of the program or a FUNCTION's body, following its GOTOs, GOSUBs and
+    // RETURNs, and hand back how it finished.
+    fn run_block(&mut self, block: &[Statement], labels: &Labels) -> Result<Flow, RuntimeError> {
+        let mut flow = self.exec_block(block, &[0])?;
+        loop {
+            flow = match flow {
+                Flow::Goto(label) => self.exec_block(block, &labels[&label])?,
+                Flow::Gosub(label, mut path) => {
+                    // come back to the statement after the GOSUB
+                    *path.last_mut().unwrap() += 1;
+                    self.returns.push(path);
+                    self.exec_block(block, &labels[&label])?
+                }
+                Flow::Return => {
+                    let path = self.returns.pop().unwrap();
+                    self.exec_block(block, &path)?
+                }
+                flow => return Ok(flow),
+            };
+        }
+    }
+
+    // Call a FUNCTION. It gets variables of its own for as long as it runs, starting with
+    // its parameters, and gives back a float or a string like the C function would.
+    fn call(&mut self, name: &str, args: &[Expr]) -> Result<Value, RuntimeError> {
+        let function = Rc::clone(&self.functions[name]);
+        // the arguments are worked out with the caller's variables
+        let mut variables = HashMap::new();
+        for (param, arg) in function.params.iter().zip(args) {
+            let value = self.eval(arg)?.stored();
+            variables.insert(param.clone(), value);
+        }
+        if self.calls >= self.max_depth {
+            return Err(RuntimeError::CallsTooDeep {
+                limit: self.max_depth,
+                line: function.line,
+            });
+        }
+
+        let arrays = function
+            .arrays
+            .iter()
+            .map(|(name, sizes)| (name.clone(), Array::new(name, sizes)))
+            .collect();
+        let caller = std::mem::replace(&mut self.variables, variables);
+        let caller_arrays = std::mem::replace(&mut self.arrays, arrays);
+        let line = self.line;
+        self.calls += 1;
+        let flow = self.run_block(&function.body, &function.labels);
+        self.calls -= 1;
+        self.variables = caller;
+        self.arrays = caller_arrays;
+        self.line = line;
+        Ok(match flow? {
+            Flow::ReturnValue(value) => value,
+            // running off the end gives back 0, or ""
+            _ => Value::empty(Type::of_name(name)),
+        })
+    }
+
+    /// Run a block starting at `resume`. The first index is the statement to begin at, any
+    /// further indices lead into its body (an IF first saying which arm), which is how a GOTO
+    /// lands inside a loop or IF.
+    fn exec_block(&mut self, block: &[Statement], resume: &[usize]) -> Result<Flow, RuntimeError> {
+        let (start, inner) = match resume.split_first() {
+            Some((start, inner)) => (*start, inner),
+            None => (0, resume),
+        };
+
+        for (index, stmt) in block.iter().enumerate().skip(start) {
+            let flow = if index == start && !inner.is_empty() {
+                self.resume_stmt(stmt, inner)?
+            } else {
+                self.exec_stmt(stmt)?
+            };
+            if !matches!(flow, Flow::Next) {
+                return Ok(flow.within(index));
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    // jump into the body of an IF, WHILE or FOR without testing its condition, like C's goto
+    fn resume_stmt(&mut self, stmt: &Statement, resume: &[usize]) -> Result<Flow, RuntimeError> {
+        match &stmt.kind {
+            StatementKind::If {
+                branches,
+                else_body,
+            } => {
+                // the first index says which arm, the ELSE coming after every ELSEIF
+                let (arm, resume) = resume.split_first().unwrap();
+                let body = match branches.get(*arm) {
+                    Some((_, body)) => body,
+                    None => else_body.as_ref().unwrap(),
+                };
+                Ok(self.exec_block(body, resume)?.within(*arm))
+            }
+            StatementKind::While { body, .. } => match self.exec_block(body, resume)? {
+                Flow::Next | Flow::Continue => self.exec_stmt(stmt),
+                Flow::Break => Ok(Flow::Next),
+                flow => Ok(flow),
+            },
+            StatementKind::For {
+                variable,
+                end,
+                step,
+                body,
+                ..
+            } => match self.exec_block(body, resume)? {
+                Flow::Next | Flow::Continue => {
+                    self.line = stmt.span.line;
+                    self.step(variable, step.as_ref())?;
+                    self.for_loop(variable, end, step.as_ref(), body)
+                }
+                Flow::Break => Ok(Flow::Next),
+                flow => Ok(flow),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn exec_stmt(&mut self, stmt: &Statement) -> Result<Flow, RuntimeError> {
+        self.line = stmt.span.line;
+        match &stmt.kind {
+            StatementKind::PrintString(text) => writeln!(self.output, "{}", text)?,
+            StatementKind::PrintExpr(expr) => {
+                let value = self.eval(expr)?;
+                writeln!(self.output, "{}", value)?;
+            }
+            StatementKind::If {
+                branches,
+                else_body,
+            } => {
+                for (arm, (condition, body)) in branches.iter().enumerate() {
+                    if self.condition(condition)? {
+                        return Ok(self.exec_block(body, &[])?.within(arm));
+                    }
+                }
+                if let Some(body) = else_body {
+                    return Ok(self.exec_block(body, &[])?.within(branches.len()));
+                }
+            }
+            StatementKind::While { condition, body } => {
+                while self.condition(condition)? {
+                    match self.exec_block(body, &[])? {
+                        Flow::Next | Flow::Continue => self.line = stmt.span.line,
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            StatementKind::For {
+                variable,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                let start = self.eval(start)?.stored();
+                self.variables.insert(variable.clone(), start);
+                return self.for_loop(variable, end, step.as_ref(), body);
+            }
+            StatementKind::Break => return Ok(Flow::Break),
+            StatementKind::Continue => return Ok(Flow::Continue),
+            StatementKind::Label(_) => {}
+            StatementKind::Goto(label) => return Ok(Flow::Goto(label.clone())),
+            StatementKind::Gosub(label) => {
+                if self.returns.len() >= self.max_depth {
+                    return Err(RuntimeError::TooDeep {
+                        limit: self.max_depth,
+                        line: stmt.span.line,
+                    });
+                }
+                return Ok(Flow::Gosub(label.clone(), Vec::new()));
+            }
+            StatementKind::Return => {
+                if self.returns.is_empty() {
+                    return Err(RuntimeError::ReturnWithoutGosub {
+                        line: stmt.span.line,
+                    });
+                }
+                return Ok(Flow::Return);
+            }
+            StatementKind::Function(_) => {}
+            StatementKind::ReturnValue(expr) => {
+                return Ok(Flow::ReturnValue(self.eval(expr)?.stored()));
+            }
+            StatementKind::Let { name, value } => {
+                let value = self.eval(value)?.stored();
+                self.variables.insert(name.clone(), value);
+            }
+            StatementKind::LetElement {
+                name,
+                indices,
+                value,
+            } => {
+                let indices = self.indices(indices)?;
+                let value = self.eval(value)?.stored();
+                let offset = self.offset(name, &indices)?;
+                self.arrays.get_mut(name).unwrap().values[offset] = value;
+            }
+            // the array was made when the program or FUNCTION started
+            StatementKind::Dim { .. } => {}
+            StatementKind::Input(name) => {
+                // anything printed so far should be visible before we block on input
+                self.output.flush()?;
+                if Type::of_name(name) == Type::String {
+                    if let Some(line) = scan::scan_line(&mut self.input)? {
+                        self.variables
+                            .insert(name.clone(), Value::String(line.into()));
+                    }
+                    return Ok(Flow::Next);
+                }
+                match scan::scan_float(&mut self.input)? {
+                    Scanned::Value(value) => {
+                        self.variables
+                            .insert(name.clone(), Value::Number(Number::Float(value)));
+                    }
+                    Scanned::NoMatch => {
+                        self.variables
+                            .insert(name.clone(), Value::empty(Type::Number));
+                        scan::skip_word(&mut self.input)?;
+                    }
+                    Scanned::Eof => {}
+                }
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    // the rest of a FOR once its variable has been set: check it, run the body, step it and
+    // go round again, like `for (; step >= 0 ? i <= end : i >= end; i += step)`
+    fn for_loop(
+        &mut self,
+        variable: &str,
+        end: &Expr,
+        step: Option<&Expr>,
+        body: &[Statement],
+    ) -> Result<Flow, RuntimeError> {
+        let line = self.line;
+        loop {
+            self.line = line;
+            // counting down unless the step is >= 0, so a NaN step counts down like in C
+            let up = match step {
+                Some(step) => self
+                    .number(step)?
+                    .compare(ComparisonOp::GtEq, Number::Int(0)),
+                None => Number::Int(1),
+            };
+            let op = if up.is_true() {
+                ComparisonOp::LtEq
+            } else {
+                ComparisonOp::GtEq
+            };
+            let end = self.number(end)?;
+            if !self
+                .variable(variable)
+                .as_number()
+                .compare(op, end)
+                .is_true()
+            {
+                return Ok(Flow::Next);
+            }
+            match self.exec_block(body, &[])? {
+                Flow::Next | Flow::Continue => {
+                    self.line = line;
+                    self.step(variable, step)?
+                }
+                Flow::Break => return Ok(Flow::Next),
+                flow => return Ok(flow),
+            }
+        }
+    }
+
+    // add a FOR's step to its variable, 1 if it doesn't have one
+    fn step(&mut self, variable: &str, step: Option<&Expr>) -> Result<(), RuntimeError> {
+        let step = match step {
+            Some(step) => self.number(step)?,
+            None => Number::Int(1),
+        };
+        let value = self
+            .variable(variable)
+            .as_number()
+            .binary(BinaryOp::Add, step);
+        // adding never fails, only / and % can
+        self.variables
+            .insert(variable.to_string(), Value::Number(value.unwrap()).stored());
+        Ok(())
+    }
+
+    // AND and OR stop as soon as they know the answer, like && and || in C, so the right
+    // hand side might never run into a division by zero
+    fn condition(&mut self, condition: &Condition) -> Result<bool, RuntimeError> {
+        Ok(match condition {
+            Condition::Comparison(comparison) => self.compare(comparison)?,
+            Condition::Not(operand) => !self.condition(operand)?,
+            Condition::And(lhs, rhs) => self.condition(lhs)? && self.condition(rhs)?,
+            Condition::Or(lhs, rhs) => self.condition(lhs)? || self.condition(rhs)?,
+        })
+    }
+
+    // A chain is true when every neighbouring pair is, `a < b < c` is `a < b AND b < c`.
+    // Each operand is worked out once, and not at all once a pair has come out false.
+    fn compare(&mut self, comparison: &Comparison) -> Result<bool, RuntimeError> {
+        let mut lhs = self.eval(&comparison.first)?;
+        for (op, expr) in &comparison.rest {
+            let rhs = self.eval(expr)?;
+            if !lhs.compare(*op, &rhs).is_true() {
+                return Ok(false);
+            }
+            lhs = rhs;
+        }
+        Ok(true)
+    }
+
+    // a variable that was declared but never written to reads as 0, or ""
+    fn variable(&self, name: &str) -> Value {
+        match self.variables.get(name) {
+            Some(value) => value.clone(),
+            None => Value::empty(Type::of_name(name)),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        Ok(match expr {
+            Expr::Number(text) => Value::Number(Number::from_literal(text)),
+            Expr::String(text) => Value::String(text.as_str().into()),
+            Expr::Variable(name) => self.variable(name),
+            Expr::Unary(op, operand) => Value::Number(self.number(operand)?.unary(*op)),
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (self.eval(lhs)?, self.eval(rhs)?);
+                lhs.binary(*op, &rhs)
+                    .ok_or(RuntimeError::DivisionByZero { line: self.line })?
+            }
+            Expr::Call(name, args) => self.call(name, args)?,
+            Expr::Builtin(builtin, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                builtins::call(*builtin, &args)
+            }
+            Expr::Index(name, indices) => {
+                let indices = self.indices(indices)?;
+                let offset = self.offset(name, &indices)?;
+                self.arrays[name].values[offset].clone()
+            }
+        })
+    }
+
+    fn indices(&mut self, indices: &[Expr]) -> Result<Vec<f64>, RuntimeError> {
+        indices
+            .iter()
+            .map(|index| Ok(self.number(index)?.as_f64()))
+            .collect()
+    }
+
+    // where an element of an array is, or an error if it's outside the array
+    fn offset(&self, name: &str, indices: &[f64]) -> Result<usize, RuntimeError> {
+        let array = &self.arrays[name];
+        array
+            .offset(indices)
+            .ok_or_else(|| RuntimeError::IndexOutOfBounds {
+                name: name.to_string(),
+                sizes: array.sizes.clone(),
+                line: self.line,
+            })
+    }
+
+    // an expression the parser has made sure is a number
+    fn number(&mut self, expr: &Expr) -> Result<Number, RuntimeError> {
+        Ok(self.eval(expr)?.as_number())
+    }
+}
+
+// record the path to every LABEL statement
+fn find_labels(
+    block: &[Statement],
+    path: &mut Vec<usize>,
+    labels: &mut HashMap<String, Vec<usize>>,
+) {
+    for (index, stmt) in block.iter().enumerate() {
+        path.push(index);
+        match &stmt.kind {
+            StatementKind::Label(name) => {
+                labels.insert(name.clone(), path.clone());
+            }
+            StatementKind::If {
+                branches,
+                else_body,
+            } => {
+                let bodies = branches.iter().map(|(_, body)| body).chain(else_body);
+                for (arm, body) in bodies.enumerate() {
+                    path.push(arm);
+                    find_labels(body, path, labels);
+                    path.pop();
+                }
+            }
+            StatementKind::While { body, .. } | StatementKind::For { body, .. } => {
+                find_labels(body, path, labels)
+            }
+            _ => {}
+        }
+        path.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+    use std::io::BufWriter;
+
+    // run a program with the given stdin and return what it printed
+    fn run(source: &str, input: &str) -> String {
+        let mut output = Vec::new();
+        Interpreter::new(input.as_bytes(), &mut output)
+            .run(&Parser::new(Lexer::new(source)).program().unwrap())
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_print() {
+        assert_eq!(run("PRINT \"hi\"\nPRINT 1.5 * 3\n", ""), "hi\n4.50\n");
+    }
+
+    #[test]
+    fn test_c_arithmetic() {
+        // integer literals divide like C ints, variables are floats
+        assert_eq!(
+            run("PRINT 7 / 2\nLET a = 7\nPRINT a / 2\n", ""),
+            "3.00\n3.50\n"
+        );
+    }
+
+    #[test]
+    fn test_fibonacci() {
+        let source = std::fs::read_to_string("../tt_snippets/fibonacci.tt").unwrap();
+        assert_eq!(
+            run(&source, "5\n"),
+            "How many fibonacci numbers do you want?\n\n0.00\n1.00\n1.00\n2.00\n3.00\n"
+        );
+    }
+
+    #[test]
+    fn test_input() {
+        let source = "INPUT a\nINPUT b\nINPUT c\nPRINT a\nPRINT b\nPRINT c\n";
+        assert_eq!(run(source, "2.5 nope"), "2.50\n0.00\n0.00\n");
+    }
+
+    #[test]
+    fn test_goto_into_loop() {
+        let source = "LET i = 0
+GOTO inside
+WHILE i < 3 REPEAT
+    PRINT \"top\"
+    LABEL inside
+    LET i = i + 1
+    PRINT i
+ENDWHILE
+";
+        assert_eq!(run(source, ""), "1.00\ntop\n2.00\ntop\n3.00\n");
+    }
+
+    #[test]
+    fn test_else() {
+        let source = "LET i = 0
+WHILE i < 4 REPEAT
+    IF i == 0 THEN
+        PRINT \"zero\"
+    ELSEIF i == 1 THEN
+        PRINT \"one\"
+    ELSEIF i == 2 THEN
+        PRINT \"two\"
+    ELSE
+        PRINT \"lots\"
+    ENDIF
+    LET i = i + 1
+ENDWHILE
+";
+        assert_eq!(run(source, ""), "zero\none\ntwo\nlots\n");
+        // a GOTO can land in any arm, and carries on after the ENDIF
+        let source = "GOTO other
+IF 1 == 1 THEN
+    PRINT \"if\"
+ELSE
+    LABEL other
+    PRINT \"else\"
+ENDIF
+PRINT \"done\"
+";
+        assert_eq!(run(source, ""), "else\ndone\n");
+    }
+
+    #[test]
+    fn test_for() {
+        assert_eq!(
+            run("FOR i = 1 TO 3\nPRINT i\nNEXT\nPRINT i\n", ""),
+            "1.00\n2.00\n3.00\n4.00\n"
+        );
+        // a negative step counts down, and the end doesn't have to be hit exactly
+        assert_eq!(
+            run("FOR i = 2 TO 0 STEP -0.75\nPRINT i\nNEXT\n", ""),
+            "2.00\n1.25\n0.50\n"
+        );
+        // past the end already, the body never runs
+        assert_eq!(
+            run("FOR i = 5 TO 1\nPRINT i\nNEXT\nPRINT i\n", ""),
+            "5.00\n"
+        );
+        // the end is worked out each time round, like C's for
+        assert_eq!(
+            run(
+                "LET n = 3\nFOR i = 1 TO n\nLET n = n - 1\nNEXT\nPRINT i\n",
+                ""
+            ),
+            "3.00\n"
+        );
+        // GOTO into the body steps and carries on from there
+        let source = "LET i = 7
+GOTO inside
+FOR i = 1 TO 9 STEP 2
+    PRINT \"top\"
+    LABEL inside
+    PRINT i
+NEXT
+";
+        assert_eq!(run(source, ""), "7.00\ntop\n9.00\n");
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let source = "LET i = 0
+WHILE i < 10 REPEAT
+    LET i = i + 1
+    IF i % 2 == 0 THEN
+        CONTINUE
+    ENDIF
+    FOR j = 1 TO 9
+        IF j == 3 THEN
+            BREAK
+        ENDIF
+        IF j == 1 THEN
+            CONTINUE
+        ENDIF
+        PRINT j
+    NEXT
+    IF i == 5 THEN
+        BREAK
+    ENDIF
+ENDWHILE
+PRINT i
+";
+        assert_eq!(run(source, ""), "2.00\n2.00\n2.00\n5.00\n");
+    }
+
+    #[test]
+    fn test_gosub() {
+        let source = "FOR i = 1 TO 3
+    IF i == 2 THEN
+        GOSUB twice
+    ELSE
+        GOSUB show
+    ENDIF
+NEXT
+GOTO end
+LABEL twice
+GOSUB show
+GOSUB show
+RETURN
+LABEL show
+PRINT i
+RETURN
+LABEL end
+";
+        assert_eq!(run(source, ""), "1.00\n2.00\n2.00\n3.00\n");
+    }
+
+    #[test]
+    fn test_gosub_errors() {
+        let run_err = |source: &str, max_depth| {
+            let program = Parser::new(Lexer::new(source)).program().unwrap();
+            let mut output = Vec::new();
+            let error = Interpreter::new("".as_bytes(), &mut output)
+                .with_max_depth(max_depth)
+                .run(&program)
+                .unwrap_err();
+            (error.to_string(), String::from_utf8(output).unwrap())
+        };
+        assert_eq!(
+            run_err("LET n = 0\nLABEL down\nLET n = n + 1\nGOSUB down\n", 5),
+            (
+                "GOSUB nested more than 5 deep at line 4".to_string(),
+                "".to_string()
+            )
+        );
+        assert_eq!(
+            run_err("GOSUB sub\nLABEL sub\nPRINT 1\nRETURN\n", 5),
+            (
+                "RETURN without GOSUB at line 4".to_string(),
+                "1.00\n1.00\n".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        let source = "LET n = 5
+PRINT fact(n)
+PRINT n
+PRINT fib(10) + nothing()
+FUNCTION fact(n)
+    IF n <= 1 THEN
+        RETURN 1
+    ENDIF
+    RETURN n * fact(n - 1)
+ENDFUNCTION
+FUNCTION fib(n)
+    LET a = 0
+    LET b = 1
+    FOR i = 1 TO n
+        LET c = a + b
+        LET a = b
+        LET b = c
+    NEXT
+    RETURN a
+ENDFUNCTION
+FUNCTION nothing()
+    GOTO end
+    RETURN 7
+    LABEL end
+ENDFUNCTION
+";
+        assert_eq!(run(source, ""), "120.00\n5.00\n55.00\n");
+    }
+
+    #[test]
+    fn test_strings() {
+        let source = "INPUT name$
+INPUT n
+INPUT rest$
+LET greeting$ = \"Hello, \" + name$
+PRINT greeting$ + \"!\"
+IF name$ == \"Ann\" AND rest$ != \"\" THEN
+    PRINT rest$
+ENDIF
+PRINT n
+PRINT shout$(\"\")
+PRINT shout$(\"hey\")
+FUNCTION shout$(word$)
+    IF word$ == \"\" THEN
+        RETURN \"nothing\"
+    ENDIF
+    LET word$ = word$ + \"!!\"
+ENDFUNCTION
+";
+        assert_eq!(
+            run(source, "Ann\n3\n and friends \n"),
+            "Hello, Ann!\nand friends \n3.00\nnothing\n\n"
+        );
+    }
+
+    #[test]
+    fn test_arrays() {
+        let source = "DIM squares(5)
+DIM names$(2, 2)
+FOR i = 0 TO 4
+    LET squares(i) = i * i
+NEXT
+LET names$(1, 0.9) = \"b\"
+PRINT squares(4) + squares(2.5)
+PRINT \"[\" + names$(0, 1) + names$(1, 0) + \"]\"
+PRINT total(2) + total(2)
+FUNCTION total(n)
+    DIM seen(3)
+    LET seen(n) = seen(n) + n
+    RETURN seen(n)
+ENDFUNCTION
+";
+        // each call gets a fresh array
+        assert_eq!(run(source, ""), "20.00\n[b]\n4.00\n");
+
+        // the line is that of the statement whose expression is out of bounds, a loop's
+        // own line for its condition
+        let errors = [
+            ("DIM a(2)\nLET a(2) = 1\n", 2),
+            (
+                "DIM a(2)\nLET i = 0\nWHILE a(i) == 0 REPEAT\nLET i = i + 1\nENDWHILE\n",
+                3,
+            ),
+            (
+                "DIM a(2)\nIF 1 == 2 THEN\nPRINT 1\nELSEIF a(-1) == 0 THEN\nENDIF\n",
+                2,
+            ),
+            ("DIM a(2)\nFOR i = 0 TO 3 STEP a(i) + 1\nPRINT i\nNEXT\n", 2),
+        ];
+        for (source, line) in errors {
+            let program = Parser::new(Lexer::new(source)).program().unwrap();
+            let error = Interpreter::new("".as_bytes(), Vec::new())
+                .run(&program)
+                .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("Index out of bounds for array a(2) at line {}", line)
+            );
+        }
+    }
+
+    #[test]
+    fn test_calls_too_deep() {
+        let source = "PRINT down(3)\nFUNCTION down(n)\nPRINT n\nRETURN down(n - 1)\nENDFUNCTION\n";
+        let program = Parser::new(Lexer::new(source)).program().unwrap();
+        let mut output = Vec::new();
+        let error = Interpreter::new("".as_bytes(), &mut output)
+            .with_max_depth(3)
+            .run(&program)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Function calls nested more than 3 deep at line 2"
+        );
+        assert_eq!(output, b"3.00\n2.00\n1.00\n");
+    }
+
+    #[test]
+    fn test_comparison_chains() {
+        // each chain and whether it holds
+        let chains = [
+            ("1 < 5 < 3", false),
+            ("1 < 3 < 5", true),
+            ("1 == 2 < 3", false),
+            ("1 == 1 < 3", true),
+            ("3 > 2 > 1 >= 1 != 0", true),
+            ("3 > 2 > 1 >= 1 != 1", false),
+            ("2 == 2.0 == 2", true),
+            ("1 < 2 < 2", false),
+            // 7 / 2 is an int, 3.5 a double
+            ("3 == 7 / 2 < 3.5", true),
+        ];
+        for (chain, holds) in chains {
+            let source = format!("IF {} THEN\nPRINT \"yes\"\nENDIF\n", chain);
+            let expected = if holds { "yes\n" } else { "" };
+            assert_eq!(run(&source, ""), expected, "{}", chain);
+        }
+        // a false pair stops the rest being worked out
+        assert_eq!(run("IF 2 < 1 < 1 / 0 THEN\nENDIF\nPRINT 1\n", ""), "1.00\n");
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        let source = "LET a = 0
+WHILE a < 5 REPEAT
+    IF a == 1 OR a == 3 AND NOT a > 3 THEN
+        PRINT a
+    ENDIF
+    IF a > 0 AND 1 / 0 == 0 THEN
+    ENDIF
+    LET a = a + 1
+ENDWHILE
+";
+        // while a is 0 the 1 / 0 is never worked out, once it's 1 it is
+        let program = Parser::new(Lexer::new(source)).program().unwrap();
+        let mut output = Vec::new();
+        let result = Interpreter::new("".as_bytes(), &mut output).run(&program);
+        assert!(matches!(
+            result,
+            Err(RuntimeError::DivisionByZero { line: 6 })
+        ));
+        assert_eq!(output, b"1.00\n");
+        assert_eq!(
+            run(&source.replace("1 / 0 == 0", "2 == 3"), ""),
+            "1.00\n3.00\n"
+        );
+
+        // brackets put an OR under an AND, and one in brackets still stops at a false left
+        // hand side
+        let source = "LET a = 0
+WHILE a < 5 REPEAT
+    IF (a == 1 OR a == 3) AND NOT (a > 5 AND 1 / 0 == 0) THEN
+        PRINT a
+    ENDIF
+    LET a = a + 1
+ENDWHILE
+";
+        assert_eq!(run(source, ""), "1.00\n3.00\n");
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let program = Parser::new(Lexer::new("PRINT 1\nPRINT 1 / 0\n"))
+            .program()
+            .unwrap();
+        let mut output = Vec::new();
+        let error = Interpreter::new("".as_bytes(), &mut output)
+            .run(&program)
+            .unwrap_err();
+        assert!(matches!(error, RuntimeError::DivisionByZero { line: 2 }));
+
+        // what was printed before the error is out before it's reported
+        let mut output = BufWriter::new(Vec::new());
+        Interpreter::new("".as_bytes(), &mut output)
+            .run(&program)
+            .unwrap_err();
+        assert_eq!(output.get_ref(), b"1.00\n");
+    }
+}
@@ -0,0 +1,189 @@
+use std::io::{self, BufRead};
+
+/// What `scanf("%f", &x)` reports back to the generated C.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Scanned {
+    Value(f32),
+    // no number at the front of the input, scanf returns 0
+    NoMatch,
+    // nothing left to read, scanf returns EOF and the variable is untouched
+    Eof,
+}
+
+fn peek(input: &mut impl BufRead) -> io::Result<Option<u8>> {
+    Ok(input.fill_buf()?.first().copied())
+}
+
+fn skip_whitespace(input: &mut impl BufRead) -> io::Result<()> {
+    while let Some(b) = peek(input)? {
+        if !b.is_ascii_whitespace() {
+            break;
+        }
+        input.consume(1);
+    }
+    Ok(())
+}
+
+// move bytes onto `text` while they match
+fn take_while(
+    input: &mut impl BufRead,
+    text: &mut String,
+    pred: impl Fn(u8) -> bool,
+) -> io::Result<usize> {
+    let mut taken = 0;
+    while let Some(b) = peek(input)? {
+        if !pred(b) {
+            break;
+        }
+        text.push(b as char);
+        input.consume(1);
+        taken += 1;
+    }
+    Ok(taken)
+}
+
+/// Read a float the way `scanf("%f")` does: skip leading whitespace then consume the
+/// longest prefix that looks like a decimal number, `inf` or `nan`.
+pub fn scan_float(input: &mut impl BufRead) -> io::Result<Scanned> {
+    skip_whitespace(input)?;
+    if peek(input)?.is_none() {
+        return Ok(Scanned::Eof);
+    }
+
+    let mut text = String::new();
+    if let Some(sign @ (b'+' | b'-')) = peek(input)? {
+        text.push(sign as char);
+        input.consume(1);
+    }
+
+    // inf, infinity and nan in any case. Like scanf we only consume letters that still
+    // spell one of them.
+    if let Some(b'i' | b'I' | b'n' | b'N') = peek(input)? {
+        let mut word = String::new();
+        while let Some(b) = peek(input)? {
+            let next = format!("{}{}", word, b.to_ascii_lowercase() as char);
+            if !"infinity".starts_with(&next) && !"nan".starts_with(&next) {
+                break;
+            }
+            word = next;
+            input.consume(1);
+        }
+        let value = match word.as_str() {
+            "inf" | "infinity" => f32::INFINITY,
+            "nan" => f32::NAN,
+            _ => return Ok(Scanned::NoMatch),
+        };
+        return Ok(Scanned::Value(if text == "-" { -value } else { value }));
+    }
+
+    let mut digits = take_while(input, &mut text, |b| b.is_ascii_digit())?;
+    if peek(input)? == Some(b'.') {
+        text.push('.');
+        input.consume(1);
+        digits += take_while(input, &mut text, |b| b.is_ascii_digit())?;
+    }
+    if digits == 0 {
+        return Ok(Scanned::NoMatch);
+    }
+    if let Some(b'e' | b'E') = peek(input)? {
+        // only an exponent if digits follow, which needs more than one byte of lookahead
+        let buf = input.fill_buf()?;
+        let exponent_len = match buf.get(1) {
+            Some(b'+' | b'-') if buf.get(2).is_some_and(u8::is_ascii_digit) => 2,
+            Some(b) if b.is_ascii_digit() => 1,
+            _ => 0,
+        };
+        if exponent_len > 0 {
+            text.push_str(&String::from_utf8_lossy(&buf[..exponent_len]));
+            input.consume(exponent_len);
+            take_while(input, &mut text, |b| b.is_ascii_digit())?;
+        }
+    }
+    Ok(Scanned::Value(text.parse().unwrap()))
+}
+
+/// `scanf("%*s")`, throw away the next whitespace separated word.
+pub fn skip_word(input: &mut impl BufRead) -> io::Result<()> {
+    skip_whitespace(input)?;
+    while let Some(b) = peek(input)? {
+        if b.is_ascii_whitespace() {
+            break;
+        }
+        input.consume(1);
+    }
+    Ok(())
+}
+
+/// Read a string the way the C runtime's `tt_input` does: skip whitespace, blank lines
+/// included, then take the rest of the line. The newline, and a carriage return before it,
+/// are thrown away. None when there's nothing left to read.
+pub fn scan_line(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    skip_whitespace(input)?;
+    if peek(input)?.is_none() {
+        return Ok(None);
+    }
+    let mut line = Vec::new();
+    input.read_until(b'\n', &mut line)?;
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_numbers() {
+        let mut input = "  12 -3.5\n1e3 .5 +7abc".as_bytes();
+        assert_eq!(scan_float(&mut input).unwrap(), Scanned::Value(12.0));
+        assert_eq!(scan_float(&mut input).unwrap(), Scanned::Value(-3.5));
+        assert_eq!(scan_float(&mut input).unwrap(), Scanned::Value(1000.0));
+        assert_eq!(scan_float(&mut input).unwrap(), Scanned::Value(0.5));
+        assert_eq!(scan_float(&mut input).unwrap(), Scanned::Value(7.0));
+        // the trailing letters are left for the next read
+        assert_eq!(scan_float(&mut input).unwrap(), Scanned::NoMatch);
+    }
+
+    #[test]
+    fn test_scan_no_match_and_eof() {
+        let mut input = "abc 4".as_bytes();
+        assert_eq!(scan_float(&mut input).unwrap(), Scanned::NoMatch);
+        skip_word(&mut input).unwrap();
+        assert_eq!(scan_float(&mut input).unwrap(), Scanned::Value(4.0));
+        assert_eq!(scan_float(&mut input).unwrap(), Scanned::Eof);
+    }
+
+    #[test]
+    fn test_scan_special() {
+        let mut input = "-inf NaN".as_bytes();
+        assert_eq!(
+            scan_float(&mut input).unwrap(),
+            Scanned::Value(f32::NEG_INFINITY)
+        );
+        assert!(matches!(scan_float(&mut input).unwrap(), Scanned::Value(v) if v.is_nan()));
+
+        // only the "n" of "nothing" is eaten, %*s takes the rest of the word
+        let mut input = "nothing 2".as_bytes();
+        assert_eq!(scan_float(&mut input).unwrap(), Scanned::NoMatch);
+        skip_word(&mut input).unwrap();
+        assert_eq!(scan_float(&mut input).unwrap(), Scanned::Value(2.0));
+    }
+
+    #[test]
+    fn test_scan_line() {
+        // what's left of the line after a number is skipped, like any whitespace
+        let mut input = "4\n\n  two words \r\nlast".as_bytes();
+        assert_eq!(scan_float(&mut input).unwrap(), Scanned::Value(4.0));
+        assert_eq!(
+            scan_line(&mut input).unwrap().as_deref(),
+            Some("two words ")
+        );
+        assert_eq!(scan_line(&mut input).unwrap().as_deref(), Some("last"));
+        assert_eq!(scan_line(&mut input).unwrap(), None);
+    }
+}
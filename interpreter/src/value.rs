@@ -0,0 +1,382 @@
+use parser::ast::{BinaryOp, ComparisonOp, Type, UnaryOp};
+use std::fmt;
+use std::rc::Rc;
+
+/// A number as the generated C program sees it.
+///
+/// The C backend pastes expressions straight into `main`, so `7 / 2` is integer division,
+/// `1.5` is a `double` and every variable is a `float`. To print exactly what the compiled
+/// program prints we carry the C type along with each value and apply C's usual arithmetic
+/// conversions.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Number {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+}
+
+impl Number {
+    /// Type a NUMBER token the way a C compiler would: decimals are `double`, integers are
+    /// `int` if they fit and `long` otherwise.
+    pub fn from_literal(text: &str) -> Number {
+        if text.contains('.') {
+            return Number::Double(text.parse().unwrap());
+        }
+        if let Ok(i) = text.parse::<i32>() {
+            Number::Int(i)
+        } else if let Ok(l) = text.parse::<i64>() {
+            Number::Long(l)
+        } else {
+            // too big for any C integer type, the closest we can get is a double
+            Number::Double(text.parse().unwrap())
+        }
+    }
+
+    pub fn as_f32(self) -> f32 {
+        match self {
+            Number::Int(i) => i as f32,
+            Number::Long(l) => l as f32,
+            Number::Float(f) => f,
+            Number::Double(d) => d as f32,
+        }
+    }
+
+    pub fn as_f64(self) -> f64 {
+        match self {
+            Number::Int(i) => i as f64,
+            Number::Long(l) => l as f64,
+            Number::Float(f) => f as f64,
+            Number::Double(d) => d,
+        }
+    }
+
+    fn as_i64(self) -> i64 {
+        match self {
+            Number::Int(i) => i as i64,
+            Number::Long(l) => l,
+            // only called after checking both sides are integers
+            _ => unreachable!(),
+        }
+    }
+
+    // position in C's conversion ranking, the wider operand decides the type of the result
+    fn rank(self) -> u8 {
+        match self {
+            Number::Int(_) => 0,
+            Number::Long(_) => 1,
+            Number::Float(_) => 2,
+            Number::Double(_) => 3,
+        }
+    }
+
+    /// C truthiness, used by IF and WHILE
+    pub fn is_true(self) -> bool {
+        match self {
+            Number::Int(i) => i != 0,
+            Number::Long(l) => l != 0,
+            Number::Float(f) => f != 0.0,
+            Number::Double(d) => d != 0.0,
+        }
+    }
+
+    pub fn unary(self, op: UnaryOp) -> Number {
+        match op {
+            UnaryOp::Plus => self,
+            UnaryOp::Minus => match self {
+                Number::Int(i) => Number::Int(i.wrapping_neg()),
+                Number::Long(l) => Number::Long(l.wrapping_neg()),
+                Number::Float(f) => Number::Float(-f),
+                Number::Double(d) => Number::Double(-d),
+            },
+        }
+    }
+
+    /// Apply a binary operator. Returns None for integer division by zero, which crashes the
+    /// compiled program.
+    pub fn binary(self, op: BinaryOp, rhs: Number) -> Option<Number> {
+        // pow takes and returns doubles whatever it's given
+        if op == BinaryOp::Pow {
+            return Some(Number::Double(self.as_f64().powf(rhs.as_f64())));
+        }
+        let result = match self.rank().max(rhs.rank()) {
+            0 => {
+                let (a, b) = (self.as_i64() as i32, rhs.as_i64() as i32);
+                match op {
+                    BinaryOp::Add => Number::Int(a.wrapping_add(b)),
+                    BinaryOp::Sub => Number::Int(a.wrapping_sub(b)),
+                    BinaryOp::Mul => Number::Int(a.wrapping_mul(b)),
+                    BinaryOp::Div | BinaryOp::Mod if b == 0 => return None,
+                    BinaryOp::Div => Number::Int(a.wrapping_div(b)),
+                    BinaryOp::Mod => Number::Int(a.wrapping_rem(b)),
+                    BinaryOp::Pow => unreachable!(),
+                }
+            }
+            1 => {
+                let (a, b) = (self.as_i64(), rhs.as_i64());
+                match op {
+                    BinaryOp::Add => Number::Long(a.wrapping_add(b)),
+                    BinaryOp::Sub => Number::Long(a.wrapping_sub(b)),
+                    BinaryOp::Mul => Number::Long(a.wrapping_mul(b)),
+                    BinaryOp::Div | BinaryOp::Mod if b == 0 => return None,
+                    BinaryOp::Div => Number::Long(a.wrapping_div(b)),
+                    BinaryOp::Mod => Number::Long(a.wrapping_rem(b)),
+                    BinaryOp::Pow => unreachable!(),
+                }
+            }
+            2 => {
+                let (a, b) = (self.as_f32(), rhs.as_f32());
+                match op {
+                    BinaryOp::Add => Number::Float(a + b),
+                    BinaryOp::Sub => Number::Float(a - b),
+                    BinaryOp::Mul => Number::Float(a * b),
+                    BinaryOp::Div => Number::Float(a / b),
+                    // rust's % on floats is fmodf
+                    BinaryOp::Mod => Number::Float(a % b),
+                    BinaryOp::Pow => unreachable!(),
+                }
+            }
+            _ => {
+                let (a, b) = (self.as_f64(), rhs.as_f64());
+                match op {
+                    BinaryOp::Add => Number::Double(a + b),
+                    BinaryOp::Sub => Number::Double(a - b),
+                    BinaryOp::Mul => Number::Double(a * b),
+                    BinaryOp::Div => Number::Double(a / b),
+                    BinaryOp::Mod => Number::Double(a % b),
+                    BinaryOp::Pow => unreachable!(),
+                }
+            }
+        };
+        Some(result)
+    }
+
+    /// Compare in the common type of both sides. Like C the result is an int of 0 or 1.
+    pub fn compare(self, op: ComparisonOp, rhs: Number) -> Number {
+        match self.rank().max(rhs.rank()) {
+            0 | 1 => compare(op, self.as_i64(), rhs.as_i64()),
+            2 => compare(op, self.as_f32(), rhs.as_f32()),
+            _ => compare(op, self.as_f64(), rhs.as_f64()),
+        }
+    }
+}
+
+/// What a variable holds or an expression works out to. The parser never lets numbers and
+/// strings meet, so asking a string for its number is a bug rather than an error.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Number(Number),
+    String(Rc<str>),
+}
+
+impl Value {
+    /// What a variable holds before anything is put in it: 0 or "".
+    pub fn empty(ty: Type) -> Value {
+        match ty {
+            Type::Number => Value::Number(Number::Float(0.0)),
+            Type::String => Value::String("".into()),
+        }
+    }
+
+    /// The value as a variable keeps it, a number becomes a float.
+    pub fn stored(self) -> Value {
+        match self {
+            Value::Number(number) => Value::Number(Number::Float(number.as_f32())),
+            string => string,
+        }
+    }
+
+    pub fn as_number(&self) -> Number {
+        match self {
+            Value::Number(number) => *number,
+            Value::String(_) => panic!("a string where the parser only allows a number"),
+        }
+    }
+
+    /// Join two strings, which `+` is the only operator for, or work out the operator on
+    /// two numbers. None for integer division by zero like [`Number::binary`].
+    pub fn binary(&self, op: BinaryOp, rhs: &Value) -> Option<Value> {
+        match (self, rhs) {
+            (Value::String(lhs), Value::String(rhs)) => {
+                Some(Value::String(format!("{}{}", lhs, rhs).into()))
+            }
+            _ => self
+                .as_number()
+                .binary(op, rhs.as_number())
+                .map(Value::Number),
+        }
+    }
+
+    /// Compare two numbers, or two strings byte by byte like `strcmp`. An int 0 or 1 either
+    /// way.
+    pub fn compare(&self, op: ComparisonOp, rhs: &Value) -> Number {
+        match (self, rhs) {
+            (Value::String(lhs), Value::String(rhs)) => compare(op, lhs, rhs),
+            _ => self.as_number().compare(op, rhs.as_number()),
+        }
+    }
+}
+
+/// How PRINT shows it, a number like [`format_float`] and a string as it is.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{}", format_float(number.as_f32())),
+            Value::String(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// An array a DIM declares, each element held the way a variable holds it. The last index
+/// counts fastest, like in a C array.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Array {
+    pub sizes: Vec<usize>,
+    pub values: Vec<Value>,
+}
+
+impl Array {
+    /// An array of 0s, or of ""s if its name says it holds strings.
+    pub fn new(name: &str, sizes: &[usize]) -> Array {
+        Array {
+            sizes: sizes.to_vec(),
+            values: vec![Value::empty(Type::of_name(name)); sizes.iter().product()],
+        }
+    }
+
+    /// Where the element at these indices is in `values`. Like C, each index is cut to a
+    /// whole number, which has to be from 0 up to one less than its size.
+    pub fn offset(&self, indices: &[f64]) -> Option<usize> {
+        let mut offset = 0;
+        for (index, size) in indices.iter().zip(&self.sizes) {
+            if index.is_nan() || *index <= -1.0 || *index >= *size as f64 {
+                return None;
+            }
+            offset = offset * size + *index as usize;
+        }
+        Some(offset)
+    }
+}
+
+fn compare<T: PartialOrd>(op: ComparisonOp, a: T, b: T) -> Number {
+    let result = match op {
+        ComparisonOp::Eq => a == b,
+        ComparisonOp::NotEq => a != b,
+        ComparisonOp::Lt => a < b,
+        ComparisonOp::LtEq => a <= b,
+        ComparisonOp::Gt => a > b,
+        ComparisonOp::GtEq => a >= b,
+    };
+    Number::Int(result as i32)
+}
+
+/// Format a value like `printf("%.2f\n", (float)(value))`.
+pub fn format_float(value: f32) -> String {
+    if value.is_nan() {
+        // glibc keeps the sign of a NaN, rust never prints one
+        if value.is_sign_negative() {
+            "-nan".to_string()
+        } else {
+            "nan".to_string()
+        }
+    } else {
+        format!("{:.2}", value as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_array_offset() {
+        let grid = Array::new("grid$", &[3, 4]);
+        assert_eq!(grid.values.len(), 12);
+        assert_eq!(grid.values[0], Value::String("".into()));
+        assert_eq!(grid.offset(&[0.0, 0.0]), Some(0));
+        assert_eq!(grid.offset(&[1.0, 2.0]), Some(6));
+        // cut towards 0 like a C cast
+        assert_eq!(grid.offset(&[2.9, -0.5]), Some(8));
+        assert_eq!(grid.offset(&[3.0, 0.0]), None);
+        assert_eq!(grid.offset(&[0.0, -1.0]), None);
+        assert_eq!(grid.offset(&[f64::NAN, 0.0]), None);
+    }
+
+    #[test]
+    fn test_literal_types() {
+        assert_eq!(Number::from_literal("7"), Number::Int(7));
+        assert_eq!(Number::from_literal("7.5"), Number::Double(7.5));
+        assert_eq!(Number::from_literal("3000000000"), Number::Long(3000000000));
+    }
+
+    #[test]
+    fn test_integer_division() {
+        let seven = Number::Int(7);
+        assert_eq!(
+            seven.binary(BinaryOp::Div, Number::Int(2)),
+            Some(Number::Int(3))
+        );
+        assert_eq!(
+            seven.binary(BinaryOp::Div, Number::Double(2.0)),
+            Some(Number::Double(3.5))
+        );
+        assert_eq!(seven.binary(BinaryOp::Div, Number::Int(0)), None);
+        // float division by zero is fine in C
+        assert_eq!(
+            Number::Float(1.0).binary(BinaryOp::Div, Number::Int(0)),
+            Some(Number::Float(f32::INFINITY))
+        );
+    }
+
+    #[test]
+    fn test_mod_and_pow() {
+        assert_eq!(
+            Number::Int(-7).binary(BinaryOp::Mod, Number::Int(2)),
+            Some(Number::Int(-1))
+        );
+        assert_eq!(Number::Int(7).binary(BinaryOp::Mod, Number::Int(0)), None);
+        assert_eq!(
+            Number::Float(7.5).binary(BinaryOp::Mod, Number::Int(2)),
+            Some(Number::Float(1.5))
+        );
+        assert_eq!(
+            Number::Int(2).binary(BinaryOp::Pow, Number::Int(10)),
+            Some(Number::Double(1024.0))
+        );
+    }
+
+    #[test]
+    fn test_float_precision() {
+        // float + float stays in single precision
+        let sum = Number::Float(16777216.0)
+            .binary(BinaryOp::Add, Number::Float(1.0))
+            .unwrap();
+        assert_eq!(format_float(sum.as_f32()), "16777216.00");
+    }
+
+    #[test]
+    fn test_strings() {
+        let (a, b) = (Value::String("ab".into()), Value::String("c".into()));
+        assert_eq!(
+            a.binary(BinaryOp::Add, &b),
+            Some(Value::String("abc".into()))
+        );
+        assert_eq!(a.compare(ComparisonOp::NotEq, &b), Number::Int(1));
+        assert_eq!(a.compare(ComparisonOp::Eq, &a), Number::Int(1));
+        assert_eq!(
+            Value::Number(Number::Int(3)).stored(),
+            Value::Number(Number::Float(3.0))
+        );
+        assert_eq!(Value::empty(Type::String).to_string(), "");
+        assert_eq!(Value::empty(Type::Number).to_string(), "0.00");
+    }
+
+    #[test]
+    fn test_format_float() {
+        assert_eq!(format_float(0.125), "0.12");
+        assert_eq!(format_float(-0.001), "-0.00");
+        assert_eq!(format_float(f32::INFINITY), "inf");
+        assert_eq!(format_float(f32::NAN), "nan");
+        assert_eq!(format_float(-f32::NAN), "-nan");
+    }
+}
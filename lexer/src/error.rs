@@ -0,0 +1,67 @@
+use crate::tokens::Span;
+use std::{error::Error, fmt};
+
+/// Everything that can go wrong while turning source into tokens. The lexer has always
+/// moved past the bad input by the time one of these is returned.
+#[derive(Clone, PartialEq, Debug)]
+pub enum LexError {
+    /// a `!` that isn't part of `!=`
+    ExpectedNotEq { found: char, span: Span },
+    /// a tab, carriage return, backslash or percent sign inside a string
+    IllegalStringChar { found: char, span: Span },
+    /// a string still open at the end of the line
+    UnterminatedString { span: Span },
+    /// a number like `1.` with no digits after the point
+    ExpectedDecimalDigit { found: char, span: Span },
+    /// a character that can't start any token
+    UnknownCharacter { found: char, span: Span },
+}
+
+impl LexError {
+    /// What went wrong, without saying where.
+    pub fn message(&self) -> String {
+        match self {
+            LexError::ExpectedNotEq { found, .. } => {
+                format!("Expected !=, got ! followed by {}", describe(*found))
+            }
+            LexError::IllegalStringChar { found, .. } => {
+                format!("Illegal character {} in string", describe(*found))
+            }
+            LexError::UnterminatedString { .. } => "Unterminated string".to_string(),
+            LexError::ExpectedDecimalDigit { found, .. } => format!(
+                "Expected digit after decimal point. Instead, saw {}",
+                describe(*found)
+            ),
+            LexError::UnknownCharacter { found, .. } => {
+                format!("Unknown token: {}", describe(*found))
+            }
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            LexError::ExpectedNotEq { span, .. }
+            | LexError::IllegalStringChar { span, .. }
+            | LexError::UnterminatedString { span }
+            | LexError::ExpectedDecimalDigit { span, .. }
+            | LexError::UnknownCharacter { span, .. } => *span,
+        }
+    }
+}
+
+// show end of input and newlines as something readable
+fn describe(c: char) -> String {
+    match c {
+        '\0' => "end of input".to_string(),
+        '\n' => "a newline".to_string(),
+        _ => format!("'{}'", c.escape_default()),
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message(), self.span())
+    }
+}
+
+impl Error for LexError {}
@@ -1,45 +1,71 @@
+pub mod error;
 pub mod tokens;
 
-use tokens::{Token, TokenType};
+use error::LexError;
+use std::rc::Rc;
+use tokens::{Span, Token, TokenType};
 /// The main entry point for the interpreter. Refer to it's relevant documentation for more information.
+#[derive(Clone)]
 pub struct Lexer {
-    // we need to store the source code
-    source: String,
-    // and the current cur_pos in the source code
-    cur_pos: i64,
+    // we need to store the source code, shared so a copy to look ahead with is cheap
+    source: Rc<str>,
+    // and the byte offset of the current character in the source code
+    cur_pos: usize,
     // and the current character
     pub cur_char: char,
+    // line and column of the current character, both starting at 1
+    line: usize,
+    column: usize,
 }
 
 impl Lexer {
     /// Returns a mutable Lexer instance.
-    /// The instance calls next_char to initialise itself with the cur_pos of 0 and the first character of input.
-    pub fn new(input_code: &String) -> Lexer {
-        let mut lex = Self {
-            source: input_code.clone(),
-            cur_char: '\0' as char,
-            cur_pos: -1,
-        };
-        lex.next_char();
-        lex
+    /// The instance starts at the first character of input with a cur_pos of 0.
+    pub fn new(input_code: &str) -> Lexer {
+        Self {
+            source: input_code.into(),
+            cur_char: input_code.chars().next().unwrap_or('\0'),
+            cur_pos: 0,
+            line: 1,
+            column: 1,
+        }
     }
 
-    /// Process the next char and update the cur_char field. Increments cur_pos by 1.
+    /// Process the next char and update the cur_char field. Moves cur_pos past the current
+    /// character, which may be more than one byte.
     pub fn next_char(&mut self) {
-        self.cur_pos += 1;
-        if self.cur_pos >= self.source.len().try_into().unwrap() {
+        if self.cur_pos >= self.source.len() {
             self.cur_char = '\0';
+            return;
+        }
+        if self.cur_char == '\n' {
+            self.line += 1;
+            self.column = 1;
         } else {
-            self.cur_char = self.source.chars().nth(self.cur_pos as usize).unwrap();
+            self.column += 1;
         }
+        self.cur_pos += self.cur_char.len_utf8();
+        self.cur_char = self.source[self.cur_pos..].chars().next().unwrap_or('\0');
     }
 
     /// Return the next char without consuming it.
     pub fn peek(&self) -> char {
-        if self.cur_pos + 1 >= self.source.len() as i64 {
+        if self.cur_pos >= self.source.len() {
             return '\0';
-        } else {
-            return self.source.as_bytes()[self.cur_pos as usize + 1] as char;
+        }
+        self.source[self.cur_pos + self.cur_char.len_utf8()..]
+            .chars()
+            .next()
+            .unwrap_or('\0')
+    }
+
+    /// Where the current character is, as an empty span.
+    pub fn position(&self) -> Span {
+        Span {
+            start: self.cur_pos,
+            end: self.cur_pos,
+            line: self.line,
+            column: self.column,
         }
     }
 
@@ -53,19 +79,31 @@ impl Lexer {
     /// skip comments
     fn skip_comment(&mut self) {
         if self.cur_char == '#' {
-            while self.cur_char != '\n' {
+            while self.cur_char != '\n' && self.cur_char != '\0' {
                 self.next_char();
             }
         }
     }
 
-    /// Return the next token and consumes it. If the token is not a valid token, it panics.
-    pub fn get_token(&mut self) -> Token {
+    /// A span from `start` up to the current character.
+    fn span_from(&self, start: Span) -> Span {
+        Span {
+            end: self.cur_pos,
+            ..start
+        }
+    }
+
+    /// Return the next token and consumes it. If the token is not a valid token the bad input
+    /// is skipped and an error returned, so calling again carries on lexing.
+    pub fn get_token(&mut self) -> Result<Token, LexError> {
         // get rid of whitespace
         self.skip_whitespace();
         // skip comments
         self.skip_comment();
 
+        // remember where the token starts
+        let start = self.position();
+
         // data associated with token
         let mut s = String::new();
         s.push(self.cur_char);
@@ -76,6 +114,11 @@ impl Lexer {
             '-' => TokenType::MINUS,
             '*' => TokenType::ASTERISK,
             '/' => TokenType::SLASH,
+            '^' => TokenType::CARET,
+            '%' => TokenType::PERCENT,
+            '(' => TokenType::LPAREN,
+            ')' => TokenType::RPAREN,
+            ',' => TokenType::COMMA,
             '=' => {
                 if self.peek() == '=' {
                     self.next_char();
@@ -105,7 +148,12 @@ impl Lexer {
                     self.next_char();
                     TokenType::NOTEQ
                 } else {
-                    panic!("Expected !=, got !{}", self.peek());
+                    let found = self.peek();
+                    self.next_char();
+                    return Err(LexError::ExpectedNotEq {
+                        found,
+                        span: self.span_from(start),
+                    });
                 }
             }
             // this lexes strings.
@@ -114,18 +162,32 @@ impl Lexer {
                 let start_pos = self.cur_pos;
                 while self.cur_char != '"' {
                     match self.cur_char {
-                        '\r' | '\n' | '\t' | '\\' | '%' => {
-                            panic!("Illegal character in string.");
+                        // leave the newline for the parser to find
+                        '\n' | '\0' => {
+                            return Err(LexError::UnterminatedString {
+                                span: self.span_from(start),
+                            });
+                        }
+                        '\r' | '\t' | '\\' | '%' => {
+                            let error = LexError::IllegalStringChar {
+                                found: self.cur_char,
+                                span: self.span_from(self.position()),
+                            };
+                            // throw away the rest of the string
+                            while !matches!(self.cur_char, '"' | '\n' | '\0') {
+                                self.next_char();
+                            }
+                            if self.cur_char == '"' {
+                                self.next_char();
+                            }
+                            return Err(error);
                         }
                         _ => {
                             self.next_char();
                         }
                     }
                 }
-                let tok_text = self
-                    .source
-                    .get(start_pos as usize..self.cur_pos as usize)
-                    .unwrap();
+                let tok_text = self.source.get(start_pos..self.cur_pos).unwrap();
                 s = String::from(tok_text);
                 TokenType::STRING
             }
@@ -139,32 +201,31 @@ impl Lexer {
                 if self.peek() == '.' {
                     self.next_char();
                     if !self.peek().is_ascii_digit() {
-                        panic!(
-                            "Expected digit after decimal point. Instead, saw {}",
-                            self.peek()
-                        );
+                        let found = self.peek();
+                        self.next_char();
+                        return Err(LexError::ExpectedDecimalDigit {
+                            found,
+                            span: self.span_from(start),
+                        });
                     }
                     while self.peek().is_ascii_digit() {
                         self.next_char();
                     }
                 }
-                let tok_text = self
-                    .source
-                    .get(start_pos as usize..=self.cur_pos as usize)
-                    .unwrap();
+                let tok_text = self.source.get(start_pos..=self.cur_pos).unwrap();
                 s = String::from(tok_text);
                 TokenType::NUMBER
             }
-            // now for identifiers
+            // now for identifiers, a $ on the end makes it a string's name
             'a'..='z' | 'A'..='Z' => {
                 let start_pos = self.cur_pos;
                 while self.peek().is_ascii_alphanumeric() {
                     self.next_char();
                 }
-                let tok_text = self
-                    .source
-                    .get(start_pos as usize..(self.cur_pos as usize + 1))
-                    .unwrap();
+                if self.peek() == '$' {
+                    self.next_char();
+                }
+                let tok_text = self.source.get(start_pos..(self.cur_pos + 1)).unwrap();
                 let keyword = Token::check_if_keyword(tok_text);
                 if keyword == TokenType::UNKNOWN {
                     // identifier
@@ -177,11 +238,18 @@ impl Lexer {
             }
             '\n' => TokenType::NEWLINE,
             '\0' => TokenType::EOF,
-            _ => panic!("Unknown token: {}", self.cur_char),
+            _ => {
+                let found = self.cur_char;
+                self.next_char();
+                return Err(LexError::UnknownCharacter {
+                    found,
+                    span: self.span_from(start),
+                });
+            }
         };
         // finished with getting token
         self.next_char();
-        Token::new(token_type, s)
+        Ok(Token::new(token_type, s, self.span_from(start)))
     }
 }
 
@@ -191,13 +259,13 @@ mod tests {
 
     #[test]
     fn lex_gen_works() {
-        let mut lex = Lexer::new(&"".to_string());
+        let mut lex = Lexer::new("");
         lex.next_char()
     }
 
     #[test]
     fn test_next_char() {
-        let mut lex = Lexer::new(&"he".to_string());
+        let mut lex = Lexer::new("he");
         assert_eq!(lex.cur_char, 'h');
         lex.next_char();
         assert_eq!(lex.cur_char, 'e');
@@ -205,36 +273,145 @@ mod tests {
 
     #[test]
     fn test_get_token() {
-        let mut lex = Lexer::new(&"IF THEN ENDIF\nSTOP".to_string());
+        let mut lex = Lexer::new("IF THEN ENDIF\nSTOP");
         let mut token: Token = Token {
             token_type: TokenType::UNKNOWN,
             value: "".to_string(),
+            span: Span::default(),
         };
-        while token != Token::new(TokenType::EOF, "\0".to_string()) {
-            token = lex.get_token();
+        while token.token_type != TokenType::EOF {
+            token = lex.get_token().unwrap();
             println!("{:?}", token);
         }
     }
 
     #[test]
     fn test_string() {
-        let mut lex = Lexer::new(&"\"Hello lads\"".to_string());
-        assert_eq!(lex.get_token().value, "Hello lads");
+        let mut lex = Lexer::new("\"Hello lads\"");
+        assert_eq!(lex.get_token().unwrap().value, "Hello lads");
+    }
+
+    #[test]
+    fn test_string_names() {
+        let mut lex = Lexer::new("name$ PRINT$ a$b");
+        assert_eq!(lex.get_token().unwrap().value, "name$");
+        // keywords don't end in $
+        let token = lex.get_token().unwrap();
+        assert_eq!(
+            (token.token_type, token.value.as_str()),
+            (TokenType::IDENTIFIER, "PRINT$")
+        );
+        assert_eq!(lex.get_token().unwrap().value, "a$");
+        assert_eq!(lex.get_token().unwrap().value, "b");
     }
 
     #[test]
     fn test_numbers() {
-        let mut lex = Lexer::new(&"123.456".to_string());
-        assert_eq!(lex.get_token().value, "123.456");
+        let mut lex = Lexer::new("123.456");
+        assert_eq!(lex.get_token().unwrap().value, "123.456");
     }
 
     #[test]
     fn test_double_op() {
-        let mut lex = Lexer::new(&"==!=>=<==".to_string());
-        assert_eq!(lex.get_token().token_type, TokenType::EQEQ);
-        assert_eq!(lex.get_token().token_type, TokenType::NOTEQ);
-        assert_eq!(lex.get_token().token_type, TokenType::GTEQ);
-        assert_eq!(lex.get_token().token_type, TokenType::LTEQ);
-        assert_eq!(lex.get_token().token_type, TokenType::EQ);
+        let mut lex = Lexer::new("==!=>=<==");
+        assert_eq!(lex.get_token().unwrap().token_type, TokenType::EQEQ);
+        assert_eq!(lex.get_token().unwrap().token_type, TokenType::NOTEQ);
+        assert_eq!(lex.get_token().unwrap().token_type, TokenType::GTEQ);
+        assert_eq!(lex.get_token().unwrap().token_type, TokenType::LTEQ);
+        assert_eq!(lex.get_token().unwrap().token_type, TokenType::EQ);
+    }
+
+    #[test]
+    fn test_brackets() {
+        let mut lex = Lexer::new("(a^2)%b,c");
+        let types: Vec<TokenType> = std::iter::from_fn(|| {
+            let token = lex.get_token().unwrap();
+            (token.token_type != TokenType::EOF).then_some(token.token_type)
+        })
+        .collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::LPAREN,
+                TokenType::IDENTIFIER,
+                TokenType::CARET,
+                TokenType::NUMBER,
+                TokenType::RPAREN,
+                TokenType::PERCENT,
+                TokenType::IDENTIFIER,
+                TokenType::COMMA,
+                TokenType::IDENTIFIER
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let mut lex = Lexer::new("LET a = 1\n  PRINT \"hé\"");
+        let mut spans = Vec::new();
+        loop {
+            let token = lex.get_token().unwrap();
+            let span = token.span;
+            spans.push((span.start, span.end, span.line, span.column));
+            if token.token_type == TokenType::EOF {
+                break;
+            }
+        }
+        assert_eq!(
+            spans,
+            vec![
+                (0, 3, 1, 1),
+                (4, 5, 1, 5),
+                (6, 7, 1, 7),
+                (8, 9, 1, 9),
+                (9, 10, 1, 10),
+                (12, 17, 2, 3),
+                // é is two bytes but one column
+                (18, 23, 2, 9),
+                (23, 23, 2, 13),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors_recover() {
+        let mut lex = Lexer::new("a ! b $ \"50%\" 1.x \"open\n# trailing comment");
+        let mut results = Vec::new();
+        loop {
+            let result = lex.get_token();
+            if let Ok(Token {
+                token_type: TokenType::EOF,
+                ..
+            }) = result
+            {
+                break;
+            }
+            results.push(result.map(|token| token.token_type));
+        }
+        assert_eq!(results.len(), 9);
+        assert_eq!(results[0], Ok(TokenType::IDENTIFIER));
+        assert!(matches!(
+            results[1],
+            Err(LexError::ExpectedNotEq { found: ' ', .. })
+        ));
+        assert_eq!(results[2], Ok(TokenType::IDENTIFIER));
+        assert!(matches!(
+            results[3],
+            Err(LexError::UnknownCharacter { found: '$', .. })
+        ));
+        assert!(matches!(
+            results[4],
+            Err(LexError::IllegalStringChar { found: '%', .. })
+        ));
+        assert!(matches!(
+            results[5],
+            Err(LexError::ExpectedDecimalDigit { found: 'x', .. })
+        ));
+        assert_eq!(results[6], Ok(TokenType::IDENTIFIER));
+        assert!(matches!(
+            results[7],
+            Err(LexError::UnterminatedString { .. })
+        ));
+        assert_eq!(results[8], Ok(TokenType::NEWLINE));
     }
 }
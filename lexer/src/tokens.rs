@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Clone, PartialEq, Debug)]
 pub enum TokenType {
     UNKNOWN = -2,
@@ -9,15 +11,31 @@ pub enum TokenType {
     // our lang keywords
     LABEL,
     GOTO,
+    GOSUB,
+    RETURN,
+    FUNCTION,
+    ENDFUNCTION,
     PRINT,
     INPUT,
     LET,
+    DIM,
     IF,
     THEN,
+    ELSEIF,
+    ELSE,
     ENDIF,
     WHILE,
     REPEAT,
     ENDWHILE,
+    FOR,
+    TO,
+    STEP,
+    NEXT,
+    BREAK,
+    CONTINUE,
+    AND,
+    OR,
+    NOT,
     // now for the operators
     EQ,
     PLUS,
@@ -30,17 +48,53 @@ pub enum TokenType {
     LTEQ,
     GT,
     GTEQ,
+    CARET,
+    PERCENT,
+    LPAREN,
+    RPAREN,
+    COMMA,
+}
+
+/// Where a token came from in the source. `start` and `end` are byte offsets (end is
+/// exclusive), `line` and `column` are where the token starts, both counting from 1.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// A span from the start of this one to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
 }
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct Token {
     pub token_type: TokenType,
     pub value: String,
+    pub span: Span,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, value: String) -> Self {
-        Self { token_type, value }
+    pub fn new(token_type: TokenType, value: String, span: Span) -> Self {
+        Self {
+            token_type,
+            value,
+            span,
+        }
     }
 
     /// check if given string represents a keyword in the language or not
@@ -48,15 +102,31 @@ impl Token {
         match token_text {
             "LABEL" => TokenType::LABEL,
             "GOTO" => TokenType::GOTO,
+            "GOSUB" => TokenType::GOSUB,
+            "RETURN" => TokenType::RETURN,
+            "FUNCTION" => TokenType::FUNCTION,
+            "ENDFUNCTION" => TokenType::ENDFUNCTION,
             "PRINT" => TokenType::PRINT,
             "INPUT" => TokenType::INPUT,
             "LET" => TokenType::LET,
+            "DIM" => TokenType::DIM,
             "IF" => TokenType::IF,
             "THEN" => TokenType::THEN,
+            "ELSEIF" => TokenType::ELSEIF,
+            "ELSE" => TokenType::ELSE,
             "ENDIF" => TokenType::ENDIF,
             "WHILE" => TokenType::WHILE,
             "REPEAT" => TokenType::REPEAT,
             "ENDWHILE" => TokenType::ENDWHILE,
+            "FOR" => TokenType::FOR,
+            "TO" => TokenType::TO,
+            "STEP" => TokenType::STEP,
+            "NEXT" => TokenType::NEXT,
+            "BREAK" => TokenType::BREAK,
+            "CONTINUE" => TokenType::CONTINUE,
+            "AND" => TokenType::AND,
+            "OR" => TokenType::OR,
+            "NOT" => TokenType::NOT,
             _ => TokenType::UNKNOWN,
         }
     }
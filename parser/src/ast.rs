@@ -0,0 +1,419 @@
+// The tree the parser builds. Every backend (the C emitter, the interpreter) walks this
+// instead of reading tokens itself.
+
+use lexer::tokens::Span;
+
+/// A whole Teeny Tiny program, its statements in source order.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Program {
+    pub statements: Vec<Statement>,
+}
+
+/// A statement and where it was written. The span of an IF, WHILE, FOR or FUNCTION covers
+/// its whole body, up to the closing keyword.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum StatementKind {
+    /// PRINT "string"
+    PrintString(String),
+    /// PRINT expression, a number or a string
+    PrintExpr(Expr),
+    /// IF condition THEN nl {statement} {ELSEIF condition THEN nl {statement}}
+    /// [ELSE nl {statement}] ENDIF
+    If {
+        /// the IF and then each ELSEIF, with the body to run when its condition holds
+        branches: Vec<(Condition, Vec<Statement>)>,
+        /// what ELSE runs when none of them do
+        else_body: Option<Vec<Statement>>,
+    },
+    /// WHILE condition REPEAT nl {statement} ENDWHILE
+    While {
+        condition: Condition,
+        body: Vec<Statement>,
+    },
+    /// FOR ident = expression TO expression [STEP expression] nl {statement} NEXT
+    ///
+    /// Works like C's `for`: the variable is set to `start`, then the body runs for as long
+    /// as it hasn't gone past `end`, adding `step` (1 if there isn't one) after each time
+    /// round. `end` and `step` are worked out again every time they're needed, and which way
+    /// is past `end` depends on whether `step` is negative.
+    For {
+        variable: String,
+        start: Expr,
+        end: Expr,
+        step: Option<Expr>,
+        body: Vec<Statement>,
+    },
+    /// BREAK, leaves the innermost WHILE or FOR
+    Break,
+    /// CONTINUE, skips the rest of the innermost WHILE or FOR's body and goes round again.
+    /// A FOR still adds its step first
+    Continue,
+    /// LABEL ident
+    Label(String),
+    /// GOTO ident
+    Goto(String),
+    /// GOSUB ident, a GOTO that RETURN comes back from
+    Gosub(String),
+    /// RETURN, to just after the last GOSUB that hasn't returned yet
+    Return,
+    /// FUNCTION ident ( [ident {, ident}] ) nl {statement} ENDFUNCTION
+    ///
+    /// Only allowed at the top level. Running into one does nothing, it only runs when
+    /// called.
+    Function(Function),
+    /// RETURN expression, from the FUNCTION it's in
+    ReturnValue(Expr),
+    /// LET ident = expression, the expression having the variable's type
+    Let { name: String, value: Expr },
+    /// LET ident ( expression {, expression} ) = expression, setting an element of an array
+    LetElement {
+        name: String,
+        indices: Vec<Expr>,
+        value: Expr,
+    },
+    /// DIM ident ( number {, number} )
+    ///
+    /// Declares an array with these sizes, every element starting as 0 or "". Like a
+    /// FUNCTION, running into one does nothing: the array is there from the start of the
+    /// program, or of each call to the FUNCTION it's in.
+    Dim { name: String, sizes: Vec<usize> },
+    /// INPUT ident. A string variable gets the rest of the line
+    Input(String),
+}
+
+/// The most elements an array can have. Indices are worked out as floats, which can't tell
+/// the whole numbers past this apart.
+pub const MAX_ARRAY_SIZE: usize = 1 << 24;
+
+/// Every DIM among the statements, and in the bodies of the IFs, WHILEs and FORs among
+/// them, but not in FUNCTIONs: the arrays of the program, or of a FUNCTION's body.
+pub fn arrays(statements: &[Statement]) -> Vec<(&str, &[usize])> {
+    let mut found = Vec::new();
+    for statement in statements {
+        match &statement.kind {
+            StatementKind::Dim { name, sizes } => found.push((name.as_str(), sizes.as_slice())),
+            StatementKind::If {
+                branches,
+                else_body,
+            } => {
+                for body in branches.iter().map(|(_, body)| body).chain(else_body) {
+                    found.extend(arrays(body));
+                }
+            }
+            StatementKind::While { body, .. } | StatementKind::For { body, .. } => {
+                found.extend(arrays(body))
+            }
+            _ => {}
+        }
+    }
+    found
+}
+
+/// A FUNCTION's name, parameters and body. It has a scope of its own: the parameters and
+/// whatever it gives a value to are its own variables, and it can't see any others. Its
+/// labels are its own too. Like a variable, its name says whether it gives back a number or
+/// a string.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Statement>,
+}
+
+/// How deeply GOSUBs, and separately function calls, can nest unless told otherwise. Every
+/// backend stops the program with an error when they go deeper, rather than running out of
+/// memory.
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+/// What an IF or WHILE tests: comparisons joined by NOT, AND and OR. Like C's `!`, `&&` and
+/// `||`, NOT binds tightest and OR loosest, and AND and OR don't look at their right hand
+/// side when the left has already decided the answer.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Condition {
+    Comparison(Comparison),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+/// An expression followed by one or more comparison operators and expressions, kept as the
+/// flat chain it was written as. The chain holds when every neighbouring pair does, so
+/// `a < b < c` is `a < b AND b < c` with `b` worked out once.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Comparison {
+    pub first: Expr,
+    pub rest: Vec<(ComparisonOp, Expr)>,
+}
+
+/// The two kinds of value. Which one a variable or FUNCTION has is in its name: one ending
+/// in `$` is a string, anything else a number.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Type {
+    Number,
+    String,
+}
+
+impl Type {
+    pub fn of_name(name: &str) -> Type {
+        if name.ends_with('$') {
+            Type::String
+        } else {
+            Type::Number
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Type::Number => "number",
+            Type::String => "string",
+        }
+    }
+}
+
+/// An expression. The parser has checked the types fit together: strings only go with
+/// strings, are joined with `+` and compared with `==` and `!=`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Expr {
+    /// number literal, as written in the source
+    Number(String),
+    /// string literal, without its quotes
+    String(String),
+    Variable(String),
+    Unary(UnaryOp, Box<Expr>),
+    /// `+` on two strings joins them
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// a FUNCTION called with these arguments, which gives back a float or a string
+    Call(String, Vec<Expr>),
+    /// a built in function called with arguments of the types it takes
+    Builtin(Builtin, Vec<Expr>),
+    /// an element of an array, with an index for each of its sizes
+    Index(String, Vec<Expr>),
+}
+
+impl Expr {
+    /// What the expression works out to.
+    pub fn ty(&self) -> Type {
+        match self {
+            Expr::Number(_) | Expr::Unary(..) => Type::Number,
+            Expr::String(_) => Type::String,
+            Expr::Variable(name) | Expr::Call(name, _) | Expr::Index(name, _) => {
+                Type::of_name(name)
+            }
+            Expr::Builtin(builtin, _) => builtin.ty(),
+            // both sides are the same, and only + goes with strings
+            Expr::Binary(BinaryOp::Add, lhs, _) => lhs.ty(),
+            Expr::Binary(..) => Type::Number,
+        }
+    }
+}
+
+/// A function that comes with the language. Like a FUNCTION, its name ends in `$` when it
+/// gives back a string.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Builtin {
+    /// how many characters a string has
+    Len,
+    /// the first n characters of a string
+    Left,
+    /// the last n characters of a string
+    Right,
+    /// n characters of a string starting from a position, 1 being the first character
+    Mid,
+    Upper,
+    Lower,
+    /// a number as PRINT shows it
+    Str,
+    /// the number at the start of a string, read the way INPUT reads one
+    Val,
+    // The maths functions are C's, and like C's give back doubles
+    Sqrt,
+    Abs,
+    /// a number cut to a whole one towards 0, like a C cast
+    Int,
+    Floor,
+    Ceil,
+    /// in radians, like COS and TAN
+    Sin,
+    Cos,
+    Tan,
+    /// arctangent, in radians
+    Atn,
+    /// natural logarithm
+    Log,
+    Exp,
+    Min,
+    Max,
+}
+
+/// Every built in function. Calls to these names are built in ones, whatever FUNCTIONs
+/// there are. New ones go on the end, bytecode refers to them by their place in here.
+pub const BUILTINS: [Builtin; 21] = [
+    Builtin::Len,
+    Builtin::Left,
+    Builtin::Right,
+    Builtin::Mid,
+    Builtin::Upper,
+    Builtin::Lower,
+    Builtin::Str,
+    Builtin::Val,
+    Builtin::Sqrt,
+    Builtin::Abs,
+    Builtin::Int,
+    Builtin::Floor,
+    Builtin::Ceil,
+    Builtin::Sin,
+    Builtin::Cos,
+    Builtin::Tan,
+    Builtin::Atn,
+    Builtin::Log,
+    Builtin::Exp,
+    Builtin::Min,
+    Builtin::Max,
+];
+
+impl Builtin {
+    pub fn lookup(name: &str) -> Option<Builtin> {
+        BUILTINS.into_iter().find(|builtin| builtin.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Len => "LEN",
+            Builtin::Left => "LEFT$",
+            Builtin::Right => "RIGHT$",
+            Builtin::Mid => "MID$",
+            Builtin::Upper => "UPPER$",
+            Builtin::Lower => "LOWER$",
+            Builtin::Str => "STR$",
+            Builtin::Val => "VAL",
+            Builtin::Sqrt => "SQRT",
+            Builtin::Abs => "ABS",
+            Builtin::Int => "INT",
+            Builtin::Floor => "FLOOR",
+            Builtin::Ceil => "CEIL",
+            Builtin::Sin => "SIN",
+            Builtin::Cos => "COS",
+            Builtin::Tan => "TAN",
+            Builtin::Atn => "ATN",
+            Builtin::Log => "LOG",
+            Builtin::Exp => "EXP",
+            Builtin::Min => "MIN",
+            Builtin::Max => "MAX",
+        }
+    }
+
+    /// The types of the arguments it takes.
+    pub fn params(self) -> &'static [Type] {
+        match self {
+            Builtin::Len | Builtin::Upper | Builtin::Lower | Builtin::Val => &[Type::String],
+            Builtin::Left | Builtin::Right => &[Type::String, Type::Number],
+            Builtin::Mid => &[Type::String, Type::Number, Type::Number],
+            Builtin::Str
+            | Builtin::Sqrt
+            | Builtin::Abs
+            | Builtin::Int
+            | Builtin::Floor
+            | Builtin::Ceil
+            | Builtin::Sin
+            | Builtin::Cos
+            | Builtin::Tan
+            | Builtin::Atn
+            | Builtin::Log
+            | Builtin::Exp => &[Type::Number],
+            Builtin::Min | Builtin::Max => &[Type::Number, Type::Number],
+        }
+    }
+
+    /// What it gives back.
+    pub fn ty(self) -> Type {
+        Type::of_name(self.name())
+    }
+}
+
+/// How tightly unary `+` and `-` bind: tighter than `*`, looser than `^`, so `-a ^ 2` is
+/// `-(a ^ 2)`.
+pub const UNARY_PRECEDENCE: u8 = 3;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UnaryOp {
+    Plus,
+    Minus,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    /// remainder, `fmod` when either side isn't an integer
+    Mod,
+    /// `^`, always a double like C's `pow`
+    Pow,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ComparisonOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+impl UnaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Plus => "+",
+            UnaryOp::Minus => "-",
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Pow => "^",
+        }
+    }
+
+    /// how tightly the operator binds, higher binds tighter. Unary + and - come between
+    /// `*` and `^`, see [`UNARY_PRECEDENCE`]
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Add | BinaryOp::Sub => 1,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 2,
+            BinaryOp::Pow => 4,
+        }
+    }
+
+    /// `2 ^ 3 ^ 2` is `2 ^ (3 ^ 2)`, everything else groups to the left
+    pub fn right_associative(self) -> bool {
+        self == BinaryOp::Pow
+    }
+}
+
+impl ComparisonOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            ComparisonOp::Eq => "==",
+            ComparisonOp::NotEq => "!=",
+            ComparisonOp::Lt => "<",
+            ComparisonOp::LtEq => "<=",
+            ComparisonOp::Gt => ">",
+            ComparisonOp::GtEq => ">=",
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use crate::ast::{Type, MAX_ARRAY_SIZE};
+use lexer::error::LexError;
+use lexer::tokens::{Span, Token, TokenType};
+use std::{error::Error, fmt};
+
+/// Everything that can stop a program from parsing, from a bad character to a GOTO with
+/// nowhere to go or a string where there should be a number.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ParseError {
+    Lex(LexError),
+    /// a specific token was required, e.g. THEN after an IF's comparison
+    UnexpectedToken {
+        expected: TokenType,
+        found: Token,
+    },
+    /// a line that doesn't start with a statement keyword
+    InvalidStatement {
+        found: Token,
+    },
+    /// a comparison with no comparison operator
+    ExpectedComparison {
+        found: Token,
+    },
+    /// something that can't start an expression
+    ExpectedExpression {
+        found: Token,
+    },
+    /// a BREAK or CONTINUE that isn't inside a WHILE or FOR
+    OutsideLoop {
+        found: Token,
+    },
+    /// a RETURN with a value that isn't inside a FUNCTION
+    OutsideFunction {
+        found: Token,
+    },
+    /// a FUNCTION that isn't at the top level, or a GOSUB inside a FUNCTION. `inside` says
+    /// what it's in, e.g. "a FUNCTION"
+    Nested {
+        found: Token,
+        inside: &'static str,
+    },
+    /// a variable read before any LET or INPUT gave it a value
+    UndeclaredVariable {
+        name: String,
+        span: Span,
+    },
+    /// a second LABEL with the same name, `first` is where it was first declared
+    DuplicateLabel {
+        name: String,
+        span: Span,
+        first: Span,
+    },
+    /// a GOTO or GOSUB to a label that is never declared
+    UndeclaredLabel {
+        name: String,
+        span: Span,
+    },
+    /// a call to a FUNCTION that is never declared
+    UndeclaredFunction {
+        name: String,
+        span: Span,
+    },
+    /// a second FUNCTION with the same name, `first` is where it was first declared
+    DuplicateFunction {
+        name: String,
+        span: Span,
+        first: Span,
+    },
+    /// a FUNCTION or array with the name of a built in function
+    BuiltinName {
+        name: String,
+        span: Span,
+    },
+    /// a FUNCTION with two parameters of the same name
+    DuplicateParameter {
+        name: String,
+        span: Span,
+        first: Span,
+    },
+    /// a call with more or fewer arguments than the FUNCTION has parameters
+    WrongArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// a second DIM of an array in the same scope, `first` is where it was first declared
+    DuplicateArray {
+        name: String,
+        span: Span,
+        first: Span,
+    },
+    /// a LET to an element of an array that no DIM declares
+    UndeclaredArray {
+        name: String,
+        span: Span,
+    },
+    /// an element of an array with more or fewer indices than it has sizes
+    WrongIndexCount {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// a DIM size that isn't a whole number above 0, or sizes making too many elements
+    ArraySize {
+        span: Span,
+    },
+    /// a string where a number has to go or the other way round, e.g. `"a" - 1` or
+    /// `LET a$ = 1`
+    WrongType {
+        expected: Type,
+        found: Type,
+        span: Span,
+    },
+}
+
+impl ParseError {
+    /// Errors about what the program means rather than how it's written. These never cause
+    /// knock on errors so they are always worth reporting.
+    pub fn is_semantic(&self) -> bool {
+        matches!(
+            self,
+            ParseError::UndeclaredVariable { .. }
+                | ParseError::DuplicateLabel { .. }
+                | ParseError::UndeclaredLabel { .. }
+                | ParseError::UndeclaredFunction { .. }
+                | ParseError::DuplicateFunction { .. }
+                | ParseError::BuiltinName { .. }
+                | ParseError::DuplicateParameter { .. }
+                | ParseError::WrongArgumentCount { .. }
+                | ParseError::DuplicateArray { .. }
+                | ParseError::UndeclaredArray { .. }
+                | ParseError::WrongIndexCount { .. }
+                | ParseError::ArraySize { .. }
+                | ParseError::WrongType { .. }
+        )
+    }
+
+    /// What went wrong, without saying where.
+    pub fn message(&self) -> String {
+        match self {
+            ParseError::Lex(error) => error.message(),
+            ParseError::UnexpectedToken { expected, found } => {
+                format!("Expected token {:?}, got {}", expected, describe(found))
+            }
+            ParseError::InvalidStatement { found } => format!(
+                "Invalid statement {} ({:?})",
+                describe(found),
+                found.token_type
+            ),
+            ParseError::ExpectedComparison { found } => {
+                format!("Expected comparison operator, got {}", describe(found))
+            }
+            ParseError::ExpectedExpression { found } => {
+                format!(
+                    "Expected a number, string, variable or '(', got {}",
+                    describe(found)
+                )
+            }
+            ParseError::OutsideLoop { found } => {
+                format!("{} outside of a loop", found.value)
+            }
+            ParseError::OutsideFunction { found } => {
+                format!("{} with a value outside of a FUNCTION", found.value)
+            }
+            ParseError::Nested { found, inside } => {
+                format!("{} can't go inside {}", found.value, inside)
+            }
+            ParseError::UndeclaredVariable { name, .. } => {
+                format!("Referencing variable before assignment: {}", name)
+            }
+            ParseError::DuplicateLabel { name, .. } => format!("Label already exists: {}", name),
+            ParseError::UndeclaredLabel { name, .. } => {
+                format!("Attempting to GOTO an undeclared label: {}", name)
+            }
+            ParseError::UndeclaredFunction { name, .. } => {
+                format!("Calling an undeclared function: {}", name)
+            }
+            ParseError::DuplicateFunction { name, .. } => {
+                format!("Function already exists: {}", name)
+            }
+            ParseError::BuiltinName { name, .. } => {
+                format!("Function already exists as a built in: {}", name)
+            }
+            ParseError::DuplicateParameter { name, .. } => {
+                format!("Parameter already exists: {}", name)
+            }
+            ParseError::WrongArgumentCount {
+                name,
+                expected,
+                found,
+                ..
+            } => format!(
+                "{} takes {} argument{}, got {}",
+                name,
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                found
+            ),
+            ParseError::DuplicateArray { name, .. } => format!("Array already exists: {}", name),
+            ParseError::UndeclaredArray { name, .. } => {
+                format!("Setting an element of an undeclared array: {}", name)
+            }
+            ParseError::WrongIndexCount {
+                name,
+                expected,
+                found,
+                ..
+            } => format!(
+                "{} takes {} ind{}, got {}",
+                name,
+                expected,
+                if *expected == 1 { "ex" } else { "ices" },
+                found
+            ),
+            ParseError::ArraySize { .. } => format!(
+                "Array sizes must be whole numbers above 0, with at most {} elements in all",
+                MAX_ARRAY_SIZE
+            ),
+            ParseError::WrongType {
+                expected, found, ..
+            } => format!("Expected a {}, got a {}", expected.name(), found.name()),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::Lex(error) => error.span(),
+            ParseError::UnexpectedToken { found, .. }
+            | ParseError::InvalidStatement { found }
+            | ParseError::ExpectedComparison { found }
+            | ParseError::ExpectedExpression { found }
+            | ParseError::OutsideLoop { found }
+            | ParseError::OutsideFunction { found }
+            | ParseError::Nested { found, .. } => found.span,
+            ParseError::UndeclaredVariable { span, .. }
+            | ParseError::DuplicateLabel { span, .. }
+            | ParseError::UndeclaredLabel { span, .. }
+            | ParseError::UndeclaredFunction { span, .. }
+            | ParseError::DuplicateFunction { span, .. }
+            | ParseError::BuiltinName { span, .. }
+            | ParseError::DuplicateParameter { span, .. }
+            | ParseError::WrongArgumentCount { span, .. }
+            | ParseError::DuplicateArray { span, .. }
+            | ParseError::UndeclaredArray { span, .. }
+            | ParseError::WrongIndexCount { span, .. }
+            | ParseError::ArraySize { span }
+            | ParseError::WrongType { span, .. } => *span,
+        }
+    }
+}
+
+// how a token reads in an error message
+fn describe(token: &Token) -> String {
+    match token.token_type {
+        TokenType::NEWLINE => "a newline".to_string(),
+        TokenType::EOF => "end of input".to_string(),
+        TokenType::STRING => format!("\"{}\"", token.value),
+        _ => format!("'{}'", token.value),
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message(), self.span())
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<LexError> for ParseError {
+    fn from(error: LexError) -> Self {
+        ParseError::Lex(error)
+    }
+}
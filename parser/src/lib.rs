@@ -1,42 +1,115 @@
-use cemitter::Emitter;
-use lexer::tokens::{Token, TokenType};
+pub mod ast;
+pub mod error;
+
+use ast::{
+    BinaryOp, Builtin, Comparison, ComparisonOp, Condition, Expr, Function, Program, Statement,
+    StatementKind, Type, UnaryOp, MAX_ARRAY_SIZE, UNARY_PRECEDENCE,
+};
+use error::ParseError;
+use lexer::tokens::{Span, Token, TokenType};
 use lexer::Lexer;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+
+// a call's FUNCTION name, the type and span of each argument and where the whole call is
+type Call = (String, Vec<(Type, Span)>, Span);
 
-pub struct Parser<'a> {
+pub struct Parser {
     lexer: Lexer,
-    emitter: &'a mut Emitter,
     cur_token: Token,
     peek_token: Token,
-    // use a hashset (FOR SPEEEEEEEEEEEED NEEOOWWWWWW)
-    symbols: HashSet<String>,
-    labels_declared: HashSet<String>,
-    labels_go_toed: HashSet<String>,
+    // the token before cur_token, for working out where a statement ends
+    last_span: Span,
+    // use a hashset (FOR SPEEEEEEEEEEEED NEEOOWWWWWW). One for each scope we're in, the
+    // program's first, then the FUNCTION's when we're in one. Only the last one is visible
+    symbols: Vec<HashSet<String>>,
+    // the arrays DIMed in each scope, how many sizes each has and where it was declared.
+    // Ones declared before this program, e.g. by an earlier REPL entry, have no span
+    arrays: Vec<HashMap<String, (usize, Option<Span>)>>,
+    // every FUNCTION declared so far, the types of its parameters and where its name is
+    functions: HashMap<String, (Vec<Type>, Span)>,
+    // FUNCTIONs declared before this program, e.g. by an earlier REPL entry
+    known_functions: HashMap<String, Vec<Type>>,
+    // every call, the type and span of each argument and where the whole call was, checked
+    // once every FUNCTION is known so they can be called before they're declared
+    calls: Vec<Call>,
+    // what the FUNCTION we're inside gives back, if we're in one. RETURN hands it back
+    function: Option<Type>,
+    // where each label was declared
+    labels_declared: HashMap<String, Span>,
+    // every GOTO and GOSUB and where it was, for reporting undeclared labels
+    labels_go_toed: Vec<(String, Span)>,
+    // the keywords that close the blocks we're currently inside, innermost last
+    open_blocks: Vec<TokenType>,
+    // how many WHILEs and FORs we're inside, BREAK and CONTINUE need at least one
+    loops: usize,
+    // everything that has gone wrong so far, we keep going after an error to find the rest
+    errors: Vec<ParseError>,
 }
 
-impl<'a> Parser<'a> {
-    pub fn new(lexer: Lexer, emitter: &'a mut Emitter) -> Self {
-        let mut newparser = Parser {
+impl Parser {
+    pub fn new(lexer: Lexer) -> Self {
+        // cur_token and peek_token are filled in by program(), where a bad first token
+        // can be reported as an error
+        Parser {
             lexer,
-            emitter,
             cur_token: Token {
                 token_type: TokenType::UNKNOWN,
                 value: "".to_string(),
+                span: Span::default(),
             },
             peek_token: Token {
                 token_type: TokenType::UNKNOWN,
                 value: "".to_string(),
+                span: Span::default(),
             },
-            symbols: HashSet::new(),
-            labels_declared: HashSet::new(),
-            labels_go_toed: HashSet::new(),
-        };
-        // initialise cur_token and peek_token.
-        // due to functions, it initialises peek_token first and then cur_token
-        // calling twice will give cur_token the first token and peek_token the second.
-        newparser.next_token();
-        newparser.next_token();
-        newparser
+            last_span: Span::default(),
+            symbols: vec![HashSet::new()],
+            arrays: vec![HashMap::new()],
+            functions: HashMap::new(),
+            known_functions: HashMap::new(),
+            calls: Vec::new(),
+            function: None,
+            labels_declared: HashMap::new(),
+            labels_go_toed: Vec::new(),
+            open_blocks: Vec::new(),
+            loops: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Treat a variable as already declared, e.g. by an earlier line typed into the REPL.
+    pub fn declare(&mut self, name: &str) {
+        self.symbols[0].insert(name.to_string());
+    }
+
+    /// Treat a FUNCTION as already declared with these parameters. Declaring it again
+    /// replaces it rather than being an error.
+    pub fn declare_function(&mut self, name: &str, params: &[String]) {
+        let types = params.iter().map(|param| Type::of_name(param)).collect();
+        self.known_functions.insert(name.to_string(), types);
+    }
+
+    /// Treat an array as already declared with this many sizes. Declaring it again replaces
+    /// it rather than being an error.
+    pub fn declare_array(&mut self, name: &str, sizes: usize) {
+        self.arrays[0].insert(name.to_string(), (sizes, None));
+    }
+
+    /// Every variable declared so far outside any FUNCTION.
+    pub fn symbols(&self) -> &HashSet<String> {
+        &self.symbols[0]
+    }
+
+    // declare the current token as a variable in the innermost scope, if it isn't already
+    fn declare_current(&mut self) {
+        let name = self.cur_token.value.clone();
+        self.symbols.last_mut().unwrap().insert(name);
+    }
+
+    // how many sizes the array called `name` has, if the scope we're in has one
+    fn array(&self, name: &str) -> Option<usize> {
+        let (sizes, _) = self.arrays.last().unwrap().get(name)?;
+        Some(*sizes)
     }
 
     pub fn check_token(&self, kind: TokenType) -> bool {
@@ -44,29 +117,92 @@ impl<'a> Parser<'a> {
     }
 
     pub fn check_peek(&self, kind: TokenType) -> bool {
-        kind == self.cur_token.token_type
+        kind == self.peek_token.token_type
     }
 
     /// Try and match the current token to the next expected token.
-    pub fn match_token(&mut self, kind: TokenType) {
+    pub fn match_token(&mut self, kind: TokenType) -> Result<(), ParseError> {
         if !self.check_token(kind.clone()) {
-            panic!("Expected token {:?}, got {:?}", kind, self.cur_token);
+            return Err(ParseError::UnexpectedToken {
+                expected: kind,
+                found: self.cur_token.clone(),
+            });
         }
         self.next_token();
+        Ok(())
     }
 
+    /// Move along a token. Anything the lexer can't make sense of is reported and skipped.
     pub fn next_token(&mut self) {
+        self.last_span = self.cur_token.span;
         self.cur_token = self.peek_token.clone();
-        self.peek_token = self.lexer.get_token();
+        loop {
+            match self.lexer.get_token() {
+                Ok(token) => {
+                    self.peek_token = token;
+                    return;
+                }
+                Err(error) => self.report(ParseError::Lex(error)),
+            }
+        }
+    }
+
+    /// Record an error and carry on.
+    fn report(&mut self, error: ParseError) {
+        // one syntax error per line is plenty, anything after the first is usually fallout
+        // from it (e.g. a bad character leaving a PRINT with nothing to print)
+        if !error.is_semantic() {
+            let line = error.span().line;
+            if self
+                .errors
+                .iter()
+                .any(|e| !e.is_semantic() && e.span().line == line)
+            {
+                return;
+            }
+        }
+        self.errors.push(error);
+    }
+
+    // After an error skip to somewhere sensible to carry on from: the start of the next line,
+    // or the keyword that ends a block we're inside.
+    fn synchronize(&mut self) {
+        loop {
+            if self.check_token(TokenType::NEWLINE) {
+                while self.check_token(TokenType::NEWLINE) {
+                    self.next_token();
+                }
+                return;
+            }
+            if self.check_token(TokenType::EOF)
+                || self.open_blocks.contains(&self.cur_token.token_type)
+            {
+                return;
+            }
+            self.next_token();
+        }
+    }
+
+    /// Match an identifier and hand back its name.
+    fn identifier(&mut self) -> Result<String, ParseError> {
+        let name = self.cur_token.value.clone();
+        self.match_token(TokenType::IDENTIFIER)?;
+        Ok(name)
     }
 
     // ----------------------------- IMPORTANT CHECKPOINT ----------------------------------
     // |     we will now begin implementing the function for each rule of the grammer      |
     // -------------------------------------------------------------------------------------
     /// Begin program. This is the inpoint for the user
-    pub fn program(&mut self) {
-        self.emitter.header_line("#include <stdio.h>".to_string());
-        self.emitter.header_line("int main(void) {".to_string());
+    /// Every error found is returned, in the order they appear in the source.
+    pub fn program(&mut self) -> Result<Program, Vec<ParseError>> {
+        let mut program = Program::default();
+
+        // initialise cur_token and peek_token.
+        // due to functions, it initialises peek_token first and then cur_token
+        // calling twice will give cur_token the first token and peek_token the second.
+        self.next_token();
+        self.next_token();
 
         // ignore excess newlines
         while self.check_token(TokenType::NEWLINE) {
@@ -74,261 +210,1422 @@ impl<'a> Parser<'a> {
         }
         // parse all the statements in this program
         while !self.check_token(TokenType::EOF) {
-            self.statement();
+            match self.statement() {
+                Ok(statement) => program.statements.push(statement),
+                Err(error) => {
+                    self.report(error);
+                    self.synchronize();
+                }
+            }
         }
 
-        // by this point program is done
-        self.emitter.emit_line("return 0;".to_string());
-        self.emitter.emit_line("}".to_string());
-
         // now check that each label in a GOTO exists
-        for label in self.labels_go_toed.iter() {
-            if !self.labels_declared.contains(label) {
-                // its not in the declared labels list so die
-                panic!("Attempting to GOTO an undeclared label: {}", label);
+        self.check_labels();
+
+        // and that each call has a FUNCTION to go to, with the right arguments for it
+        for (name, args, span) in std::mem::take(&mut self.calls) {
+            let params = match self.functions.get(&name) {
+                Some((params, _)) => Some(params.clone()),
+                None => self.known_functions.get(&name).cloned(),
+            };
+            match params {
+                None => self.report(ParseError::UndeclaredFunction { name, span }),
+                Some(params) => self.check_args(name, &params, args, span),
+            }
+        }
+
+        if self.errors.is_empty() {
+            Ok(program)
+        } else {
+            let mut errors = std::mem::take(&mut self.errors);
+            errors.sort_by_key(|error| error.span().start);
+            Err(errors)
+        }
+    }
+
+    // Complain about every GOTO and GOSUB so far whose label isn't declared. Labels only
+    // reach within the FUNCTION, or the code outside any, they're in.
+    fn check_labels(&mut self) {
+        for (label, span) in std::mem::take(&mut self.labels_go_toed) {
+            if !self.labels_declared.contains_key(&label) {
+                // its not in the declared labels list so complain
+                self.report(ParseError::UndeclaredLabel { name: label, span });
+            }
+        }
+    }
+
+    // The statements of an IF, WHILE or FOR body, up to one of the keywords that can close it.
+    // Errors inside are reported and skipped so the rest of the body still gets checked.
+    fn block(&mut self, ends: &[TokenType]) -> Vec<Statement> {
+        let mut body = Vec::new();
+        self.open_blocks.extend_from_slice(ends);
+        while !ends.contains(&self.cur_token.token_type) {
+            // the end of the file or of an outer block means this one was never closed,
+            // leave that for the caller to report
+            if self.check_token(TokenType::EOF)
+                || self.open_blocks.contains(&self.cur_token.token_type)
+            {
+                break;
+            }
+            match self.statement() {
+                Ok(statement) => body.push(statement),
+                Err(error) => {
+                    self.report(error);
+                    self.synchronize();
+                }
+            }
+        }
+        let open = self.open_blocks.len() - ends.len();
+        self.open_blocks.truncate(open);
+        body
+    }
+
+    // The header and the closing keyword of a block can both be broken. Report the header's
+    // error and hand back the closing one, which is where parsing has got to.
+    fn block_parts<T>(
+        &mut self,
+        header: Result<T, ParseError>,
+        end: Result<(), ParseError>,
+    ) -> Result<T, ParseError> {
+        match (header, end) {
+            (Err(header), Err(end)) => {
+                self.report(header);
+                Err(end)
+            }
+            (header, end) => {
+                end?;
+                header
+            }
+        }
+    }
+
+    // The condition and THEN of an IF or ELSEIF. A broken one is skipped over so the body
+    // still gets checked.
+    fn if_header(&mut self) -> Result<Condition, ParseError> {
+        let condition = self.condition().and_then(|condition| {
+            // needs to have a THEN after comparison expression.
+            self.match_token(TokenType::THEN)?;
+            self.nl()?;
+            Ok(condition)
+        });
+        if condition.is_err() {
+            self.synchronize();
+        }
+        condition
+    }
+
+    // Everything in a FOR before its body: the variable, where it starts and ends, and the
+    // step if there is one.
+    fn for_header(&mut self) -> Result<(String, Expr, Expr, Option<Expr>), ParseError> {
+        let span = self.cur_token.span;
+        let variable = self.identifier()?;
+        // it counts, so it has to be a number
+        self.check_type(Type::Number, Type::of_name(&variable), span);
+        self.match_token(TokenType::EQ)?;
+        let start = self.typed_expression(Type::Number)?;
+        self.match_token(TokenType::TO)?;
+        let end = self.typed_expression(Type::Number)?;
+        let step = if self.check_token(TokenType::STEP) {
+            self.next_token();
+            Some(self.typed_expression(Type::Number)?)
+        } else {
+            None
+        };
+        self.nl()?;
+        Ok((variable, start, end, step))
+    }
+
+    // The name and parameters of a FUNCTION, declaring both. The name is declared before the
+    // body is parsed so the FUNCTION can call itself.
+    fn function_header(&mut self) -> Result<(String, Vec<String>), ParseError> {
+        let span = self.cur_token.span;
+        let name = self.identifier()?;
+        self.match_token(TokenType::LPAREN)?;
+        let mut params: Vec<(String, Span)> = Vec::new();
+        if !self.check_token(TokenType::RPAREN) {
+            loop {
+                let param_span = self.cur_token.span;
+                let param = self.identifier()?;
+                if let Some((_, first)) = params.iter().find(|(other, _)| *other == param) {
+                    self.report(ParseError::DuplicateParameter {
+                        name: param.clone(),
+                        span: param_span,
+                        first: *first,
+                    });
+                }
+                self.symbols.last_mut().unwrap().insert(param.clone());
+                params.push((param, param_span));
+                if !self.check_token(TokenType::COMMA) {
+                    break;
+                }
+                self.next_token();
             }
         }
+        self.match_token(TokenType::RPAREN)?;
+
+        if Builtin::lookup(&name).is_some() {
+            self.report(ParseError::BuiltinName {
+                name: name.clone(),
+                span,
+            });
+        } else if let Some((_, first)) = self.functions.get(&name) {
+            self.report(ParseError::DuplicateFunction {
+                name: name.clone(),
+                span,
+                first: *first,
+            });
+        } else {
+            let types = params
+                .iter()
+                .map(|(param, _)| Type::of_name(param))
+                .collect();
+            self.functions.insert(name.clone(), (types, span));
+        }
+        self.nl()?;
+        Ok((name, params.into_iter().map(|(param, _)| param).collect()))
     }
 
     // Process each type of statement that we have defined. (e.g. PRINT | IF)
-    fn statement(&mut self) {
+    fn statement(&mut self) -> Result<Statement, ParseError> {
         // ----- Here begins our gigantic if statements which we knew we eventually had to add eh?
+        let start = self.cur_token.span;
 
         // is it a PRINT?
-        // PRINT (expression | string)
-        if self.check_token(TokenType::PRINT) {
+        // PRINT expression
+        let statement = if self.check_token(TokenType::PRINT) {
             self.next_token();
 
-            // check for string or expression.
-            if self.check_token(TokenType::STRING) {
-                self.emitter
-                    .emit_line(format!("printf(\"{}\\n\");", self.cur_token.value));
-                self.next_token();
-            } else {
-                // then we have an expression to evaluate and print (e.g. 2+2)
-                self.emitter
-                    .emit("printf(\"%.2f\\n\", (float)(".to_string()); // printf("%.2f\n", (float)(EXPRESSION))
-                self.expression(); // expression will print result
-                self.emitter.emit_line("));".to_string()); // one bracket to close expression and 1 for printf
+            // a string on its own is printed as it is, anything else (e.g. 2+2 or "a" + b$)
+            // has to be worked out first
+            match self.expression()? {
+                Expr::String(text) => StatementKind::PrintString(text),
+                expr => StatementKind::PrintExpr(expr),
             }
         } else
         // IF statement?
-        // IF comparison THEN nl { statement } ENDIF nl
+        // IF condition THEN nl { statement } { ELSEIF condition THEN nl { statement } }
+        //     [ ELSE nl { statement } ] ENDIF nl
         if self.check_token(TokenType::IF) {
             self.next_token();
-            self.emitter.emit("if (".to_string());
-            self.comparison();
-
-            // needs to have a THEN after comparison expression.
-            self.match_token(TokenType::THEN);
-            self.nl();
-            self.emitter.emit_line("){".to_string()); // closing if comparison and opening block
+            let mut conditions = Vec::new();
+            let mut bodies = Vec::new();
+            let mut else_body = None;
+            loop {
+                conditions.push(self.if_header());
+                // in the body of the IF, we'll have zero or more statements
+                bodies.push(self.block(&[TokenType::ELSEIF, TokenType::ELSE, TokenType::ENDIF]));
+                if self.check_token(TokenType::ELSEIF) {
+                    self.next_token();
+                    continue;
+                }
+                if self.check_token(TokenType::ELSE) {
+                    self.next_token();
+                    if let Err(error) = self.nl() {
+                        self.report(error);
+                        self.synchronize();
+                    }
+                    else_body = Some(self.block(&[TokenType::ENDIF]));
+                }
+                break;
+            }
 
-            // in the body of the IF, we'll have zero or more statements
-            while !self.check_token(TokenType::ENDIF) {
-                self.statement();
+            // only the last broken condition is handed back, report the rest now
+            let mut header = Ok(Vec::new());
+            for condition in conditions {
+                match (&mut header, condition) {
+                    (Ok(conditions), Ok(condition)) => conditions.push(condition),
+                    (Ok(_), Err(error)) => header = Err(error),
+                    (Err(_), Ok(_)) => {}
+                    (Err(previous), Err(error)) => self.report(std::mem::replace(previous, error)),
+                }
             }
 
             // we need to have an ENDIF eventually after IF
-            self.match_token(TokenType::ENDIF);
-            self.emitter.emit_line("}".to_string());
+            let end = self.match_token(TokenType::ENDIF);
+            StatementKind::If {
+                branches: self
+                    .block_parts(header, end)?
+                    .into_iter()
+                    .zip(bodies)
+                    .collect(),
+                else_body,
+            }
         } else
-        // WHILE comparison REPEAT { statement } ENDWHILE
+        // WHILE condition REPEAT { statement } ENDWHILE
         if self.check_token(TokenType::WHILE) {
             self.next_token();
-            self.emitter.emit("while(".to_string());
-            self.comparison();
-
-            // like for IF we had THEN, WHILE has REPEAT
-            self.match_token(TokenType::REPEAT);
-            self.nl();
-            self.emitter.emit_line("){".to_string());
+            let condition = self.condition().and_then(|condition| {
+                // like for IF we had THEN, WHILE has REPEAT
+                self.match_token(TokenType::REPEAT)?;
+                self.nl()?;
+                Ok(condition)
+            });
+            if condition.is_err() {
+                self.synchronize();
+            }
 
             // then the statements in the body
-            while !self.check_token(TokenType::ENDWHILE) {
-                self.statement();
-            }
+            self.loops += 1;
+            let body = self.block(&[TokenType::ENDWHILE]);
+            self.loops -= 1;
             // even though we check for it previously, _make sure_ that it is there.
-            self.match_token(TokenType::ENDWHILE);
-            self.emitter.emit_line("}".to_string());
+            let end = self.match_token(TokenType::ENDWHILE);
+            StatementKind::While {
+                condition: self.block_parts(condition, end)?,
+                body,
+            }
+        } else
+        // FOR ident = expression TO expression [ STEP expression ] nl { statement } NEXT
+        if self.check_token(TokenType::FOR) {
+            self.next_token();
+            // the loop variable is declared like LET declares one
+            self.declare_current();
+            let header = self.for_header();
+            if header.is_err() {
+                self.synchronize();
+            }
+
+            self.loops += 1;
+            let body = self.block(&[TokenType::NEXT]);
+            self.loops -= 1;
+            let end = self.match_token(TokenType::NEXT);
+            let (variable, start, end, step) = self.block_parts(header, end)?;
+            StatementKind::For {
+                variable,
+                start,
+                end,
+                step,
+                body,
+            }
+        } else
+        // BREAK | CONTINUE, only inside a loop
+        if self.check_token(TokenType::BREAK) || self.check_token(TokenType::CONTINUE) {
+            if self.loops == 0 {
+                self.report(ParseError::OutsideLoop {
+                    found: self.cur_token.clone(),
+                });
+            }
+            let statement = if self.check_token(TokenType::BREAK) {
+                StatementKind::Break
+            } else {
+                StatementKind::Continue
+            };
+            self.next_token();
+            statement
         } else
         // a label for GOTO statements
         // LABEL ident
         if self.check_token(TokenType::LABEL) {
             self.next_token();
+            // make sure that there is a name for the LABEL
+            let span = self.cur_token.span;
+            let name = self.identifier()?;
             // make sure that the label doesn't already exist
-            if self.labels_declared.contains(&self.cur_token.value) {
-                panic!("Label already exists: {}", self.cur_token.value);
+            if let Some(first) = self.labels_declared.get(&name) {
+                self.report(ParseError::DuplicateLabel {
+                    name: name.clone(),
+                    span,
+                    first: *first,
+                });
+            } else {
+                // it doesnt exist so add it now
+                self.labels_declared.insert(name.clone(), span);
             }
-            // it doesnt exist so add it now
-            self.labels_declared
-                .insert(self.cur_token.value.to_string());
-
-            self.emitter.emit_line(format!("{}:", self.cur_token.value));
-            // make sure that there is a name for the LABEL
-            self.match_token(TokenType::IDENTIFIER);
+            StatementKind::Label(name)
         } else
         // GOTO ident
         if self.check_token(TokenType::GOTO) {
             self.next_token();
             // add the identifier to the gotoed HashSet
-            self.labels_go_toed.insert(self.cur_token.value.to_string());
-            self.emitter
-                .emit_line(format!("goto {};", self.cur_token.value));
-            self.match_token(TokenType::IDENTIFIER);
+            self.labels_go_toed
+                .push((self.cur_token.value.to_string(), self.cur_token.span));
+            StatementKind::Goto(self.identifier()?)
         } else
-        // LET ident = expression
-        if self.check_token(TokenType::LET) {
+        // GOSUB ident, its label is checked along with the GOTOs
+        if self.check_token(TokenType::GOSUB) {
+            // a RETURN in a FUNCTION leaves it, so it couldn't come back from a GOSUB
+            if self.function.is_some() {
+                self.report(ParseError::Nested {
+                    found: self.cur_token.clone(),
+                    inside: "a FUNCTION",
+                });
+            }
+            self.next_token();
+            self.labels_go_toed
+                .push((self.cur_token.value.to_string(), self.cur_token.span));
+            StatementKind::Gosub(self.identifier()?)
+        } else
+        // RETURN on its own goes back to the last GOSUB, whether there is one is only known
+        // when it runs. In a FUNCTION it's RETURN expression, and hands back a value
+        if self.check_token(TokenType::RETURN) {
+            let token = self.cur_token.clone();
             self.next_token();
+            if let Some(ty) = self.function {
+                StatementKind::ReturnValue(self.typed_expression(ty)?)
+            } else if self.check_token(TokenType::NEWLINE) || self.check_token(TokenType::EOF) {
+                StatementKind::Return
+            } else {
+                return Err(ParseError::OutsideFunction { found: token });
+            }
+        } else
+        // FUNCTION ident ( [ident {, ident}] ) nl { statement } ENDFUNCTION
+        if self.check_token(TokenType::FUNCTION) {
+            // C can't put a function inside anything else
+            if self.function.is_some() || !self.open_blocks.is_empty() {
+                self.report(ParseError::Nested {
+                    found: self.cur_token.clone(),
+                    inside: if self.function.is_some() {
+                        "a FUNCTION"
+                    } else {
+                        "an IF, WHILE or FOR"
+                    },
+                });
+            }
+            self.next_token();
+
+            // the body starts with nothing declared, not even the labels outside
+            self.symbols.push(HashSet::new());
+            self.arrays.push(HashMap::new());
+            let labels_declared = std::mem::take(&mut self.labels_declared);
+            let labels_go_toed = std::mem::take(&mut self.labels_go_toed);
+            let loops = std::mem::replace(&mut self.loops, 0);
+            let ty = Type::of_name(&self.cur_token.value);
+            let function = self.function.replace(ty);
 
-            // check if it exists in symbol table, and declare if not
-            if !self.symbols.contains(&self.cur_token.value) {
-                self.symbols.insert(self.cur_token.value.to_string());
-                self.emitter
-                    .header_line(format!("float {};", self.cur_token.value));
+            let header = self.function_header();
+            if header.is_err() {
+                self.synchronize();
             }
+            let body = self.block(&[TokenType::ENDFUNCTION]);
+            let end = self.match_token(TokenType::ENDFUNCTION);
+
+            self.check_labels();
+            self.labels_declared = labels_declared;
+            self.labels_go_toed = labels_go_toed;
+            self.loops = loops;
+            self.function = function;
+            self.symbols.pop();
+            self.arrays.pop();
+
+            let (name, params) = self.block_parts(header, end)?;
+            StatementKind::Function(Function { name, params, body })
+        } else
+        // LET ident = expression | LET ident ( expression {, expression} ) = expression
+        if self.check_token(TokenType::LET) {
+            self.next_token();
+
+            // setting an element of an array doesn't declare anything
+            if self.check_peek(TokenType::LPAREN) {
+                let (name, indices) = self.element()?;
+                self.match_token(TokenType::EQ)?;
+                StatementKind::LetElement {
+                    value: self.typed_expression(Type::of_name(&name))?,
+                    name,
+                    indices,
+                }
+            } else {
+                // check if it exists in symbol table, and declare if not
+                self.declare_current();
 
-            // I am fully 100% rarted
-            self.emitter
-                .emit(self.cur_token.value.clone() + &" = ".to_string());
-            self.match_token(TokenType::IDENTIFIER);
-            self.match_token(TokenType::EQ);
+                let name = self.identifier()?;
+                self.match_token(TokenType::EQ)?;
 
-            self.expression();
-            self.emitter.emit_line(";".to_string());
+                StatementKind::Let {
+                    value: self.typed_expression(Type::of_name(&name))?,
+                    name,
+                }
+            }
         } else
         // INPUT ident
         if self.check_token(TokenType::INPUT) {
             self.next_token();
             // if the variable doesn't exist already, declare it
-            if !self.symbols.contains(&self.cur_token.value) {
-                self.symbols.insert(self.cur_token.value.to_string());
-                self.emitter
-                    .header_line(format!("float {};", self.cur_token.value));
-            }
-            // emit scanf and validate input. If invalid set to 0 and clear input
-            self.emitter.emit_line(format!(
-                "if (0 == scanf(\"%f\", &{})) {{",
-                self.cur_token.value
-            )); // if (0 == scanf("%f", &example_float));
-            self.emitter
-                .emit_line(format!("{} = 0.0;", self.cur_token.value)); // example_float = 0;
-            self.emitter.emit("scanf(\"%".to_string()); // scanf("%*s)
-            self.emitter.emit_line("*s\");".to_string());
-            self.emitter.emit_line("}".to_string());
+            self.declare_current();
             // we gotta know what to input into
-            self.match_token(TokenType::IDENTIFIER);
+            StatementKind::Input(self.identifier()?)
+        } else
+        // DIM ident ( number {, number} )
+        if self.check_token(TokenType::DIM) {
+            self.next_token();
+            let span = self.cur_token.span;
+            let name = self.identifier()?;
+            self.match_token(TokenType::LPAREN)?;
+            let sizes_start = self.cur_token.span;
+            let mut sizes = Vec::new();
+            loop {
+                sizes.push(self.array_size()?);
+                if !self.check_token(TokenType::COMMA) {
+                    break;
+                }
+                self.next_token();
+            }
+            self.match_token(TokenType::RPAREN)?;
+            let total = sizes
+                .iter()
+                .try_fold(1usize, |total, size| total.checked_mul(*size));
+            if total.is_none_or(|total| total > MAX_ARRAY_SIZE) {
+                self.report(ParseError::ArraySize {
+                    span: sizes_start.to(self.last_span),
+                });
+            }
+
+            if Builtin::lookup(&name).is_some() {
+                self.report(ParseError::BuiltinName {
+                    name: name.clone(),
+                    span,
+                });
+            } else if let Some((_, Some(first))) = self.arrays.last().unwrap().get(&name) {
+                self.report(ParseError::DuplicateArray {
+                    name: name.clone(),
+                    span,
+                    first: *first,
+                });
+            } else {
+                self.arrays
+                    .last_mut()
+                    .unwrap()
+                    .insert(name.clone(), (sizes.len(), Some(span)));
+            }
+            StatementKind::Dim { name, sizes }
         } else {
             // invalid statement
-            panic!(
-                "Invalid statement at {} ({:?})",
-                self.cur_token.value, self.cur_token.token_type
-            );
-        }
+            return Err(ParseError::InvalidStatement {
+                found: self.cur_token.clone(),
+            });
+        };
+        let span = start.to(self.last_span);
         // print a newline must be at end
-        self.nl();
+        self.nl()?;
+        Ok(Statement {
+            kind: statement,
+            span,
+        })
+    }
+
+    // A DIM size, a whole number above 0. A bad one is reported and taken as 1 so the rest
+    // of the program still gets checked.
+    fn array_size(&mut self) -> Result<usize, ParseError> {
+        let token = self.cur_token.clone();
+        self.match_token(TokenType::NUMBER)?;
+        match token.value.parse::<usize>() {
+            Ok(size) if size > 0 => Ok(size),
+            _ => {
+                self.report(ParseError::ArraySize { span: token.span });
+                Ok(1)
+            }
+        }
+    }
+
+    // condition ::= and_condition {OR and_condition}
+    fn condition(&mut self) -> Result<Condition, ParseError> {
+        let mut condition = self.and_condition()?;
+        while self.check_token(TokenType::OR) {
+            self.next_token();
+            let rhs = self.and_condition()?;
+            condition = Condition::Or(Box::new(condition), Box::new(rhs));
+        }
+        Ok(condition)
+    }
+
+    // and_condition ::= not_condition {AND not_condition}
+    fn and_condition(&mut self) -> Result<Condition, ParseError> {
+        let mut condition = self.not_condition()?;
+        while self.check_token(TokenType::AND) {
+            self.next_token();
+            let rhs = self.not_condition()?;
+            condition = Condition::And(Box::new(condition), Box::new(rhs));
+        }
+        Ok(condition)
+    }
+
+    // not_condition ::= NOT not_condition | "(" condition ")" | comparison
+    fn not_condition(&mut self) -> Result<Condition, ParseError> {
+        if self.check_token(TokenType::NOT) {
+            self.next_token();
+            return Ok(Condition::Not(Box::new(self.not_condition()?)));
+        }
+        if self.check_token(TokenType::LPAREN) && self.condition_in_brackets() {
+            // like an expression's brackets, these only group
+            self.next_token();
+            let condition = self.condition()?;
+            self.match_token(TokenType::RPAREN)?;
+            return Ok(condition);
+        }
+        Ok(Condition::Comparison(self.comparison()?))
+    }
+
+    // Whether the bracket we're on holds a condition rather than starting an expression, as
+    // in `(a > 1 OR b > 1) AND c`. Expressions never have a comparison, AND, OR or NOT in
+    // them, even in brackets, so it does if one of those comes before its ")".
+    fn condition_in_brackets(&self) -> bool {
+        let mut lexer = self.lexer.clone();
+        let mut token = self.peek_token.token_type.clone();
+        let mut depth = 1;
+        loop {
+            match token {
+                TokenType::LPAREN => depth += 1,
+                TokenType::RPAREN if depth == 1 => return false,
+                TokenType::RPAREN => depth -= 1,
+                TokenType::NEWLINE | TokenType::EOF => return false,
+                TokenType::EQEQ
+                | TokenType::NOTEQ
+                | TokenType::GT
+                | TokenType::GTEQ
+                | TokenType::LT
+                | TokenType::LTEQ
+                | TokenType::AND
+                | TokenType::OR
+                | TokenType::NOT => return true,
+                _ => {}
+            }
+            // anything the lexer can't make sense of gets reported on the way through for real
+            token = loop {
+                if let Ok(next) = lexer.get_token() {
+                    break next.token_type;
+                }
+            };
+        }
     }
 
     // evaluate a comparison
     // comparison ::= expression (("==" | "!=" | ">" | ">=" | "<" | "<=") expression)+
     // this means that a comparison is an expression with one or more other expressions
     // with a comparison operator between.
-    fn comparison(&mut self) {
-        self.expression();
+    // Everything in the chain has the type of the first expression, and strings can only be
+    // compared with == and !=.
+    fn comparison(&mut self) -> Result<Comparison, ParseError> {
+        let mut start = self.cur_token.span;
+        let first = self.expression()?;
+        let ty = first.ty();
+        let mut rest = Vec::new();
 
         // must have at least one comp.op. and another expression afterwards.
-        if self.is_comparison_operator() {
-            self.emitter.emit(self.cur_token.value.clone());
-            self.next_token();
-            self.expression();
-        } else {
-            panic!("Expected comparison operator at: {}", self.cur_token.value);
+        if self.comparison_operator().is_none() {
+            return Err(ParseError::ExpectedComparison {
+                found: self.cur_token.clone(),
+            });
         }
 
-        // we can now have 0 or more comp.op.s and expression pairs
-        while self.is_comparison_operator() {
-            self.emitter.emit(self.cur_token.value.clone());
+        // then as many more comp.op. and expression pairs as there are
+        while let Some(op) = self.comparison_operator() {
+            if !matches!(op, ComparisonOp::Eq | ComparisonOp::NotEq) {
+                self.check_type(Type::Number, ty, start.to(self.last_span));
+            }
             self.next_token();
-            self.expression();
+            start = self.cur_token.span;
+            rest.push((op, self.typed_expression(ty)?));
         }
+        Ok(Comparison { first, rest })
     }
 
-    // helper to determine whether there is a comparison operator
-    fn is_comparison_operator(&mut self) -> bool {
-        self.check_token(TokenType::GT)
-            || self.check_token(TokenType::GTEQ)
-            || self.check_token(TokenType::LT)
-            || self.check_token(TokenType::LTEQ)
-            || self.check_token(TokenType::EQEQ)
-            || self.check_token(TokenType::NOTEQ)
+    // helper to determine whether there is a comparison operator, and which one
+    fn comparison_operator(&self) -> Option<ComparisonOp> {
+        match self.cur_token.token_type {
+            TokenType::GT => Some(ComparisonOp::Gt),
+            TokenType::GTEQ => Some(ComparisonOp::GtEq),
+            TokenType::LT => Some(ComparisonOp::Lt),
+            TokenType::LTEQ => Some(ComparisonOp::LtEq),
+            TokenType::EQEQ => Some(ComparisonOp::Eq),
+            TokenType::NOTEQ => Some(ComparisonOp::NotEq),
+            _ => None,
+        }
     }
 
     // evaluate an expression
-    // expression ::= term {( "-" | "+" ) term}
-    // an expression is a term optionally followed by a pos. or neg. term
-    fn expression(&mut self) {
-        self.term();
-
-        // can have 0 or more +/- expressions
-        while self.check_token(TokenType::PLUS) || self.check_token(TokenType::MINUS) {
-            self.emitter.emit(self.cur_token.value.clone());
-            self.next_token();
-            self.term();
-        }
+    // expression ::= operand {binary_op operand}
+    // binary_op  ::= "+" | "-" | "*" | "/" | "%" | "^"
+    // The operators are sorted out by precedence climbing: see BinaryOp::precedence for
+    // which binds tighter.
+    fn expression(&mut self) -> Result<Expr, ParseError> {
+        self.binary(0)
     }
 
-    // term ::= unary {( "/" | "*" ) unary}
-    fn term(&mut self) {
-        self.unary();
-
-        // we can have 0 or more * or / and expressions
-        while self.check_token(TokenType::ASTERISK) || self.check_token(TokenType::SLASH) {
-            self.emitter.emit(self.cur_token.value.clone());
+    // An expression made only of operators that bind at least as tightly as `min`.
+    // Strings can only be joined with +, which needs a string on both sides.
+    fn binary(&mut self, min: u8) -> Result<Expr, ParseError> {
+        let start = self.cur_token.span;
+        let mut expr = self.operand()?;
+        while let Some(op) = self.binary_operator() {
+            if op.precedence() < min {
+                break;
+            }
+            let expected = if op == BinaryOp::Add {
+                expr.ty()
+            } else {
+                self.check_type(Type::Number, expr.ty(), start.to(self.last_span));
+                Type::Number
+            };
             self.next_token();
-            self.unary();
+            // a left associative operator's right hand side can't contain another of the
+            // same precedence, a right associative one's can
+            let next = if op.right_associative() {
+                op.precedence()
+            } else {
+                op.precedence() + 1
+            };
+            let rhs_start = self.cur_token.span;
+            let rhs = self.binary(next)?;
+            self.check_type(expected, rhs.ty(), rhs_start.to(self.last_span));
+            expr = Expr::Binary(op, Box::new(expr), Box::new(rhs));
         }
+        Ok(expr)
     }
 
-    // unary ::= ["+" | "-"] primary
-    fn unary(&mut self) {
-        // optionally has a positive or negative
-        if self.check_token(TokenType::PLUS) || self.check_token(TokenType::MINUS) {
-            self.emitter.emit(self.cur_token.value.clone());
-            self.next_token();
+    // helper to determine whether there is a binary operator, and which one
+    fn binary_operator(&self) -> Option<BinaryOp> {
+        match self.cur_token.token_type {
+            TokenType::PLUS => Some(BinaryOp::Add),
+            TokenType::MINUS => Some(BinaryOp::Sub),
+            TokenType::ASTERISK => Some(BinaryOp::Mul),
+            TokenType::SLASH => Some(BinaryOp::Div),
+            TokenType::PERCENT => Some(BinaryOp::Mod),
+            TokenType::CARET => Some(BinaryOp::Pow),
+            _ => None,
         }
-        self.primary();
     }
 
-    // primary ::= number | ident
-    fn primary(&mut self) {
-        if self.check_token(TokenType::NUMBER) {
-            self.emitter.emit(self.cur_token.value.clone());
-            self.next_token();
+    // operand ::= ("+" | "-") operand {"^" operand} | primary
+    fn operand(&mut self) -> Result<Expr, ParseError> {
+        // optionally has a positive or negative, which takes in any ^ after it
+        let op = match self.cur_token.token_type {
+            TokenType::PLUS => UnaryOp::Plus,
+            TokenType::MINUS => UnaryOp::Minus,
+            _ => return self.primary(),
+        };
+        self.next_token();
+        let start = self.cur_token.span;
+        let operand = self.binary(UNARY_PRECEDENCE + 1)?;
+        self.check_type(Type::Number, operand.ty(), start.to(self.last_span));
+        Ok(Expr::Unary(op, Box::new(operand)))
+    }
+
+    // primary ::= number | string | ident "(" [expression {"," expression}] ")" | ident
+    //     | "(" expression ")"
+    // An ident followed by brackets is an element of an array if there's one by that name,
+    // otherwise a call
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let expr = if self.check_token(TokenType::NUMBER) {
+            Expr::Number(self.cur_token.value.clone())
+        } else if self.check_token(TokenType::STRING) {
+            Expr::String(self.cur_token.value.clone())
+        } else if self.check_token(TokenType::IDENTIFIER) && self.check_peek(TokenType::LPAREN) {
+            if self.array(&self.cur_token.value).is_some() {
+                let (name, indices) = self.element()?;
+                return Ok(Expr::Index(name, indices));
+            }
+            return self.call();
         } else if self.check_token(TokenType::IDENTIFIER) {
             // check that the variable exists before we allow it
-            if !self.symbols.contains(&self.cur_token.value) {
-                panic!(
-                    "Referencing variable before assignment: {}",
-                    self.cur_token.value
-                );
+            if !self.symbols.last().unwrap().contains(&self.cur_token.value) {
+                self.report(ParseError::UndeclaredVariable {
+                    name: self.cur_token.value.clone(),
+                    span: self.cur_token.span,
+                });
             }
-            self.emitter.emit(self.cur_token.value.clone());
+            Expr::Variable(self.cur_token.value.clone())
+        } else if self.check_token(TokenType::LPAREN) {
+            // the brackets only group, the tree keeps the grouping without them
             self.next_token();
+            let expr = self.expression()?;
+            self.match_token(TokenType::RPAREN)?;
+            return Ok(expr);
         } else {
-            panic!("Unexpected token at {}", self.cur_token.value);
+            return Err(ParseError::ExpectedExpression {
+                found: self.cur_token.clone(),
+            });
+        };
+        self.next_token();
+        Ok(expr)
+    }
+
+    // An element of an array: its name and an index, a number, for each of its sizes.
+    fn element(&mut self) -> Result<(String, Vec<Expr>), ParseError> {
+        let start = self.cur_token.span;
+        let name = self.identifier()?;
+        self.match_token(TokenType::LPAREN)?;
+        let mut indices = Vec::new();
+        loop {
+            indices.push(self.typed_expression(Type::Number)?);
+            if !self.check_token(TokenType::COMMA) {
+                break;
+            }
+            self.next_token();
+        }
+        self.match_token(TokenType::RPAREN)?;
+        match self.array(&name) {
+            None => self.report(ParseError::UndeclaredArray {
+                name: name.clone(),
+                span: start,
+            }),
+            Some(sizes) if sizes != indices.len() => self.report(ParseError::WrongIndexCount {
+                name: name.clone(),
+                expected: sizes,
+                found: indices.len(),
+                span: start.to(self.last_span),
+            }),
+            Some(_) => {}
+        }
+        Ok((name, indices))
+    }
+
+    // A call to a built in function, or to a FUNCTION. Whether there is one, and whether it
+    // takes these arguments, is checked at the end of the program.
+    fn call(&mut self) -> Result<Expr, ParseError> {
+        let start = self.cur_token.span;
+        let name = self.identifier()?;
+        self.match_token(TokenType::LPAREN)?;
+        let mut args = Vec::new();
+        let mut types = Vec::new();
+        if !self.check_token(TokenType::RPAREN) {
+            loop {
+                let arg_start = self.cur_token.span;
+                let arg = self.expression()?;
+                types.push((arg.ty(), arg_start.to(self.last_span)));
+                args.push(arg);
+                if !self.check_token(TokenType::COMMA) {
+                    break;
+                }
+                self.next_token();
+            }
+        }
+        self.match_token(TokenType::RPAREN)?;
+        let span = start.to(self.last_span);
+        if let Some(builtin) = Builtin::lookup(&name) {
+            self.check_args(name, builtin.params(), types, span);
+            return Ok(Expr::Builtin(builtin, args));
+        }
+        self.calls.push((name.clone(), types, span));
+        Ok(Expr::Call(name, args))
+    }
+
+    // Complain about a call to `name` whose arguments, their types and where they are,
+    // don't fit the parameters.
+    fn check_args(&mut self, name: String, params: &[Type], args: Vec<(Type, Span)>, span: Span) {
+        if params.len() != args.len() {
+            self.report(ParseError::WrongArgumentCount {
+                name,
+                expected: params.len(),
+                found: args.len(),
+                span,
+            });
+            return;
+        }
+        for (expected, (found, span)) in params.iter().zip(args) {
+            self.check_type(*expected, found, span);
         }
     }
 
+    // Complain about a `found` at `span` where only an `expected` can go.
+    fn check_type(&mut self, expected: Type, found: Type, span: Span) {
+        if expected != found {
+            self.report(ParseError::WrongType {
+                expected,
+                found,
+                span,
+            });
+        }
+    }
+
+    // An expression that has to have a certain type, e.g. the value a LET gives a variable.
+    fn typed_expression(&mut self, expected: Type) -> Result<Expr, ParseError> {
+        let start = self.cur_token.span;
+        let expr = self.expression()?;
+        self.check_type(expected, expr.ty(), start.to(self.last_span));
+        Ok(expr)
+    }
+
     // a newline
-    fn nl(&mut self) {
+    fn nl(&mut self) -> Result<(), ParseError> {
         // we need at least one newline
-        self.match_token(TokenType::NEWLINE);
+        self.match_token(TokenType::NEWLINE)?;
         // but allow for more
         while self.check_token(TokenType::NEWLINE) {
             self.next_token();
         }
+        Ok(())
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use lexer::error::LexError;
+
+    fn parse(source: &str) -> Result<Program, Vec<ParseError>> {
+        Parser::new(Lexer::new(source)).program()
+    }
+
+    fn number(text: &str) -> Box<Expr> {
+        Box::new(Expr::Number(text.to_string()))
+    }
+
+    #[test]
+    fn test_expression_precedence() {
+        let program = parse("PRINT 1 + 2 * -3\n").unwrap();
+        assert_eq!(
+            program.statements[0].kind,
+            StatementKind::PrintExpr(Expr::Binary(
+                BinaryOp::Add,
+                number("1"),
+                Box::new(Expr::Binary(
+                    BinaryOp::Mul,
+                    number("2"),
+                    Box::new(Expr::Unary(UnaryOp::Minus, number("3")))
+                ))
+            ))
+        );
+    }
+
+    #[test]
+    fn test_brackets_and_powers() {
+        let program = parse("PRINT -(1 + 2) ^ 3 ^ 2 % 4\n").unwrap();
+        let power = Expr::Binary(
+            BinaryOp::Pow,
+            Box::new(Expr::Binary(BinaryOp::Add, number("1"), number("2"))),
+            Box::new(Expr::Binary(BinaryOp::Pow, number("3"), number("2"))),
+        );
+        assert_eq!(
+            program.statements[0].kind,
+            StatementKind::PrintExpr(Expr::Binary(
+                BinaryOp::Mod,
+                Box::new(Expr::Unary(UnaryOp::Minus, Box::new(power))),
+                number("4")
+            ))
+        );
+        assert!(matches!(
+            parse("PRINT (1 + 2\n").unwrap_err()[..],
+            [ParseError::UnexpectedToken {
+                expected: TokenType::RPAREN,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_else() {
+        let program = parse(
+            "LET a = 1\nIF a == 1 THEN\nPRINT 1\nELSEIF a == 2 THEN\nELSEIF a == 3 THEN\nPRINT 3\nELSE\nPRINT 4\nENDIF\n",
+        )
+        .unwrap();
+        let StatementKind::If {
+            branches,
+            else_body,
+        } = &program.statements[1].kind
+        else {
+            panic!("expected an IF, got {:?}", program.statements[1]);
+        };
+        let bodies: Vec<usize> = branches.iter().map(|(_, body)| body.len()).collect();
+        assert_eq!(bodies, [1, 0, 1]);
+        assert_eq!(
+            else_body.as_ref().unwrap()[0].kind,
+            StatementKind::PrintExpr(*number("4"))
+        );
+
+        // every broken arm is reported, and ELSE has to come last
+        assert_eq!(
+            errors("IF 1 THEN\nELSEIF 2 THEN\nELSE\nELSEIF 1 > 0 THEN\nENDIF\n"),
+            vec![
+                "Expected comparison operator, got 'THEN' at line 1, column 6",
+                "Expected comparison operator, got 'THEN' at line 2, column 10",
+                "Invalid statement 'ELSEIF' (ELSEIF) at line 4, column 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        let program = parse("IF NOT 1 > 2 OR 3 > 4 AND NOT NOT 5 > 6 THEN\nENDIF\n").unwrap();
+        let StatementKind::If { branches, .. } = &program.statements[0].kind else {
+            panic!("expected an IF, got {:?}", program.statements[0]);
+        };
+        let condition = &branches[0].0;
+        let compare = |lhs: &str, rhs: &str| {
+            Box::new(Condition::Comparison(Comparison {
+                first: *number(lhs),
+                rest: vec![(ComparisonOp::Gt, *number(rhs))],
+            }))
+        };
+        assert_eq!(
+            *condition,
+            Condition::Or(
+                Box::new(Condition::Not(compare("1", "2"))),
+                Box::new(Condition::And(
+                    compare("3", "4"),
+                    Box::new(Condition::Not(Box::new(Condition::Not(compare("5", "6")))))
+                ))
+            )
+        );
+
+        // brackets group conditions too, and can still start an expression
+        let program = parse("IF (1 > 2 OR (3 > 4)) AND ((5) + 0) > 6 THEN\nENDIF\n").unwrap();
+        let StatementKind::If { branches, .. } = &program.statements[0].kind else {
+            panic!("expected an IF, got {:?}", program.statements[0]);
+        };
+        assert_eq!(
+            branches[0].0,
+            Condition::And(
+                Box::new(Condition::Or(compare("1", "2"), compare("3", "4"))),
+                Box::new(Condition::Comparison(Comparison {
+                    first: Expr::Binary(BinaryOp::Add, number("5"), number("0")),
+                    rest: vec![(ComparisonOp::Gt, *number("6"))],
+                }))
+            )
+        );
+        let errors = parse("IF (1 > 2 OR 3 > 4 THEN\nENDIF\n").unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "Expected token RPAREN, got 'THEN' at line 1, column 20"
+        );
+    }
+
+    #[test]
+    fn test_blocks() {
+        let program = parse(
+            "LET a = 1\nWHILE a < 3 REPEAT\nIF a == 1 THEN\nPRINT \"one\"\nENDIF\nENDWHILE\n",
+        )
+        .unwrap();
+        let StatementKind::While { condition, body } = &program.statements[1].kind else {
+            panic!("expected a WHILE, got {:?}", program.statements[1]);
+        };
+        let Condition::Comparison(condition) = condition else {
+            panic!("expected a comparison, got {:?}", condition);
+        };
+        assert_eq!(condition.rest, vec![(ComparisonOp::Lt, *number("3"))]);
+        let StatementKind::If {
+            branches,
+            else_body,
+        } = &body[0].kind
+        else {
+            panic!("expected an IF, got {:?}", body[0]);
+        };
+        assert_eq!(*else_body, None);
+        let (condition, body) = &branches[0];
+        assert_eq!(
+            *condition,
+            Condition::Comparison(Comparison {
+                first: Expr::Variable("a".to_string()),
+                rest: vec![(ComparisonOp::Eq, *number("1"))],
+            })
+        );
+        assert_eq!(body[0].kind, StatementKind::PrintString("one".to_string()));
+
+        // a block's span runs from its keyword to the end of the closing one
+        let span = program.statements[1].span;
+        assert_eq!((span.line, span.column), (2, 1));
+        assert_eq!(span.end, 70);
+        assert_eq!(program.statements[0].span.end, 9);
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let program =
+            parse("WHILE 1 < 2 REPEAT\nIF 1 < 2 THEN\nBREAK\nENDIF\nCONTINUE\nENDWHILE\n").unwrap();
+        let StatementKind::While { body, .. } = &program.statements[0].kind else {
+            panic!("expected a WHILE, got {:?}", program.statements[0]);
+        };
+        assert_eq!(body[1].kind, StatementKind::Continue);
+
+        assert_eq!(
+            errors("BREAK\nFOR i = 1 TO 2\nNEXT\nIF 1 < 2 THEN\nCONTINUE\nENDIF\n"),
+            vec![
+                "BREAK outside of a loop at line 1, column 1",
+                "CONTINUE outside of a loop at line 5, column 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gosub() {
+        let program = parse("GOSUB sub\nLABEL sub\nRETURN\n").unwrap();
+        assert_eq!(
+            program.statements[0].kind,
+            StatementKind::Gosub("sub".to_string())
+        );
+        assert_eq!(program.statements[2].kind, StatementKind::Return);
+        assert_eq!(
+            errors("GOSUB nowhere\nRETURN\n"),
+            vec!["Attempting to GOTO an undeclared label: nowhere at line 1, column 7"]
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        let program = parse(
+            "PRINT add(1, twice(2))\nFUNCTION add(a, b)\nLET c = a + b\nRETURN c\nENDFUNCTION\nFUNCTION twice(a)\nRETURN add(a, a)\nENDFUNCTION\n",
+        )
+        .unwrap();
+        assert_eq!(
+            program.statements[0].kind,
+            StatementKind::PrintExpr(Expr::Call(
+                "add".to_string(),
+                vec![
+                    *number("1"),
+                    Expr::Call("twice".to_string(), vec![*number("2")])
+                ]
+            ))
+        );
+        let StatementKind::Function(function) = &program.statements[1].kind else {
+            panic!("expected a FUNCTION, got {:?}", program.statements[1]);
+        };
+        assert_eq!(
+            (function.name.as_str(), &function.params[..]),
+            ("add", &["a".to_string(), "b".to_string()][..])
+        );
+        assert_eq!(
+            function.body[1].kind,
+            StatementKind::ReturnValue(Expr::Variable("c".to_string()))
+        );
+        assert_eq!(program.statements[1].span.line, 2);
+
+        // a FUNCTION only sees its own variables and labels
+        let source = "LET x = 1
+FUNCTION f(a, a)
+PRINT x
+GOSUB g
+RETURN a
+ENDFUNCTION
+FUNCTION f()
+ENDFUNCTION
+RETURN 1
+PRINT f(1) + g()
+WHILE x < 2 REPEAT
+FUNCTION h()
+ENDFUNCTION
+ENDWHILE
+LABEL g
+";
+        assert_eq!(
+            errors(source),
+            vec![
+                "Parameter already exists: a at line 2, column 15",
+                "Referencing variable before assignment: x at line 3, column 7",
+                "GOSUB can't go inside a FUNCTION at line 4, column 1",
+                "Attempting to GOTO an undeclared label: g at line 4, column 7",
+                "Function already exists: f at line 7, column 10",
+                "RETURN with a value outside of a FUNCTION at line 9, column 1",
+                "f takes 2 arguments, got 1 at line 10, column 7",
+                "Calling an undeclared function: g at line 10, column 14",
+                "FUNCTION can't go inside an IF, WHILE or FOR at line 12, column 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        let program = parse(
+            "LET a$ = \"x\" + \"y\"\nPRINT a$ + \"!\"\nPRINT \"hi\"\nIF a$ != \"xy\" THEN\nENDIF\n",
+        )
+        .unwrap();
+        assert_eq!(
+            program.statements[0].kind,
+            StatementKind::Let {
+                name: "a$".to_string(),
+                value: Expr::Binary(
+                    BinaryOp::Add,
+                    Box::new(Expr::String("x".to_string())),
+                    Box::new(Expr::String("y".to_string()))
+                )
+            }
+        );
+        assert_eq!(
+            program.statements[1].kind,
+            StatementKind::PrintExpr(Expr::Binary(
+                BinaryOp::Add,
+                Box::new(Expr::Variable("a$".to_string())),
+                Box::new(Expr::String("!".to_string()))
+            ))
+        );
+        // a string on its own is still just printed
+        assert_eq!(
+            program.statements[2].kind,
+            StatementKind::PrintString("hi".to_string())
+        );
+
+        let source = "LET a$ = 1
+LET b = \"b\"
+LET c$ = a$ - \"c\" + a$ * 2
+IF a$ == 1 OR a$ < \"b\" THEN
+ENDIF
+FOR i$ = 1 TO -a$
+NEXT
+PRINT f(a$, 1) + f$(2)
+FUNCTION f(x, y$)
+RETURN y$
+ENDFUNCTION
+FUNCTION f$(x)
+ENDFUNCTION
+";
+        assert_eq!(
+            errors(source),
+            vec![
+                "Expected a string, got a number at line 1, column 10",
+                "Expected a number, got a string at line 2, column 9",
+                "Expected a number, got a string at line 3, column 10",
+                // a - on strings still gives a number, which c$ can't hold
+                "Expected a string, got a number at line 3, column 10",
+                "Expected a number, got a string at line 3, column 15",
+                "Expected a number, got a string at line 3, column 21",
+                "Expected a string, got a number at line 4, column 10",
+                "Expected a number, got a string at line 4, column 15",
+                "Expected a number, got a string at line 6, column 5",
+                "Expected a number, got a string at line 6, column 16",
+                "Expected a number, got a string at line 8, column 9",
+                "Expected a string, got a number at line 8, column 13",
+                "Expected a number, got a string at line 8, column 18",
+                "Expected a number, got a string at line 10, column 8",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_builtins() {
+        let program = parse("PRINT MID$(\"abc\", LEN(\"a\"), 1)\n").unwrap();
+        assert_eq!(
+            program.statements[0].kind,
+            StatementKind::PrintExpr(Expr::Builtin(
+                Builtin::Mid,
+                vec![
+                    Expr::String("abc".to_string()),
+                    Expr::Builtin(Builtin::Len, vec![Expr::String("a".to_string())]),
+                    Expr::Number("1".to_string()),
+                ]
+            ))
+        );
+
+        let source = "LET a = LEN(1) + VAL(\"1\", 2)
+LET b$ = STR$(\"x\") + LEFT$(1, \"x\")
+LET c = UPPER$(\"x\")
+FUNCTION LEN(x)
+ENDFUNCTION
+LET d$ = SQRT(2) + MAX(1) + ABS(\"x\")
+";
+        assert_eq!(
+            errors(source),
+            vec![
+                "Expected a string, got a number at line 1, column 13",
+                "VAL takes 1 argument, got 2 at line 1, column 18",
+                "Expected a number, got a string at line 2, column 15",
+                "Expected a string, got a number at line 2, column 28",
+                "Expected a number, got a string at line 2, column 31",
+                "Expected a number, got a string at line 3, column 9",
+                "Function already exists as a built in: LEN at line 4, column 10",
+                "Expected a string, got a number at line 6, column 10",
+                "MAX takes 2 arguments, got 1 at line 6, column 20",
+                "Expected a number, got a string at line 6, column 33",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_arrays() {
+        let program = parse("DIM grid(3, 4)\nLET grid(1, 2) = grid(0, 0) + 1\n").unwrap();
+        assert_eq!(
+            program.statements[0].kind,
+            StatementKind::Dim {
+                name: "grid".to_string(),
+                sizes: vec![3, 4],
+            }
+        );
+        assert_eq!(
+            program.statements[1].kind,
+            StatementKind::LetElement {
+                name: "grid".to_string(),
+                indices: vec![Expr::Number("1".to_string()), Expr::Number("2".to_string())],
+                value: Expr::Binary(
+                    BinaryOp::Add,
+                    Box::new(Expr::Index(
+                        "grid".to_string(),
+                        vec![Expr::Number("0".to_string()), Expr::Number("0".to_string())]
+                    )),
+                    Box::new(Expr::Number("1".to_string()))
+                ),
+            }
+        );
+
+        let source = "DIM a(10)
+DIM a(5)
+DIM b(0, 1.5)
+DIM c(100000, 100000)
+DIM LEN(3)
+LET d(1) = 2
+LET a(1, 2) = \"x\"
+PRINT a(\"x\")
+DIM e$(2)
+LET e$(0) = a(0)
+FUNCTION f()
+    DIM a(2, 2)
+    RETURN a(1, 1)
+ENDFUNCTION
+";
+        assert_eq!(
+            errors(source),
+            vec![
+                "Array already exists: a at line 2, column 5",
+                "Array sizes must be whole numbers above 0, with at most 16777216 elements in all at line 3, column 7",
+                "Array sizes must be whole numbers above 0, with at most 16777216 elements in all at line 3, column 10",
+                "Array sizes must be whole numbers above 0, with at most 16777216 elements in all at line 4, column 7",
+                "Function already exists as a built in: LEN at line 5, column 5",
+                "Setting an element of an undeclared array: d at line 6, column 5",
+                "a takes 1 index, got 2 at line 7, column 5",
+                "Expected a number, got a string at line 7, column 15",
+                "Expected a number, got a string at line 8, column 9",
+                "Expected a string, got a number at line 10, column 13",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_for() {
+        let program =
+            parse("FOR i = 10 TO 1 STEP -0.5\nPRINT i\nNEXT\nFOR j = 1 TO i\nNEXT\n").unwrap();
+        let StatementKind::For {
+            variable,
+            start,
+            end,
+            step,
+            body,
+        } = &program.statements[0].kind
+        else {
+            panic!("expected a FOR, got {:?}", program.statements[0]);
+        };
+        assert_eq!(
+            (variable.as_str(), start, end),
+            ("i", &*number("10"), &*number("1"))
+        );
+        assert_eq!(*step, Some(Expr::Unary(UnaryOp::Minus, number("0.5"))));
+        assert_eq!(
+            body[0].kind,
+            StatementKind::PrintExpr(Expr::Variable("i".to_string()))
+        );
+        let StatementKind::For { step, body, .. } = &program.statements[1].kind else {
+            panic!("expected a FOR, got {:?}", program.statements[1]);
+        };
+        assert_eq!((step, body.len()), (&None, 0));
+
+        assert_eq!(
+            errors("FOR i = 1 10\nPRINT i\nFOR j = 1 TO 2\nPRINT k\n"),
+            vec![
+                "Expected token TO, got '10' at line 1, column 11",
+                "Referencing variable before assignment: k at line 4, column 7",
+                "Expected token NEXT, got end of input at line 5, column 1",
+            ]
+        );
+    }
+
+    // every error message from parsing the source, in order
+    fn errors(source: &str) -> Vec<String> {
+        parse(source)
+            .unwrap_err()
+            .iter()
+            .map(|error| error.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_undeclared_label() {
+        assert_eq!(
+            errors("\nGOTO nowhere\nGOTO nowhere\nGOTO elsewhere\n"),
+            vec![
+                "Attempting to GOTO an undeclared label: nowhere at line 2, column 6",
+                "Attempting to GOTO an undeclared label: nowhere at line 3, column 6",
+                "Attempting to GOTO an undeclared label: elsewhere at line 4, column 6",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_undeclared_variable() {
+        assert_eq!(
+            errors("LET foo = bar * 3 + 2\n"),
+            vec!["Referencing variable before assignment: bar at line 1, column 11"]
+        );
+    }
+
+    #[test]
+    fn test_declare() {
+        // variables from earlier REPL entries
+        let mut parser = Parser::new(Lexer::new("LET foo = bar * 3\n"));
+        parser.declare("bar");
+        assert!(parser.program().is_ok());
+        let mut names: Vec<&String> = parser.symbols().iter().collect();
+        names.sort();
+        assert_eq!(names, ["bar", "foo"]);
+
+        // and functions, which can be declared again
+        let mut parser = Parser::new(Lexer::new(
+            "PRINT f(1)\nFUNCTION f(a, b)\nRETURN a\nENDFUNCTION\nPRINT f(1, 2)\n",
+        ));
+        parser.declare_function("f", &["a".to_string()]);
+        parser.declare_function("g", &[]);
+        assert_eq!(
+            parser
+                .program()
+                .unwrap_err()
+                .iter()
+                .map(|error| error.to_string())
+                .collect::<Vec<_>>(),
+            ["f takes 2 arguments, got 1 at line 1, column 7"]
+        );
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        assert!(matches!(
+            parse("IF 1 THEN\nENDIF\n").unwrap_err()[..],
+            [ParseError::ExpectedComparison { .. }]
+        ));
+        assert!(matches!(
+            parse("JUMP GOTO\n").unwrap_err()[..],
+            [ParseError::InvalidStatement { .. }]
+        ));
+        assert!(matches!(
+            parse("PRINT \"oops\n").unwrap_err()[..],
+            [ParseError::Lex(LexError::UnterminatedString { .. })]
+        ));
+        assert!(matches!(
+            parse("LABEL a\nLABEL a\n").unwrap_err()[..],
+            [ParseError::DuplicateLabel { .. }]
+        ));
+    }
+
+    #[test]
+    fn test_recovery() {
+        let source = "PRINT \"fine\"
+LET a = $ 1
+IF a > THEN
+    PRINT b
+    GOTO missing
+ENDIF
+WHILE a < 3 REPEAT
+    LET a = a +
+ENDWHILE
+ENDIF
+LABEL twice
+LABEL twice
+";
+        assert_eq!(
+            errors(source),
+            vec![
+                "Unknown token: '$' at line 2, column 9",
+                "Expected a number, string, variable or '(', got 'THEN' at line 3, column 8",
+                "Referencing variable before assignment: b at line 4, column 11",
+                "Attempting to GOTO an undeclared label: missing at line 5, column 10",
+                "Expected a number, string, variable or '(', got a newline at line 8, column 16",
+                "Invalid statement 'ENDIF' (ENDIF) at line 10, column 1",
+                "Label already exists: twice at line 12, column 7",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unclosed_blocks() {
+        assert_eq!(
+            errors("WHILE 1 < 2 REPEAT\nIF 1 < 2 THEN\nPRINT \"x\"\nENDWHILE\n"),
+            vec!["Expected token ENDIF, got 'ENDWHILE' at line 4, column 1"]
+        );
+        assert_eq!(
+            errors("IF 1 < 2 THEN\nPRINT \"x\"\n"),
+            vec!["Expected token ENDIF, got end of input at line 3, column 1"]
+        );
+    }
+}
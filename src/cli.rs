@@ -0,0 +1,366 @@
+use parser::ast::DEFAULT_MAX_DEPTH;
+use std::fmt;
+
+pub const USAGE: &str = "\
+Usage: teeny_tiny_rust <command> [options] <file.tt>
+
+Commands:
+    build   transpile the program to C (out.c unless -o says otherwise)
+    run     compile the program with the C compiler and run it
+    check   report errors without generating anything
+    tokens  print the tokens the lexer sees
+    ast     print the syntax tree the parser builds
+    fmt     print the program neatly formatted
+    disasm  print the bytecode the VM runs, for a .tt or .ttc file
+    repl    type in statements and run them straight away, the file is optional
+
+Options:
+    -o, --output <path>      where to write the output, - for stdout
+    -q, --quiet              don't print progress messages
+    --native                 build: compile the C into an executable as well
+    --bytecode               build: write VM bytecode to a .ttc file instead of C
+    --interpret              run: use the built in interpreter, no C compiler needed
+    --vm                     run: compile to bytecode for the built in VM instead
+    --cflags <flags>         extra flags for the C compiler, after $CFLAGS
+    --line-directives        point the C back at the .tt lines with #line, for C
+                             compiler messages and debuggers
+    --max-depth <n>          how deep GOSUBs and function calls can
+                             nest before stopping with an error, 1000 by default
+    --error-format=<format>  human (the default) or json, one object per line
+    -h, --help               show this message
+
+Use - as the file to read the program from stdin. A .ttc file can be given to run
+and disasm in place of a .tt one. The C compiler is $CC, or the
+first of cc, gcc and clang that is installed.";
+
+/// What the user asked us to do with the program.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command {
+    Build,
+    Run,
+    Check,
+    Tokens,
+    Ast,
+    Fmt,
+    Disasm,
+    Repl,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Command> {
+        match name {
+            "build" => Some(Command::Build),
+            "run" => Some(Command::Run),
+            "check" => Some(Command::Check),
+            "tokens" => Some(Command::Tokens),
+            "ast" => Some(Command::Ast),
+            "fmt" => Some(Command::Fmt),
+            "disasm" => Some(Command::Disasm),
+            "repl" => Some(Command::Repl),
+            _ => None,
+        }
+    }
+}
+
+/// What `run` runs the program with.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Engine {
+    /// build it with the C compiler
+    Native,
+    /// walk the syntax tree
+    Interpreter,
+    /// compile to bytecode for the VM
+    Vm,
+}
+
+/// Everything from the command line.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Options {
+    pub command: Command,
+    /// path to the program, `-` for stdin. Empty for a repl with nothing to load
+    pub input: String,
+    /// where the output goes, `-` for stdout. Each command has its own default
+    pub output: Option<String>,
+    pub quiet: bool,
+    pub json: bool,
+    /// build an executable rather than stopping at C
+    pub native: bool,
+    /// build bytecode for the VM rather than C
+    pub bytecode: bool,
+    pub engine: Engine,
+    /// from each --cflags, split up later
+    pub cflags: Vec<String>,
+    /// emit #line directives in the C
+    pub line_directives: bool,
+    /// how deep GOSUBs can nest, in every engine
+    pub max_depth: usize,
+}
+
+/// Why the command line couldn't be understood. `Help` isn't really an error, it just
+/// stops us from doing anything else.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ArgsError {
+    Help,
+    Invalid(String),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::Help => write!(f, "{}", USAGE),
+            ArgsError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Options {
+    /// Parse the arguments after the program name. The command can be left out, in which
+    /// case we build, and `--run file.tt` still works the way it used to.
+    pub fn parse(args: &[String]) -> Result<Options, ArgsError> {
+        let mut command = None;
+        let mut input = None;
+        let mut output = None;
+        let mut quiet = false;
+        let mut json = false;
+        let mut native = false;
+        let mut bytecode = false;
+        let mut engine = None;
+        let mut cflags = Vec::new();
+        let mut line_directives = false;
+        let mut max_depth = DEFAULT_MAX_DEPTH;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Err(ArgsError::Help),
+                "-q" | "--quiet" => quiet = true,
+                "--native" => native = true,
+                "--bytecode" => bytecode = true,
+                "--interpret" => engine = Some(Engine::Interpreter),
+                "--vm" => engine = Some(Engine::Vm),
+                "--line-directives" => line_directives = true,
+                "--run" if command.is_none() => {
+                    command = Some(Command::Run);
+                    engine = Some(Engine::Interpreter);
+                }
+                "-o" | "--output" => match args.next() {
+                    Some(path) => output = Some(path.clone()),
+                    None => return Err(invalid(format!("{} needs a path after it", arg))),
+                },
+                "--cflags" => match args.next() {
+                    Some(flags) => cflags.push(flags.clone()),
+                    None => return Err(invalid("--cflags needs flags after it")),
+                },
+                "--max-depth" => match args.next() {
+                    Some(depth) => max_depth = parse_depth(depth)?,
+                    None => return Err(invalid("--max-depth needs a number after it")),
+                },
+                _ => {
+                    if let Some(path) = arg.strip_prefix("--output=") {
+                        output = Some(path.to_string());
+                    } else if let Some(flags) = arg.strip_prefix("--cflags=") {
+                        cflags.push(flags.to_string());
+                    } else if let Some(depth) = arg.strip_prefix("--max-depth=") {
+                        max_depth = parse_depth(depth)?;
+                    } else if let Some(format) = arg.strip_prefix("--error-format=") {
+                        json = match format {
+                            "json" => true,
+                            "human" => false,
+                            _ => {
+                                return Err(invalid(format!(
+                                    "unknown error format '{}', expected human or json",
+                                    format
+                                )))
+                            }
+                        };
+                    } else if arg.len() > 1 && arg.starts_with('-') {
+                        return Err(invalid(format!("unknown option '{}'", arg)));
+                    } else if command.is_none() && input.is_none() {
+                        match Command::from_name(arg) {
+                            Some(name) => command = Some(name),
+                            None => input = Some(arg.clone()),
+                        }
+                    } else if input.is_none() {
+                        input = Some(arg.clone());
+                    } else {
+                        return Err(invalid(format!("unexpected argument '{}'", arg)));
+                    }
+                }
+            }
+        }
+
+        let command = command.unwrap_or(Command::Build);
+        if native && command != Command::Build {
+            return Err(invalid("--native only works with build"));
+        }
+        if bytecode && command != Command::Build {
+            return Err(invalid("--bytecode only works with build"));
+        }
+        if native && bytecode {
+            return Err(invalid("--native and --bytecode can't be used together"));
+        }
+        if engine.is_some() && command != Command::Run {
+            return Err(invalid("--interpret and --vm only work with run"));
+        }
+        if native && output.as_deref() == Some("-") {
+            return Err(invalid("an executable can't be written to stdout"));
+        }
+
+        // the REPL is happy to start from nothing
+        let input = match input {
+            Some(input) => input,
+            None if command == Command::Repl => String::new(),
+            None => return Err(invalid("Compiler needs a source file as an argument")),
+        };
+
+        Ok(Options {
+            command,
+            input,
+            output,
+            quiet,
+            json,
+            native,
+            bytecode,
+            engine: engine.unwrap_or(Engine::Native),
+            cflags,
+            line_directives,
+            max_depth,
+        })
+    }
+}
+
+// a GOSUB depth, which has to allow at least one
+fn parse_depth(depth: &str) -> Result<usize, ArgsError> {
+    match depth.parse() {
+        Ok(depth) if depth > 0 => Ok(depth),
+        _ => Err(invalid(format!(
+            "--max-depth needs a whole number above 0, not '{}'",
+            depth
+        ))),
+    }
+}
+
+fn invalid(message: impl Into<String>) -> ArgsError {
+    ArgsError::Invalid(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, ArgsError> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        Options::parse(&args)
+    }
+
+    #[test]
+    fn test_commands() {
+        let options = parse("run -q prog.tt").unwrap();
+        assert_eq!(options.command, Command::Run);
+        assert_eq!(options.input, "prog.tt");
+        assert!(options.quiet);
+
+        let options = parse("fmt - --output=neat.tt --error-format=json").unwrap();
+        assert_eq!(options.command, Command::Fmt);
+        assert_eq!(options.input, "-");
+        assert_eq!(options.output.as_deref(), Some("neat.tt"));
+        assert!(options.json);
+
+        assert_eq!(parse("disasm prog.ttc").unwrap().command, Command::Disasm);
+        assert!(parse("build --bytecode prog.tt").unwrap().bytecode);
+        assert_eq!(parse("repl").unwrap().input, "");
+
+        // a file called "check" still needs a command in front of it
+        assert_eq!(parse("build check").unwrap().input, "check");
+    }
+
+    #[test]
+    fn test_old_style() {
+        let options = parse("prog.tt -o prog.c").unwrap();
+        assert_eq!(options.command, Command::Build);
+        assert_eq!(options.output.as_deref(), Some("prog.c"));
+        let options = parse("--run prog.tt").unwrap();
+        assert_eq!(options.command, Command::Run);
+        assert_eq!(options.engine, Engine::Interpreter);
+    }
+
+    #[test]
+    fn test_native() {
+        let options = parse("build --native prog.tt --cflags -g --cflags=-Wall").unwrap();
+        assert!(options.native);
+        assert_eq!(options.cflags, vec!["-g", "-Wall"]);
+        assert!(!options.line_directives);
+        assert!(
+            parse("run --line-directives prog.tt")
+                .unwrap()
+                .line_directives
+        );
+        assert_eq!(parse("run prog.tt").unwrap().engine, Engine::Native);
+        assert_eq!(parse("run --vm prog.tt").unwrap().engine, Engine::Vm);
+        assert_eq!(
+            parse("run --native prog.tt"),
+            Err(invalid("--native only works with build"))
+        );
+        assert_eq!(
+            parse("build --interpret prog.tt"),
+            Err(invalid("--interpret and --vm only work with run"))
+        );
+        assert_eq!(
+            parse("build --native --bytecode prog.tt"),
+            Err(invalid("--native and --bytecode can't be used together"))
+        );
+        assert_eq!(
+            parse("run --bytecode prog.ttc"),
+            Err(invalid("--bytecode only works with build"))
+        );
+        assert_eq!(
+            parse("build --native prog.tt -o -"),
+            Err(invalid("an executable can't be written to stdout"))
+        );
+    }
+
+    #[test]
+    fn test_max_depth() {
+        assert_eq!(parse("run a.tt").unwrap().max_depth, DEFAULT_MAX_DEPTH);
+        assert_eq!(parse("run --max-depth 5 a.tt").unwrap().max_depth, 5);
+        assert_eq!(
+            parse("build a.tt --max-depth=70000").unwrap().max_depth,
+            70000
+        );
+        assert_eq!(
+            parse("run --max-depth=0 a.tt"),
+            Err(invalid("--max-depth needs a whole number above 0, not '0'"))
+        );
+        assert_eq!(
+            parse("run a.tt --max-depth"),
+            Err(invalid("--max-depth needs a number after it"))
+        );
+    }
+
+    #[test]
+    fn test_bad_arguments() {
+        assert_eq!(parse("build prog.tt --help"), Err(ArgsError::Help));
+        assert_eq!(
+            parse("build"),
+            Err(invalid("Compiler needs a source file as an argument"))
+        );
+        assert_eq!(
+            parse("build prog.tt -o"),
+            Err(invalid("-o needs a path after it"))
+        );
+        assert_eq!(
+            parse("build a.tt b.tt"),
+            Err(invalid("unexpected argument 'b.tt'"))
+        );
+        assert_eq!(
+            parse("build --verbose a.tt"),
+            Err(invalid("unknown option '--verbose'"))
+        );
+        assert_eq!(
+            parse("--error-format=xml a.tt"),
+            Err(invalid(
+                "unknown error format 'xml', expected human or json"
+            ))
+        );
+    }
+}
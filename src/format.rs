@@ -0,0 +1,157 @@
+use lexer::tokens::TokenType;
+use lexer::Lexer;
+
+const INDENT: &str = "    ";
+
+/// Lay a program out the same way every time: one space between tokens, none after a
+/// unary sign, inside brackets or before a comma, block bodies indented by four spaces and at most one blank line in a row.
+/// Comments are kept where they were. Works from the tokens, so it expects a program
+/// that has already parsed.
+pub fn format(source: &str) -> String {
+    let mut out = String::new();
+    let mut lexer = Lexer::new(source);
+    let mut depth = 0;
+    // blank lines are only written once we know something comes after them
+    let mut blank = false;
+
+    // the line being built, and the first and last tokens on it
+    let mut line = String::new();
+    let mut first: Option<TokenType> = None;
+    let mut last: Option<TokenType> = None;
+    let mut sign = false;
+    let mut previous_end = 0;
+
+    loop {
+        let Ok(token) = lexer.get_token() else {
+            continue;
+        };
+
+        // the lexer skips comments, so look for one between this token and the last
+        let gap = &source[previous_end..token.span.start];
+        if let Some(hash) = gap.find('#') {
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(gap[hash..].trim_end());
+        }
+        previous_end = token.span.end;
+
+        match token.token_type {
+            TokenType::NEWLINE | TokenType::EOF => {
+                if line.is_empty() {
+                    blank = !out.is_empty();
+                } else {
+                    // ELSEIF and ELSE line up with their IF, the body after them is indented
+                    if matches!(
+                        first,
+                        Some(
+                            TokenType::ENDIF
+                                | TokenType::ENDWHILE
+                                | TokenType::NEXT
+                                | TokenType::ENDFUNCTION
+                                | TokenType::ELSEIF
+                                | TokenType::ELSE
+                        )
+                    ) {
+                        depth = usize::saturating_sub(depth, 1);
+                    }
+                    if blank {
+                        out.push('\n');
+                        blank = false;
+                    }
+                    out.push_str(&INDENT.repeat(depth));
+                    out.push_str(&line);
+                    out.push('\n');
+                    if matches!(
+                        first,
+                        Some(
+                            TokenType::IF
+                                | TokenType::WHILE
+                                | TokenType::FOR
+                                | TokenType::FUNCTION
+                                | TokenType::ELSEIF
+                                | TokenType::ELSE
+                        )
+                    ) {
+                        depth += 1;
+                    }
+                }
+                if token.token_type == TokenType::EOF {
+                    return out;
+                }
+                line.clear();
+                first = None;
+                last = None;
+                sign = false;
+            }
+            token_type => {
+                // a call's brackets stick to its name, and commas to what's before them
+                let tight = sign
+                    || matches!(token_type, TokenType::RPAREN | TokenType::COMMA)
+                    || last == Some(TokenType::LPAREN)
+                    || (token_type == TokenType::LPAREN && last == Some(TokenType::IDENTIFIER));
+                if !line.is_empty() && !tight {
+                    line.push(' ');
+                }
+                // a + or - with no operand to its left is a sign and sticks to what follows
+                sign = matches!(token_type, TokenType::PLUS | TokenType::MINUS)
+                    && !matches!(
+                        last,
+                        Some(
+                            TokenType::NUMBER
+                                | TokenType::STRING
+                                | TokenType::IDENTIFIER
+                                | TokenType::RPAREN
+                        )
+                    );
+                line.push_str(&source[token.span.start..token.span.end]);
+                first = first.or(Some(token_type.clone()));
+                last = Some(token_type);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let source = "\n\n# counts down\nLET  a=10\nWHILE a>0 REPEAT   # loop\nIF a==5 THEN\nPRINT \"half  way\"\nENDIF\n\n\n\nPRINT a*-1\nLET a = a - -1 - 2\nENDWHILE\n\n";
+        let expected = "# counts down\nLET a = 10\nWHILE a > 0 REPEAT # loop\n    IF a == 5 THEN\n        PRINT \"half  way\"\n    ENDIF\n\n    PRINT a * -1\n    LET a = a - -1 - 2\nENDWHILE\n";
+        assert_eq!(format(source), expected);
+        // formatting twice changes nothing
+        assert_eq!(format(expected), expected);
+    }
+
+    #[test]
+    fn test_format_else() {
+        let source = "IF a==1 THEN\nPRINT 1\n  ELSEIF a==2 THEN\nPRINT 2\nELSE\nFOR i=1 TO a STEP -1\nPRINT i\nNEXT\nENDIF\n";
+        let expected = "IF a == 1 THEN\n    PRINT 1\nELSEIF a == 2 THEN\n    PRINT 2\nELSE\n    FOR i = 1 TO a STEP -1\n        PRINT i\n    NEXT\nENDIF\n";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn test_format_functions() {
+        let source =
+            "FUNCTION add ( a,b )\nRETURN a+b\nENDFUNCTION\nPRINT add (1 , -2)*add(3,( 4 ))\n";
+        let expected =
+            "FUNCTION add(a, b)\n    RETURN a + b\nENDFUNCTION\nPRINT add(1, -2) * add(3, (4))\n";
+        assert_eq!(format(source), expected);
+        assert_eq!(format(expected), expected);
+    }
+
+    #[test]
+    fn test_format_signs() {
+        assert_eq!(format("LET a = -+1\nPRINT - a"), "LET a = -+1\nPRINT -a\n");
+        assert_eq!(
+            format("PRINT -( a+1 )^2%( -a )-(1)\n"),
+            "PRINT -(a + 1) ^ 2 % (-a) - (1)\n"
+        );
+        assert_eq!(
+            format("LET a$ = \"x\"+a$+\"y\"\n"),
+            "LET a$ = \"x\" + a$ + \"y\"\n"
+        );
+    }
+}
@@ -1,45 +1,415 @@
+mod cli;
+mod format;
+mod native;
+mod repl;
+
 use cemitter::Emitter;
+use cli::{ArgsError, Command, Engine, Options, USAGE};
+use diagnostics::Diagnostic;
+use interpreter::error::RuntimeError;
+use interpreter::Interpreter;
+use lexer::error::LexError;
+use lexer::tokens::TokenType;
 use lexer::Lexer;
+use parser::ast::Program;
 use parser::Parser;
-use std::env;
+use repl::Repl;
+use std::fs;
+use std::io::{self, BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::{env, process, thread};
+use vm::chunk::Chunk;
+use vm::disassemble::disassemble;
+use vm::{ttc, Vm};
 
-fn main() {
-    println!("Teeny Tiny Transpiler");
+// exit codes, so scripts can tell a broken program from a broken command line
+const EXIT_PROGRAM: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_BUILD: i32 = 4;
+
+// The interpreter goes a few Rust calls deeper for every function call a program makes.
+// This is room for each of them with a few blocks nested inside, debug builds use a lot more
+const STACK_PER_CALL: usize = if cfg!(debug_assertions) {
+    64 * 1024
+} else {
+    16 * 1024
+};
+// what the main thread would normally get
+const MIN_STACK: usize = 8 * 1024 * 1024;
 
+/// Why a command didn't finish. Errors in the program itself have already been reported
+/// against the source by the time one of these comes back, this is the one line summary.
+enum Failure {
+    /// the program has errors, or stopped with one when run
+    Program(String),
+    /// reading the program or writing the output failed
+    Io(String),
+    /// the C compiler is missing or rejected the generated C
+    Build(String),
+    /// the program stopped with an error when run, which has already been reported
+    Stopped,
+}
+
+fn main() {
     // get command line arguments
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        println!("Error: Compiler needs a source file as an argument");
-        return;
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(ArgsError::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    // every error ends up here so the user gets a message instead of a panic. The work is
+    // done on a thread with enough stack for the interpreter to nest calls --max-depth deep
+    let stack = STACK_PER_CALL
+        .saturating_mul(options.max_depth)
+        .max(MIN_STACK);
+    let result = thread::scope(|scope| {
+        let work = thread::Builder::new()
+            .stack_size(stack)
+            .spawn_scoped(scope, || {
+                if options.command == Command::Repl {
+                    start_repl(&options)
+                } else {
+                    compile(&options)
+                }
+            });
+        match work {
+            Ok(work) => work
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+            Err(e) => Err(Failure::Io(format!(
+                "could not make room for --max-depth {}: {}",
+                options.max_depth, e
+            ))),
+        }
+    });
+    match result {
+        Ok(0) => {}
+        // a program we ran exited with this, pass it on
+        Ok(code) => process::exit(code),
+        Err(failure) => fail(&options, failure),
     }
+}
 
-    let input_file = match std::fs::read(args[1].clone()) {
-        Ok(o) => o,
-        Err(e) => panic!("Failed to read file {}", e),
+// report why we stopped and exit with the matching code
+fn fail(options: &Options, failure: Failure) -> ! {
+    let (message, code) = match failure {
+        // the runtime error's diagnostic said it all
+        Failure::Stopped => process::exit(EXIT_PROGRAM),
+        Failure::Program(message) => (message, EXIT_PROGRAM),
+        Failure::Io(message) => (message, EXIT_IO),
+        Failure::Build(message) => (message, EXIT_BUILD),
     };
+    if options.json {
+        let diagnostic = Diagnostic::error(message);
+        eprintln!("{}", diagnostics::to_json(&diagnostic, &options.input));
+    } else {
+        eprintln!("Error: {}", message);
+    }
+    process::exit(code);
+}
 
-    // seemingly cursed?
-    // idk what I meant by the above comment, perhaps im remarking about how amazed I am by
-    // rust's string handling. idk
-    let lexer = Lexer::new(&match String::from_utf8(input_file) {
-        Ok(o) => o,
-        Err(e) => panic!(
-            "Internal error, failed to convert input file to String: {}",
-            e
-        ),
-    });
+/// Do what the options say, returning the exit code to finish with.
+fn compile(options: &Options) -> Result<i32, Failure> {
+    let name = if options.input == "-" {
+        "<stdin>"
+    } else {
+        options.input.as_str()
+    };
+    let input_file = if options.input == "-" {
+        let mut bytes = Vec::new();
+        io::stdin()
+            .read_to_end(&mut bytes)
+            .map(|_| bytes)
+            .map_err(|e| Failure::Io(format!("Failed to read stdin: {}", e)))?
+    } else {
+        fs::read(name).map_err(|e| Failure::Io(format!("Failed to read file {}: {}", name, e)))?
+    };
+
+    // bytecode is told apart by its magic number rather than the file name, so it works
+    // from stdin too
+    let (source, bytecode) = if input_file.starts_with(ttc::MAGIC) {
+        (String::new(), Some(input_file))
+    } else {
+        // seemingly cursed?
+        // idk what I meant by the above comment, perhaps im remarking about how amazed I am
+        // by rust's string handling. idk
+        let source = String::from_utf8(input_file)
+            .map_err(|e| Failure::Io(format!("{} is not valid UTF-8: {}", name, e)))?;
+        (source, None)
+    };
+
+    // show an error against the source, in colour if a person is watching, with a blank
+    // line after it like rustc. Tools get exactly one line each
+    let colour = io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let report = |diagnostic: Diagnostic| {
+        if options.json {
+            eprintln!("{}", diagnostics::to_json(&diagnostic, name));
+        } else {
+            eprintln!(
+                "{}",
+                diagnostics::render(&diagnostic, name, &source, colour)
+            );
+        }
+    };
+    // progress goes to stderr so it never mixes with output sent to stdout
+    let progress = |message: &str| {
+        if !options.quiet && !options.json {
+            eprintln!("{}", message);
+        }
+    };
+
+    // init parser and let it build the program
+    let parse = || -> Result<Program, Failure> {
+        let mut parser = Parser::new(Lexer::new(&source));
+        parser.program().map_err(|errors| {
+            for error in &errors {
+                report(error.into());
+            }
+            Failure::Program(format!(
+                "could not compile, found {} error(s)",
+                errors.len()
+            ))
+        })
+    };
+
+    // the C for a native build, which never touches out.c
+    let generate = |program: &Program| -> String {
+        let mut emitter = Emitter::new(String::new());
+        emitter.max_depth(options.max_depth);
+        if options.line_directives {
+            emitter.line_directives(name);
+        }
+        emitter.program(program);
+        emitter.output()
+    };
+
+    // what running a program on the interpreter or VM came to
+    let finished = |result: Result<(), RuntimeError>| -> Result<i32, Failure> {
+        result.map(|_| 0).map_err(|error| {
+            report(diagnostics::runtime(&error, &source));
+            Failure::Stopped
+        })
+    };
+    let run_vm = |chunk: &Chunk| {
+        let stdout = io::stdout();
+        finished(
+            Vm::new(io::stdin().lock(), BufWriter::new(stdout.lock()))
+                .with_max_depth(options.max_depth)
+                .run(chunk),
+        )
+    };
+    let compile_vm = |program: &Program| -> Result<Chunk, Failure> {
+        vm::compiler::compile(program).map_err(|error| {
+            report((&error).into());
+            Failure::Program("could not compile to bytecode".to_string())
+        })
+    };
+
+    if let Some(bytes) = bytecode {
+        let chunk = ttc::read(&bytes).map_err(|error| {
+            report((&error).into());
+            Failure::Program(format!("could not load {}", name))
+        })?;
+        return match options.command {
+            Command::Run if options.engine == Engine::Interpreter => Err(Failure::Program(
+                "bytecode can only run on the VM, leave out --interpret".to_string(),
+            )),
+            Command::Run => run_vm(&chunk),
+            Command::Disasm => {
+                write_output(options.output.as_deref(), disassemble(&chunk).as_bytes())?;
+                Ok(0)
+            }
+            _ => Err(Failure::Program(format!(
+                "{} is bytecode, it can only be used with run and disasm",
+                name
+            ))),
+        };
+    }
+
+    match options.command {
+        Command::Build => {
+            progress("Teeny Tiny Transpiler");
+            let program = parse()?;
+            progress("Parsing complete");
+
+            if options.native {
+                let output = options
+                    .output
+                    .as_ref()
+                    .map_or_else(|| native::default_output(&options.input), PathBuf::from);
+                if let Err(error) = native::build(
+                    &generate(&program),
+                    &output,
+                    &native::flags(&options.cflags),
+                ) {
+                    report((&error).into());
+                    return Err(Failure::Build("could not build an executable".to_string()));
+                }
+                progress(&format!("Built {}", output.display()));
+                return Ok(0);
+            }
 
-    // lets init an emitter as well
-    let mut emitter = Emitter::new("out.c".to_string());
-    // init parser
-    let mut parser = Parser::new(lexer, &mut emitter);
+            if options.bytecode {
+                let chunk = compile_vm(&program)?;
+                let output = match options.output.as_deref() {
+                    Some(path) => path.to_string(),
+                    None if options.input == "-" => "out.ttc".to_string(),
+                    None => Path::new(&options.input)
+                        .with_extension("ttc")
+                        .display()
+                        .to_string(),
+                };
+                write_output(Some(&output), &ttc::write(&chunk))?;
+                progress(&format!("Wrote {}", output));
+                return Ok(0);
+            }
 
-    // allow parser to parse
-    parser.program();
-    println!("Parsing complete");
-    // allow emitter to emit
-    emitter.write_file();
+            // lets init an emitter as well
+            let path = options.output.as_deref().unwrap_or("out.c");
+            let mut emitter = Emitter::new(path.to_string());
+            emitter.max_depth(options.max_depth);
+            if options.line_directives {
+                emitter.line_directives(name);
+            }
+            // allow emitter to emit
+            emitter.program(&program);
+            if path == "-" {
+                write_output(Some("-"), emitter.output().as_bytes())?;
+            } else if let Err(error) = emitter.write_file() {
+                report((&error).into());
+                return Err(Failure::Io("could not write the C output".to_string()));
+            }
 
-    // allow me fam
-    println!("Compiling completed.");
+            // allow me fam
+            progress("Compiling completed.");
+        }
+        Command::Run => {
+            let program = parse()?;
+            // stdout belongs to the program
+            return match options.engine {
+                Engine::Native => {
+                    let flags = native::flags(&options.cflags);
+                    native::run(&generate(&program), &flags).map_err(|error| {
+                        report((&error).into());
+                        Failure::Build("could not build the program to run it".to_string())
+                    })
+                }
+                Engine::Interpreter => {
+                    let stdout = io::stdout();
+                    finished(
+                        Interpreter::new(io::stdin().lock(), BufWriter::new(stdout.lock()))
+                            .with_max_depth(options.max_depth)
+                            .run(&program),
+                    )
+                }
+                Engine::Vm => run_vm(&compile_vm(&program)?),
+            };
+        }
+        Command::Check => {
+            parse()?;
+            progress(&format!("{}: no errors found", name));
+        }
+        Command::Tokens => {
+            let (listing, errors) = list_tokens(&source);
+            for error in &errors {
+                report(error.into());
+            }
+            write_output(options.output.as_deref(), listing.as_bytes())?;
+            if !errors.is_empty() {
+                return Err(Failure::Program(format!("found {} error(s)", errors.len())));
+            }
+        }
+        Command::Ast => {
+            let program = parse()?;
+            write_output(
+                options.output.as_deref(),
+                format!("{:#?}\n", program).as_bytes(),
+            )?;
+        }
+        Command::Fmt => {
+            // formatting a program that doesn't parse could make things worse
+            parse()?;
+            write_output(
+                options.output.as_deref(),
+                format::format(&source).as_bytes(),
+            )?;
+        }
+        Command::Repl => unreachable!("the REPL doesn't read its input up front"),
+        Command::Disasm => {
+            let chunk = compile_vm(&parse()?)?;
+            write_output(options.output.as_deref(), disassemble(&chunk).as_bytes())?;
+        }
+    }
+    Ok(0)
+}
+
+// run the REPL on the terminal, loading the file first if there is one
+fn start_repl(options: &Options) -> Result<i32, Failure> {
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    let colour = io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let mut repl = Repl::new(
+        stdin.lock(),
+        io::stdout(),
+        io::stderr(),
+        interactive,
+        colour,
+    )
+    .with_max_depth(options.max_depth);
+    let mut session = || -> io::Result<()> {
+        if !options.input.is_empty() {
+            repl.load(&options.input)?;
+        }
+        repl.run()
+    };
+    session().map_err(|e| Failure::Io(format!("REPL failed: {}", e)))?;
+    Ok(0)
+}
+
+/// One line per token with where it starts, plus whatever the lexer couldn't make sense of.
+fn list_tokens(source: &str) -> (String, Vec<LexError>) {
+    let mut lexer = Lexer::new(source);
+    let mut listing = String::new();
+    let mut errors = Vec::new();
+    loop {
+        match lexer.get_token() {
+            Ok(token) => {
+                listing.push_str(&format!(
+                    "{:>4}:{:<4} {:<10} {}\n",
+                    token.span.line,
+                    token.span.column,
+                    format!("{:?}", token.token_type),
+                    token.value.escape_default()
+                ));
+                if token.token_type == TokenType::EOF {
+                    return (listing, errors);
+                }
+            }
+            Err(error) => errors.push(error),
+        }
+    }
+}
+
+// write to the named file, or stdout if there isn't one or it's -
+fn write_output(path: Option<&str>, bytes: &[u8]) -> Result<(), Failure> {
+    match path {
+        None | Some("-") => {
+            let mut stdout = io::stdout().lock();
+            stdout
+                .write_all(bytes)
+                .and_then(|_| stdout.flush())
+                .map_err(|e| Failure::Io(format!("Could not write to stdout: {}", e)))
+        }
+        Some(path) => fs::write(path, bytes)
+            .map_err(|e| Failure::Io(format!("Could not write to file '{}': {}", path, e))),
+    }
 }
@@ -0,0 +1,176 @@
+use cemitter::compiler::CCompiler;
+use cemitter::error::EmitError;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, fs, process};
+
+// always passed to the C compiler, before $CFLAGS and --cflags
+const DEFAULT_FLAGS: [&str; 1] = ["-O2"];
+
+/// A fresh directory under the system temp dir, removed again when dropped.
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    fn new() -> Result<Self, EmitError> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "teeny_tiny_{}_{}",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path).map_err(|error| EmitError::Io {
+            path: path.display().to_string(),
+            error,
+        })?;
+        Ok(TempDir { path })
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        // nothing useful to do if this fails, it's in the temp dir anyway
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Flags for the C compiler: ours, then `$CFLAGS`, then any from the command line.
+pub fn flags(extra: &[String]) -> Vec<String> {
+    let mut flags: Vec<String> = DEFAULT_FLAGS.iter().map(|flag| flag.to_string()).collect();
+    if let Ok(cflags) = env::var("CFLAGS") {
+        flags.extend(cflags.split_whitespace().map(String::from));
+    }
+    for extra in extra {
+        flags.extend(extra.split_whitespace().map(String::from));
+    }
+    flags
+}
+
+/// Compile the generated C into the executable `output`. The C only lives in a temp dir
+/// while the compiler needs it. Any warnings are passed on to stderr.
+pub fn build(c: &str, output: &Path, flags: &[String]) -> Result<(), EmitError> {
+    let compiler = CCompiler::locate()?;
+    let dir = TempDir::new()?;
+    let source = dir.path.join("out.c");
+    fs::write(&source, c).map_err(|error| EmitError::Io {
+        path: source.display().to_string(),
+        error,
+    })?;
+    let warnings = compiler.compile(&source, output, flags)?;
+    eprint!("{}", warnings);
+    Ok(())
+}
+
+/// Build the generated C into a temp dir and run it, with our stdin, stdout and stderr. Returns
+/// the exit code to pass on, which is 128 plus the signal number if it was killed.
+pub fn run(c: &str, flags: &[String]) -> Result<i32, EmitError> {
+    let dir = TempDir::new()?;
+    let executable = dir
+        .path
+        .join("program")
+        .with_extension(env::consts::EXE_EXTENSION);
+    build(c, &executable, flags)?;
+    let status = Command::new(&executable)
+        .status()
+        .map_err(|error| EmitError::Io {
+            path: executable.display().to_string(),
+            error,
+        })?;
+    Ok(status.code().unwrap_or_else(|| signal(&status) + 128))
+}
+
+#[cfg(unix)]
+fn signal(status: &process::ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal().unwrap_or(0)
+}
+
+#[cfg(not(unix))]
+fn signal(_: &process::ExitStatus) -> i32 {
+    0
+}
+
+/// Where `build --native` puts the executable when no `-o` is given: next to the program,
+/// named after it.
+pub fn default_output(input: &str) -> PathBuf {
+    if input == "-" {
+        PathBuf::from("a.out")
+    } else {
+        Path::new(input).with_extension(env::consts::EXE_EXTENSION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cemitter::Emitter;
+    use interpreter::Interpreter;
+    use lexer::Lexer;
+    use parser::Parser;
+    use std::process::Stdio;
+    use vm::Vm;
+
+    // What the program prints built with the C compiler, the interpreter and the VM, or
+    // None when there's no C compiler to try it with.
+    fn run_all(source: &str) -> Option<[String; 3]> {
+        CCompiler::locate().ok()?;
+        let program = Parser::new(Lexer::new(source)).program().unwrap();
+        let mut emitter = Emitter::new(String::new());
+        emitter.program(&program);
+        let dir = TempDir::new().unwrap();
+        let executable = dir
+            .path
+            .join("program")
+            .with_extension(env::consts::EXE_EXTENSION);
+        build(&emitter.output(), &executable, &flags(&[])).unwrap();
+        let native = Command::new(&executable)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+
+        let mut interpreted = Vec::new();
+        Interpreter::new("".as_bytes(), &mut interpreted)
+            .run(&program)
+            .unwrap();
+        let mut vm = Vec::new();
+        Vm::new("".as_bytes(), &mut vm)
+            .run(&vm::compiler::compile(&program).unwrap())
+            .unwrap();
+        Some([native.stdout, interpreted, vm].map(|output| String::from_utf8(output).unwrap()))
+    }
+
+    #[test]
+    fn test_c_library_names() {
+        // variables and parameters named after the C functions the generated C calls
+        let sources = [
+            "LET exit = 1\nLET pow = 2\nLET sqrt = 3\nLET free = 4\nLET printf = 5\n\
+             PRINT exit + pow + sqrt + free + printf\n",
+            "PRINT f(1)\nFUNCTION f(exit)\nRETURN exit + 1\nENDFUNCTION\n",
+            // ^ is pow()
+            "LET pow = 2\nPRINT pow ^ 2\n",
+            // strings are freed with free()
+            "LET free = 1\nLET a$ = \"x\"\nPRINT a$\n",
+            // SQRT is sqrt()
+            "LET sqrt = 16\nPRINT SQRT(sqrt)\n",
+        ];
+        for source in sources {
+            let Some([native, interpreted, vm]) = run_all(source) else {
+                return;
+            };
+            assert_eq!(native, interpreted, "{}", source);
+            assert_eq!(native, vm, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_function_variables_start_at_zero() {
+        // the second call's x is never set, so it mustn't see what the first call left
+        let source = "FUNCTION f(n)\nIF n > 1 THEN\nLET x = 100\nENDIF\nPRINT x + 1\n\
+                      ENDFUNCTION\nLET a = f(2)\nLET a = f(1)\n";
+        if let Some(outputs) = run_all(source) {
+            assert_eq!(outputs, ["101.00\n1.00\n"; 3].map(String::from));
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use crate::format;
+use crate::list_tokens;
+use diagnostics::Diagnostic;
+use interpreter::value::{Array, Value};
+use interpreter::Interpreter;
+use lexer::tokens::TokenType;
+use lexer::Lexer;
+use parser::ast::{Program, Statement, StatementKind, Type, DEFAULT_MAX_DEPTH};
+use parser::Parser;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+Type statements to run them. An IF, WHILE, FOR or FUNCTION waits for its ENDIF, ENDWHILE,
+NEXT or ENDFUNCTION. Labels only reach within what was typed in one go, functions can be
+called from any later entry and typing one in again replaces it.
+
+    :vars            show every variable and its value, and every array's sizes
+    :tokens [code]   show the tokens of the code, or of the last entry
+    :ast [code]      show the syntax tree of the code, or of the last entry
+    :load <file>     run a file as if it were typed in
+    :save <file>     write everything that has run so far to a file
+    :reset           forget every variable, array, function and everything typed
+    :help            show this message
+    :quit            leave, as does Ctrl-D";
+
+/// Reads statements a line at a time and runs them with the interpreter, keeping the
+/// variables around from one entry to the next.
+pub struct Repl<R: BufRead, W: Write, E: Write> {
+    input: R,
+    output: W,
+    // diagnostics, kept apart from what the program prints
+    errors: E,
+    // show prompts, only wanted when a person is typing
+    prompt: bool,
+    colour: bool,
+    variables: HashMap<String, Value>,
+    arrays: HashMap<String, Array>,
+    // what the parser has seen declared, including variables never given a value
+    symbols: HashSet<String>,
+    // every FUNCTION defined so far, the latest of each name
+    functions: Vec<Statement>,
+    // every entry that parsed, for :save and :tokens
+    history: Vec<String>,
+    // how deep GOSUBs and function calls can nest
+    max_depth: usize,
+}
+
+impl<R: BufRead, W: Write, E: Write> Repl<R, W, E> {
+    pub fn new(input: R, output: W, errors: E, prompt: bool, colour: bool) -> Self {
+        Repl {
+            input,
+            output,
+            errors,
+            prompt,
+            colour,
+            variables: HashMap::new(),
+            arrays: HashMap::new(),
+            symbols: HashSet::new(),
+            functions: Vec::new(),
+            history: Vec::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+
+    /// Stop an entry with an error when its GOSUBs or function calls nest deeper than this.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Keep going until the input runs out or the user asks to leave.
+    pub fn run(&mut self) -> io::Result<()> {
+        if self.prompt {
+            writeln!(self.output, "Teeny Tiny REPL, :help for help")?;
+        }
+        let mut entry = String::new();
+        loop {
+            if self.prompt {
+                write!(
+                    self.output,
+                    "{}",
+                    if entry.is_empty() { "> " } else { "... " }
+                )?;
+                self.output.flush()?;
+            }
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                // run whatever was left half typed, the parser will say what's missing
+                if !entry.trim().is_empty() {
+                    self.execute(&entry, "<repl>")?;
+                }
+                return Ok(());
+            }
+            if !line.ends_with('\n') {
+                line.push('\n');
+            }
+
+            if entry.is_empty() {
+                if let Some(command) = line.trim().strip_prefix(':') {
+                    if !self.command(command)? {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                if line.trim().is_empty() {
+                    continue;
+                }
+            }
+            entry.push_str(&line);
+            if open_blocks(&entry) == 0 {
+                self.execute(&std::mem::take(&mut entry), "<repl>")?;
+            }
+        }
+    }
+
+    /// Run a file as if it had been typed in, keeping its variables.
+    pub fn load(&mut self, path: &str) -> io::Result<()> {
+        match fs::read_to_string(path) {
+            Ok(source) => self.execute(&source, path),
+            Err(e) => writeln!(self.errors, "Error: failed to read {}: {}", path, e),
+        }
+    }
+
+    // Run a meta command, without its colon. Returns false when it's time to stop.
+    fn command(&mut self, command: &str) -> io::Result<bool> {
+        let (name, argument) = match command.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (command, ""),
+        };
+        // :tokens and :ast look at the last entry when not given any code
+        let code = if argument.is_empty() {
+            self.history.last().cloned().unwrap_or_default()
+        } else {
+            format!("{}\n", argument)
+        };
+        match name {
+            "vars" => {
+                let mut names: Vec<&String> = self.symbols.iter().collect();
+                names.sort();
+                for name in names {
+                    let value = self
+                        .variables
+                        .get(name)
+                        .cloned()
+                        .unwrap_or_else(|| Value::empty(Type::of_name(name)));
+                    match value {
+                        // quoted so an empty or padded string shows up
+                        Value::String(text) => writeln!(self.output, "{} = {:?}", name, text)?,
+                        number => writeln!(self.output, "{} = {}", name, number)?,
+                    }
+                }
+                // there could be a lot of elements, so just what DIM said
+                let mut arrays: Vec<(&String, &Array)> = self.arrays.iter().collect();
+                arrays.sort_by_key(|(name, _)| *name);
+                for (name, array) in arrays {
+                    let sizes: Vec<String> = array.sizes.iter().map(usize::to_string).collect();
+                    writeln!(self.output, "DIM {}({})", name, sizes.join(", "))?;
+                }
+            }
+            "tokens" => {
+                let (listing, errors) = list_tokens(&code);
+                write!(self.output, "{}", listing)?;
+                for error in &errors {
+                    self.report(error.into(), &code, "<repl>")?;
+                }
+            }
+            "ast" => {
+                if let Some((program, _)) = self.parse(&code, "<repl>")? {
+                    writeln!(self.output, "{:#?}", program)?;
+                }
+            }
+            "load" if !argument.is_empty() => self.load(argument)?,
+            "save" if !argument.is_empty() => {
+                let source = format::format(&self.history.concat());
+                if let Err(e) = fs::write(argument, source) {
+                    writeln!(self.errors, "Error: could not write {}: {}", argument, e)?;
+                }
+            }
+            "load" | "save" => writeln!(self.errors, "Error: :{} needs a file name", name)?,
+            "reset" => {
+                self.variables.clear();
+                self.arrays.clear();
+                self.symbols.clear();
+                self.functions.clear();
+                self.history.clear();
+            }
+            "help" => writeln!(self.output, "{}", HELP)?,
+            "quit" | "q" => return Ok(false),
+            _ => writeln!(self.errors, "Error: unknown command ':{}', try :help", name)?,
+        }
+        Ok(true)
+    }
+
+    // parse with everything declared so far, reporting any errors
+    fn parse(&mut self, source: &str, name: &str) -> io::Result<Option<(Program, Parser)>> {
+        let mut parser = Parser::new(Lexer::new(source));
+        for symbol in &self.symbols {
+            parser.declare(symbol);
+        }
+        for statement in &self.functions {
+            if let StatementKind::Function(function) = &statement.kind {
+                parser.declare_function(&function.name, &function.params);
+            }
+        }
+        for (name, array) in &self.arrays {
+            parser.declare_array(name, array.sizes.len());
+        }
+        match parser.program() {
+            Ok(program) => Ok(Some((program, parser))),
+            Err(errors) => {
+                for error in &errors {
+                    self.report(error.into(), source, name)?;
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    // parse and run an entry. One that doesn't parse is forgotten entirely
+    fn execute(&mut self, source: &str, name: &str) -> io::Result<()> {
+        let Some((program, parser)) = self.parse(source, name)? else {
+            return Ok(());
+        };
+        self.symbols = parser.symbols().clone();
+        self.history.push(source.to_string());
+        // functions are kept even if the entry stops with an error, as they were defined
+        for statement in &program.statements {
+            if let StatementKind::Function(function) = &statement.kind {
+                self.functions.retain(|old| {
+                    !matches!(&old.kind, StatementKind::Function(old) if old.name == function.name)
+                });
+                self.functions.push(statement.clone());
+            }
+        }
+
+        let mut interpreter = Interpreter::new(&mut self.input, &mut self.output)
+            .with_variables(std::mem::take(&mut self.variables))
+            .with_arrays(std::mem::take(&mut self.arrays))
+            .with_functions(&self.functions)
+            .with_max_depth(self.max_depth);
+        let result = interpreter.run(&program);
+        // the variables set before a runtime error still count
+        (self.variables, self.arrays) = interpreter.into_variables();
+        if let Err(error) = result {
+            self.report(diagnostics::runtime(&error, source), source, name)?;
+        }
+        Ok(())
+    }
+
+    fn report(&mut self, diagnostic: Diagnostic, source: &str, name: &str) -> io::Result<()> {
+        let text = diagnostics::render(&diagnostic, name, source, self.colour);
+        writeln!(self.errors, "{}", text)
+    }
+}
+
+// How many IFs, WHILEs, FORs and FUNCTIONs are still waiting for their end keyword.
+fn open_blocks(source: &str) -> usize {
+    let mut lexer = Lexer::new(source);
+    let mut depth: usize = 0;
+    loop {
+        // anything the lexer doesn't like is left for the parser to report
+        let Ok(token) = lexer.get_token() else {
+            continue;
+        };
+        match token.token_type {
+            TokenType::IF | TokenType::WHILE | TokenType::FOR | TokenType::FUNCTION => depth += 1,
+            TokenType::ENDIF | TokenType::ENDWHILE | TokenType::NEXT | TokenType::ENDFUNCTION => {
+                depth = depth.saturating_sub(1)
+            }
+            TokenType::EOF => return depth,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // feed the REPL some lines and return what it printed and what it complained about
+    fn session(lines: &str) -> (String, String) {
+        let (mut output, mut errors) = (Vec::new(), Vec::new());
+        Repl::new(lines.as_bytes(), &mut output, &mut errors, false, false)
+            .run()
+            .unwrap();
+        (
+            String::from_utf8(output).unwrap(),
+            String::from_utf8(errors).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_variables_persist() {
+        let (output, errors) = session("LET a = 2\nLET b = a * 3\nPRINT a + b\n:vars\n");
+        assert_eq!(output, "8.00\na = 2.00\nb = 6.00\n");
+        assert_eq!(errors, "");
+    }
+
+    #[test]
+    fn test_strings() {
+        let (output, errors) = session(
+            "INPUT name$\n  Ann Smith\nLET hi$ = \"Hi \" + name$\nPRINT hi$\nLET a = 1\n:vars\n",
+        );
+        assert_eq!(
+            output,
+            "Hi Ann Smith\na = 1.00\nhi$ = \"Hi Ann Smith\"\nname$ = \"Ann Smith\"\n"
+        );
+        assert_eq!(errors, "");
+    }
+
+    #[test]
+    fn test_arrays() {
+        let (output, errors) = session(
+            "DIM a(3)\nLET a(1) = 5\nPRINT a(1) + a(0)\nDIM b$(2, 2)\n:vars\nLET a(3) = 1\nDIM a(2)\nPRINT a(1)\n",
+        );
+        assert_eq!(output, "5.00\nDIM a(3)\nDIM b$(2, 2)\n0.00\n");
+        assert!(errors.contains("Index out of bounds for array a(3) at line 1"));
+    }
+
+    #[test]
+    fn test_blocks() {
+        let (output, _) = session(
+            "LET i = 0\nWHILE i < 2 REPEAT\nIF i == 1 THEN\nPRINT \"one\"\nENDIF\nLET i = i + 1\nENDWHILE\nPRINT i\nFOR j = 1 TO 2\nPRINT j\nNEXT\n",
+        );
+        assert_eq!(output, "one\n2.00\n1.00\n2.00\n");
+    }
+
+    #[test]
+    fn test_functions() {
+        let (output, errors) = session(
+            "FUNCTION twice(x)\nRETURN x * 2\nENDFUNCTION\nPRINT twice(2)\nFUNCTION twice(x)\nRETURN x * 3\nENDFUNCTION\nPRINT twice(2)\n:reset\nPRINT twice(2)\n",
+        );
+        assert_eq!(output, "4.00\n6.00\n");
+        assert!(errors.contains("error[E0204]"));
+    }
+
+    #[test]
+    fn test_input() {
+        // INPUT reads from the same place as the statements
+        let (output, _) = session("INPUT a\n4.5\nPRINT a\n");
+        assert_eq!(output, "4.50\n");
+    }
+
+    #[test]
+    fn test_errors() {
+        // a bad entry is reported and forgotten, the session carries on
+        let (output, errors) = session("PRINT b\nLET b = 1 / 0\nPRINT b\n:reset\nPRINT b\n:nope\n");
+        assert_eq!(output, "0.00\n");
+        assert_eq!(errors.matches("error[E0201]").count(), 2);
+        assert!(errors.contains("error[E0401]"));
+        assert!(errors.contains("unknown command ':nope'"));
+    }
+
+    #[test]
+    fn test_tokens_and_ast() {
+        let (output, _) = session("LET a = 1\n:tokens\n:ast PRINT a\n");
+        assert!(output.starts_with("   1:1    LET        LET\n"));
+        assert!(output.contains("PrintExpr(\n"));
+        assert!(output.contains("Variable(\n"));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("repl_test_{}.tt", std::process::id()));
+        let path = path.display();
+        let (_, errors) = session(&format!(
+            "LET a = 1\nIF a == 1 THEN\nPRINT \"yes\"\nENDIF\n:save {}\n",
+            path
+        ));
+        assert_eq!(errors, "");
+        let (output, _) = session(&format!(":load {}\nPRINT a\n", path));
+        std::fs::remove_file(path.to_string()).unwrap();
+        assert_eq!(output, "yes\n1.00\n");
+    }
+}
@@ -0,0 +1,159 @@
+use crate::error::CompileError;
+use interpreter::value::Number;
+use parser::ast::{BinaryOp, Builtin, ComparisonOp, UnaryOp};
+
+/// One VM instruction. Operands index into the tables of the [`Chunk`] they belong to,
+/// jump targets are instruction indices.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Instruction {
+    /// push `numbers[i]`
+    Number(u16),
+    /// push `strings[i]`
+    String(u16),
+    /// push the variable in slot `i`
+    Load(u16),
+    /// pop into the variable in slot `i`, a number as a float
+    Store(u16),
+    /// pop an index for each of the sizes of `arrays[i]`, the last one first, and push
+    /// that element
+    LoadElement(u16),
+    /// pop a value then the indices, like LoadElement, and put the value in that element
+    StoreElement(u16),
+    /// pop one value and push the result of the operator
+    Unary(UnaryOp),
+    /// pop the right hand side then the left and push the result. ADD joins two strings
+    Binary(BinaryOp),
+    /// like Binary, the result is an int 0 or 1 as in C
+    Compare(ComparisonOp),
+    /// like Compare, but leaves the right hand side under the result for the next
+    /// comparison in a chain
+    CompareChain(ComparisonOp),
+    /// throw away the top value
+    Pop,
+    Jump(u32),
+    /// pop a value and jump if it is zero
+    JumpIfFalse(u32),
+    /// jump, remembering the next instruction for RETURN. The stack must be empty
+    Gosub(u32),
+    /// go back to just after the last GOSUB that hasn't returned
+    Return,
+    /// pop the arguments and call `functions[i]` with them
+    Call(u16),
+    /// pop the arguments and push what the built in function gives back
+    Builtin(Builtin),
+    /// pop a value and go back to the caller, pushing it there (a number as a float)
+    ReturnValue,
+    /// print `strings[i]` and a newline
+    PrintString(u16),
+    /// pop a value and print it as a float, like `printf("%.2f\n")`
+    PrintNumber,
+    /// pop a string and print it and a newline
+    PrintText,
+    /// read into slot `i`: a float the way `scanf("%f")` does, or for a string variable
+    /// the next line that isn't blank
+    Input(u16),
+    Halt,
+}
+
+/// A compiled program: the code plus the tables it refers to. Variables and functions whose
+/// names end in `$` hold strings, like in the source.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Instruction>,
+    /// the .tt line each instruction came from
+    pub lines: Vec<u32>,
+    pub numbers: Vec<Number>,
+    pub strings: Vec<String>,
+    /// variable names, by slot, outside any function
+    pub variables: Vec<String>,
+    /// the arrays outside any function
+    pub arrays: Vec<Dim>,
+    pub functions: Vec<Function>,
+    /// every LABEL and the instruction it marks, in source order
+    pub labels: Vec<(String, u32)>,
+}
+
+/// A FUNCTION's code. Inside it LOAD, STORE and INPUT use its own variables rather than
+/// the chunk's.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Function {
+    pub name: String,
+    /// the instruction it starts at
+    pub start: u32,
+    /// the .tt line of the FUNCTION, for errors
+    pub line: u32,
+    /// how many arguments it takes, they go in the first slots
+    pub params: u16,
+    /// variable names, by slot, parameters first
+    pub variables: Vec<String>,
+    /// its own arrays, made afresh for every call
+    pub arrays: Vec<Dim>,
+}
+
+/// An array a DIM declares. It's made when the code it belongs to starts running, every
+/// element 0 or "" depending on its name.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Dim {
+    pub name: String,
+    pub sizes: Vec<usize>,
+}
+
+impl Function {
+    /// The slot for one of the function's variables, giving it one if it hasn't got one yet.
+    pub fn variable(&mut self, name: &str) -> Result<u16, CompileError> {
+        index(&mut self.variables, name.to_string(), "variables")
+    }
+}
+
+impl Chunk {
+    /// Add an instruction, returning its index.
+    pub fn push(&mut self, instruction: Instruction, line: u32) -> u32 {
+        self.code.push(instruction);
+        self.lines.push(line);
+        (self.code.len() - 1) as u32
+    }
+
+    /// Where the next instruction will go.
+    pub fn next(&self) -> u32 {
+        self.code.len() as u32
+    }
+
+    /// Point the jump at `index` to `target`, once we know where that is.
+    pub fn patch(&mut self, index: u32, target: u32) {
+        match &mut self.code[index as usize] {
+            Instruction::Jump(to) | Instruction::JumpIfFalse(to) | Instruction::Gosub(to) => {
+                *to = target
+            }
+            instruction => panic!("can't patch {:?}, it isn't a jump", instruction),
+        }
+    }
+
+    pub fn number(&mut self, number: Number) -> Result<u16, CompileError> {
+        index(&mut self.numbers, number, "numbers")
+    }
+
+    pub fn string(&mut self, text: &str) -> Result<u16, CompileError> {
+        index(&mut self.strings, text.to_string(), "strings")
+    }
+
+    /// The slot for a variable, giving it one if it hasn't got one yet.
+    pub fn variable(&mut self, name: &str) -> Result<u16, CompileError> {
+        index(&mut self.variables, name.to_string(), "variables")
+    }
+}
+
+// where `item` is in a table, adding it if it isn't there yet
+fn index<T: PartialEq>(
+    table: &mut Vec<T>,
+    item: T,
+    what: &'static str,
+) -> Result<u16, CompileError> {
+    let index = match table.iter().position(|existing| *existing == item) {
+        Some(index) => index,
+        None => {
+            table.push(item);
+            table.len() - 1
+        }
+    };
+    u16::try_from(index).map_err(|_| CompileError::TooLarge { what })
+}
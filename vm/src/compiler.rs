@@ -0,0 +1,521 @@
+use crate::chunk::{self, Chunk, Instruction};
+use crate::error::CompileError;
+use interpreter::value::Number;
+use parser::ast::{
+    self, BinaryOp, Comparison, ComparisonOp, Condition, Expr, Program, Statement, StatementKind,
+    Type,
+};
+use std::collections::HashMap;
+
+/// Compile a parsed program to bytecode. GOTOs become plain jumps, so like the C they can
+/// land in the middle of a loop or IF. FUNCTIONs go after the HALT.
+pub fn compile(program: &Program) -> Result<Chunk, CompileError> {
+    let mut compiler = Compiler::default();
+
+    // number every FUNCTION first, so they can be called before they're declared
+    let mut functions = Vec::new();
+    for statement in &program.statements {
+        if let StatementKind::Function(function) = &statement.kind {
+            let index = u16::try_from(functions.len())
+                .map_err(|_| CompileError::TooLarge { what: "functions" })?;
+            compiler.functions.insert(function.name.clone(), index);
+            let mut compiled = chunk::Function {
+                name: function.name.clone(),
+                start: 0,
+                line: statement.span.line as u32,
+                params: 0,
+                variables: Vec::new(),
+                arrays: dims(&function.body),
+            };
+            for param in &function.params {
+                compiled.variable(param)?;
+                compiled.params += 1;
+            }
+            compiler.chunk.functions.push(compiled);
+            functions.push((function, statement.span.line as u32));
+        }
+    }
+
+    compiler.chunk.arrays = dims(&program.statements);
+    compiler.block(&program.statements)?;
+    let end = compiler.line;
+    compiler.chunk.push(Instruction::Halt, end);
+    compiler.patch_gotos(0);
+
+    for (index, (function, line)) in functions.into_iter().enumerate() {
+        compiler.function = Some(index);
+        compiler.chunk.functions[index].start = compiler.chunk.next();
+        let labels = compiler.chunk.labels.len();
+        compiler.block(&function.body)?;
+        // running off the end gives back 0 or ""
+        compiler.line = line;
+        match Type::of_name(&function.name) {
+            Type::Number => {
+                let zero = compiler.chunk.number(Number::Int(0))?;
+                compiler.emit(Instruction::Number(zero));
+            }
+            Type::String => {
+                let empty = compiler.chunk.string("")?;
+                compiler.emit(Instruction::String(empty));
+            }
+        }
+        compiler.emit(Instruction::ReturnValue);
+        compiler.patch_gotos(labels);
+    }
+    Ok(compiler.chunk)
+}
+
+// the arrays the DIMs in a block declare, which get numbered in this order
+fn dims(block: &[Statement]) -> Vec<chunk::Dim> {
+    ast::arrays(block)
+        .into_iter()
+        .map(|(name, sizes)| chunk::Dim {
+            name: name.to_string(),
+            sizes: sizes.to_vec(),
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Compiler {
+    chunk: Chunk,
+    // GOTOs and GOSUBs waiting for their label's address
+    gotos: Vec<(u32, String)>,
+    // the line of the statement being compiled
+    line: u32,
+    // the loops we're inside, innermost last
+    loops: Vec<Loop>,
+    // the number of each FUNCTION
+    functions: HashMap<String, u16>,
+    // the FUNCTION being compiled, if any
+    function: Option<usize>,
+}
+
+// The BREAKs and CONTINUEs in a loop, waiting for it to be compiled far enough to know
+// where they go.
+#[derive(Default)]
+struct Loop {
+    breaks: Vec<u32>,
+    continues: Vec<u32>,
+}
+
+impl Compiler {
+    fn block(&mut self, block: &[Statement]) -> Result<(), CompileError> {
+        for statement in block {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> u32 {
+        self.chunk.push(instruction, self.line)
+    }
+
+    // the slot for a variable of the FUNCTION being compiled, or outside any
+    fn variable(&mut self, name: &str) -> Result<u16, CompileError> {
+        match self.function {
+            Some(index) => self.chunk.functions[index].variable(name),
+            None => self.chunk.variable(name),
+        }
+    }
+
+    // the number of an array of the FUNCTION being compiled, or outside any. The parser
+    // has made sure it's declared
+    fn array(&self, name: &str) -> Result<u16, CompileError> {
+        let arrays = match self.function {
+            Some(index) => &self.chunk.functions[index].arrays,
+            None => &self.chunk.arrays,
+        };
+        let index = arrays.iter().position(|dim| dim.name == name).unwrap();
+        u16::try_from(index).map_err(|_| CompileError::TooLarge { what: "arrays" })
+    }
+
+    // Every label since `first` is known now, point the GOTOs at them. Labels don't reach
+    // in or out of a FUNCTION, and the parser has made sure they all exist.
+    fn patch_gotos(&mut self, first: usize) {
+        let labels: HashMap<String, u32> = self.chunk.labels[first..].iter().cloned().collect();
+        for (index, label) in std::mem::take(&mut self.gotos) {
+            self.chunk.patch(index, labels[&label]);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), CompileError> {
+        self.line = statement.span.line as u32;
+        match &statement.kind {
+            StatementKind::PrintString(text) => {
+                let index = self.chunk.string(text)?;
+                self.emit(Instruction::PrintString(index));
+            }
+            StatementKind::PrintExpr(expr) => {
+                self.expression(expr)?;
+                self.emit(match expr.ty() {
+                    Type::Number => Instruction::PrintNumber,
+                    Type::String => Instruction::PrintText,
+                });
+            }
+            StatementKind::If {
+                branches,
+                else_body,
+            } => {
+                // every arm but the last jumps past the rest once its body is done
+                let mut ends = Vec::new();
+                for (index, (condition, body)) in branches.iter().enumerate() {
+                    // an ELSEIF's condition belongs to the IF, not the end of the arm before
+                    self.line = statement.span.line as u32;
+                    let skip = self.condition(condition)?;
+                    self.block(body)?;
+                    if index + 1 < branches.len() || else_body.is_some() {
+                        ends.push(self.emit(Instruction::Jump(0)));
+                    }
+                    self.patch_all(&skip, self.chunk.next());
+                }
+                if let Some(body) = else_body {
+                    self.block(body)?;
+                }
+                self.patch_all(&ends, self.chunk.next());
+            }
+            StatementKind::While { condition, body } => {
+                let top = self.chunk.next();
+                let exit = self.condition(condition)?;
+                let body = self.loop_body(body)?;
+                self.line = statement.span.line as u32;
+                self.emit(Instruction::Jump(top));
+                self.patch_all(&exit, self.chunk.next());
+                self.patch_all(&body.continues, top);
+                self.patch_all(&body.breaks, self.chunk.next());
+            }
+            StatementKind::For {
+                variable,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                let slot = self.variable(variable)?;
+                self.expression(start)?;
+                self.emit(Instruction::Store(slot));
+
+                // which way to compare depends on the sign of the step, when there is one
+                let top = self.chunk.next();
+                let mut exits = Vec::new();
+                if let Some(step) = step {
+                    self.expression(step)?;
+                    let zero = self.chunk.number(Number::Int(0))?;
+                    self.emit(Instruction::Number(zero));
+                    self.emit(Instruction::Compare(ComparisonOp::GtEq));
+                    let down = self.emit(Instruction::JumpIfFalse(0));
+                    exits.push(self.past_end(slot, end, ComparisonOp::LtEq)?);
+                    let run = self.emit(Instruction::Jump(0));
+                    self.chunk.patch(down, self.chunk.next());
+                    exits.push(self.past_end(slot, end, ComparisonOp::GtEq)?);
+                    self.chunk.patch(run, self.chunk.next());
+                } else {
+                    exits.push(self.past_end(slot, end, ComparisonOp::LtEq)?);
+                }
+
+                let body = self.loop_body(body)?;
+                self.line = statement.span.line as u32;
+                self.patch_all(&body.continues, self.chunk.next());
+                self.emit(Instruction::Load(slot));
+                match step {
+                    Some(step) => self.expression(step)?,
+                    None => {
+                        let one = self.chunk.number(Number::Int(1))?;
+                        self.emit(Instruction::Number(one));
+                    }
+                }
+                self.emit(Instruction::Binary(BinaryOp::Add));
+                self.emit(Instruction::Store(slot));
+                self.emit(Instruction::Jump(top));
+                self.patch_all(&exits, self.chunk.next());
+                self.patch_all(&body.breaks, self.chunk.next());
+            }
+            StatementKind::Break => {
+                let jump = self.emit(Instruction::Jump(0));
+                self.innermost_loop().breaks.push(jump);
+            }
+            StatementKind::Continue => {
+                let jump = self.emit(Instruction::Jump(0));
+                self.innermost_loop().continues.push(jump);
+            }
+            StatementKind::Label(name) => {
+                let target = self.chunk.next();
+                self.chunk.labels.push((name.clone(), target));
+            }
+            StatementKind::Goto(name) => {
+                let jump = self.emit(Instruction::Jump(0));
+                self.gotos.push((jump, name.clone()));
+            }
+            StatementKind::Gosub(name) => {
+                let jump = self.emit(Instruction::Gosub(0));
+                self.gotos.push((jump, name.clone()));
+            }
+            StatementKind::Return => {
+                self.emit(Instruction::Return);
+            }
+            // compiled after the HALT by compile()
+            StatementKind::Function(_) => {}
+            StatementKind::ReturnValue(value) => {
+                self.expression(value)?;
+                self.emit(Instruction::ReturnValue);
+            }
+            StatementKind::Let { name, value } => {
+                self.expression(value)?;
+                let slot = self.variable(name)?;
+                self.emit(Instruction::Store(slot));
+            }
+            StatementKind::LetElement {
+                name,
+                indices,
+                value,
+            } => {
+                for index in indices {
+                    self.expression(index)?;
+                }
+                self.expression(value)?;
+                let array = self.array(name)?;
+                self.emit(Instruction::StoreElement(array));
+            }
+            // the array is made when the code it's in starts running
+            StatementKind::Dim { .. } => {}
+            StatementKind::Input(name) => {
+                let slot = self.variable(name)?;
+                self.emit(Instruction::Input(slot));
+            }
+        }
+        Ok(())
+    }
+
+    // A condition becomes jumps rather than a value: it falls through when true, and hands
+    // back the jumps to patch with where to go when false. That's all AND, OR and NOT need
+    // to short circuit.
+    fn condition(&mut self, condition: &Condition) -> Result<Vec<u32>, CompileError> {
+        Ok(match condition {
+            Condition::Comparison(comparison) => self.comparison(comparison)?,
+            Condition::Not(operand) => {
+                let operand_false = self.condition(operand)?;
+                // the operand being true means this is false
+                let jump = self.emit(Instruction::Jump(0));
+                self.patch_all(&operand_false, self.chunk.next());
+                vec![jump]
+            }
+            Condition::And(lhs, rhs) => {
+                let mut exits = self.condition(lhs)?;
+                exits.extend(self.condition(rhs)?);
+                exits
+            }
+            Condition::Or(lhs, rhs) => {
+                let lhs_false = self.condition(lhs)?;
+                // true already, skip the right hand side
+                let jump = self.emit(Instruction::Jump(0));
+                self.patch_all(&lhs_false, self.chunk.next());
+                let exits = self.condition(rhs)?;
+                self.chunk.patch(jump, self.chunk.next());
+                exits
+            }
+        })
+    }
+
+    // compile the body of a WHILE or FOR, handing back its BREAKs and CONTINUEs
+    fn loop_body(&mut self, body: &[Statement]) -> Result<Loop, CompileError> {
+        self.loops.push(Loop::default());
+        self.block(body)?;
+        Ok(self.loops.pop().unwrap())
+    }
+
+    // the parser makes sure BREAK and CONTINUE are only used inside a loop
+    fn innermost_loop(&mut self) -> &mut Loop {
+        self.loops
+            .last_mut()
+            .expect("BREAK or CONTINUE outside a loop")
+    }
+
+    // check a FOR's variable against its end, handing back the jump out of the loop for
+    // when it's gone past
+    fn past_end(&mut self, slot: u16, end: &Expr, op: ComparisonOp) -> Result<u32, CompileError> {
+        self.emit(Instruction::Load(slot));
+        self.expression(end)?;
+        self.emit(Instruction::Compare(op));
+        Ok(self.emit(Instruction::JumpIfFalse(0)))
+    }
+
+    fn patch_all(&mut self, jumps: &[u32], target: u32) {
+        for jump in jumps {
+            self.chunk.patch(*jump, target);
+        }
+    }
+
+    // `a < b < c` is `a < b AND b < c`, with b worked out once. Every comparison but the
+    // last leaves its right hand side on the stack for the next one, so a false one has to
+    // jump out through a POP to leave the stack as it found it.
+    fn comparison(&mut self, comparison: &Comparison) -> Result<Vec<u32>, CompileError> {
+        self.expression(&comparison.first)?;
+        let Some(((last_op, last), chain)) = comparison.rest.split_last() else {
+            unreachable!("the parser makes sure a comparison has an operator");
+        };
+        let mut chained = Vec::new();
+        for (op, expr) in chain {
+            self.expression(expr)?;
+            self.emit(Instruction::CompareChain(*op));
+            chained.push(self.emit(Instruction::JumpIfFalse(0)));
+        }
+        self.expression(last)?;
+        self.emit(Instruction::Compare(*last_op));
+        let mut exits = vec![self.emit(Instruction::JumpIfFalse(0))];
+        if !chained.is_empty() {
+            let skip = self.emit(Instruction::Jump(0));
+            self.patch_all(&chained, self.chunk.next());
+            self.emit(Instruction::Pop);
+            exits.push(self.emit(Instruction::Jump(0)));
+            self.chunk.patch(skip, self.chunk.next());
+        }
+        Ok(exits)
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<(), CompileError> {
+        match expr {
+            Expr::Number(text) => {
+                let index = self.chunk.number(Number::from_literal(text))?;
+                self.emit(Instruction::Number(index));
+            }
+            Expr::String(text) => {
+                let index = self.chunk.string(text)?;
+                self.emit(Instruction::String(index));
+            }
+            Expr::Variable(name) => {
+                let slot = self.variable(name)?;
+                self.emit(Instruction::Load(slot));
+            }
+            Expr::Unary(op, operand) => {
+                self.expression(operand)?;
+                self.emit(Instruction::Unary(*op));
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                self.expression(lhs)?;
+                self.expression(rhs)?;
+                self.emit(Instruction::Binary(*op));
+            }
+            Expr::Call(name, args) => {
+                for arg in args {
+                    self.expression(arg)?;
+                }
+                self.emit(Instruction::Call(self.functions[name]));
+            }
+            Expr::Builtin(builtin, args) => {
+                for arg in args {
+                    self.expression(arg)?;
+                }
+                self.emit(Instruction::Builtin(*builtin));
+            }
+            Expr::Index(name, indices) => {
+                for index in indices {
+                    self.expression(index)?;
+                }
+                let array = self.array(name)?;
+                self.emit(Instruction::LoadElement(array));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn compile_source(source: &str) -> Chunk {
+        compile(&Parser::new(Lexer::new(source)).program().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_compile_loop() {
+        let chunk = compile_source("LET a = 2\nWHILE a > 0 REPEAT\nLET a = a - 1\nENDWHILE\n");
+        assert_eq!(
+            chunk.code,
+            vec![
+                Instruction::Number(0),
+                Instruction::Store(0),
+                Instruction::Load(0),
+                Instruction::Number(1),
+                Instruction::Compare(ComparisonOp::Gt),
+                Instruction::JumpIfFalse(11),
+                Instruction::Load(0),
+                Instruction::Number(2),
+                Instruction::Binary(BinaryOp::Sub),
+                Instruction::Store(0),
+                Instruction::Jump(2),
+                Instruction::Halt,
+            ]
+        );
+        assert_eq!(chunk.lines, vec![1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 2, 2]);
+        assert_eq!(
+            chunk.numbers,
+            vec![Number::Int(2), Number::Int(0), Number::Int(1)]
+        );
+    }
+
+    #[test]
+    fn test_compile_goto() {
+        let chunk = compile_source("GOTO end\nPRINT \"skipped\"\nLABEL end\n");
+        assert_eq!(
+            chunk.code,
+            vec![
+                Instruction::Jump(2),
+                Instruction::PrintString(0),
+                Instruction::Halt
+            ]
+        );
+        assert_eq!(chunk.labels, vec![("end".to_string(), 2)]);
+    }
+
+    #[test]
+    fn test_compile_logical_operators() {
+        let chunk = compile_source("IF NOT 1 > 2 OR 3 > 4 THEN\nPRINT \"yes\"\nENDIF\n");
+        assert_eq!(
+            chunk.code,
+            vec![
+                Instruction::Number(0),
+                Instruction::Number(1),
+                Instruction::Compare(ComparisonOp::Gt),
+                // 1 > 2 being false makes the NOT true
+                Instruction::JumpIfFalse(5),
+                // otherwise the NOT is false, try the other side of the OR
+                Instruction::Jump(6),
+                // the NOT was true, so the OR is
+                Instruction::Jump(10),
+                Instruction::Number(2),
+                Instruction::Number(3),
+                Instruction::Compare(ComparisonOp::Gt),
+                Instruction::JumpIfFalse(11),
+                Instruction::PrintString(0),
+                Instruction::Halt,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_comparison_chain() {
+        // 1 == 2 AND 2 < 3
+        let chunk = compile_source("IF 1 == 2 < 3 THEN\nPRINT \"yes\"\nENDIF\n");
+        assert_eq!(
+            chunk.code,
+            vec![
+                Instruction::Number(0),
+                Instruction::Number(1),
+                Instruction::CompareChain(ComparisonOp::Eq),
+                Instruction::JumpIfFalse(8),
+                Instruction::Number(2),
+                Instruction::Compare(ComparisonOp::Lt),
+                Instruction::JumpIfFalse(11),
+                Instruction::Jump(10),
+                // 2 is still on the stack when 1 == 2 is false
+                Instruction::Pop,
+                Instruction::Jump(11),
+                Instruction::PrintString(0),
+                Instruction::Halt,
+            ]
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use crate::chunk::{Chunk, Dim, Function, Instruction};
+use interpreter::value::Number;
+use std::fmt::Write;
+
+/// A readable listing of a chunk: its tables, then each instruction with the .tt line it
+/// came from, labels and functions where they start and operands resolved to what they
+/// refer to.
+///
+/// ```text
+///  line  addr  instruction
+///     1  0000  NUMBER        0  ; 10
+///        0001  STORE         0  ; a
+/// loop:
+///     2  0002  LOAD          0  ; a
+/// ```
+pub fn disassemble(chunk: &Chunk) -> String {
+    let mut out = String::new();
+
+    out.push_str("numbers:\n");
+    for (index, number) in chunk.numbers.iter().enumerate() {
+        let _ = writeln!(out, "{:>6}  {}", index, number_text(number));
+    }
+    out.push_str("strings:\n");
+    for (index, text) in chunk.strings.iter().enumerate() {
+        let _ = writeln!(out, "{:>6}  {:?}", index, text);
+    }
+    out.push_str("variables:\n");
+    for (index, name) in chunk.variables.iter().enumerate() {
+        let _ = writeln!(out, "{:>6}  {}", index, name);
+    }
+    out.push_str("arrays:\n");
+    for (index, dim) in chunk.arrays.iter().enumerate() {
+        let _ = writeln!(out, "{:>6}  {}", index, dim_text(dim));
+    }
+
+    out.push_str("functions:\n");
+    for (index, function) in chunk.functions.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "{:>6}  {} at {:04}",
+            index,
+            signature(function),
+            function.start
+        );
+        for (slot, name) in function.variables.iter().enumerate() {
+            let _ = writeln!(out, "{:>10}  {}", slot, name);
+        }
+        // its arrays are numbered apart from its variables, the sizes tell them apart
+        for (index, dim) in function.arrays.iter().enumerate() {
+            let _ = writeln!(out, "{:>10}  {}", index, dim_text(dim));
+        }
+    }
+
+    out.push_str("code:\n line  addr  instruction\n");
+    for (index, instruction) in chunk.code.iter().enumerate() {
+        for function in chunk.functions.iter().filter(|f| f.start as usize == index) {
+            let _ = writeln!(out, "{}:", signature(function));
+        }
+        for (name, _) in chunk.labels.iter().filter(|(_, at)| *at as usize == index) {
+            let _ = writeln!(out, "{}:", name);
+        }
+        let line = chunk.lines.get(index).copied().unwrap_or(0);
+        // only show the line when it changes, like a listing file
+        let line = if index > 0 && chunk.lines.get(index - 1) == Some(&line) {
+            String::new()
+        } else {
+            line.to_string()
+        };
+        let _ = writeln!(
+            out,
+            "{:>5}  {:04}  {}",
+            line,
+            index,
+            instruction_text(chunk, scope_at(chunk, index), instruction)
+        );
+    }
+    out
+}
+
+// `name(a, b)`
+fn signature(function: &Function) -> String {
+    let params = function.variables.iter().take(function.params as usize);
+    format!(
+        "{}({})",
+        function.name,
+        params.cloned().collect::<Vec<_>>().join(", ")
+    )
+}
+
+// `grid(2, 3)`
+fn dim_text(dim: &Dim) -> String {
+    let sizes: Vec<String> = dim.sizes.iter().map(usize::to_string).collect();
+    format!("{}({})", dim.name, sizes.join(", "))
+}
+
+// the variables and arrays instructions use at an address: those of the function it's
+// in, which is the last to start before it, or the chunk's
+fn scope_at(chunk: &Chunk, index: usize) -> (&[String], &[Dim]) {
+    chunk
+        .functions
+        .iter()
+        .filter(|function| function.start as usize <= index)
+        .max_by_key(|function| function.start)
+        .map_or((&chunk.variables, &chunk.arrays), |function| {
+            (&function.variables, &function.arrays)
+        })
+}
+
+fn number_text(number: &Number) -> String {
+    match number {
+        Number::Int(i) => format!("int {}", i),
+        Number::Long(l) => format!("long {}", l),
+        Number::Float(f) => format!("float {:?}", f),
+        Number::Double(d) => format!("double {:?}", d),
+    }
+}
+
+fn instruction_text(
+    chunk: &Chunk,
+    (variables, arrays): (&[String], &[Dim]),
+    instruction: &Instruction,
+) -> String {
+    // the name, the operand and what the operand means
+    let (name, operand, comment) = match *instruction {
+        Instruction::Number(i) => (
+            "NUMBER",
+            i.to_string(),
+            chunk.numbers.get(i as usize).map(number_text),
+        ),
+        Instruction::String(i) => ("STRING", i.to_string(), string(chunk, i)),
+        Instruction::Load(i) => ("LOAD", i.to_string(), variable(variables, i)),
+        Instruction::Store(i) => ("STORE", i.to_string(), variable(variables, i)),
+        Instruction::LoadElement(i) => ("LOAD_ELEMENT", i.to_string(), array(arrays, i)),
+        Instruction::StoreElement(i) => ("STORE_ELEMENT", i.to_string(), array(arrays, i)),
+        Instruction::Unary(op) => ("UNARY", op.symbol().to_string(), None),
+        Instruction::Binary(op) => ("BINARY", op.symbol().to_string(), None),
+        Instruction::Compare(op) => ("COMPARE", op.symbol().to_string(), None),
+        Instruction::CompareChain(op) => ("COMPARE_CHAIN", op.symbol().to_string(), None),
+        Instruction::Pop => ("POP", String::new(), None),
+        Instruction::Jump(t) => ("JUMP", format!("{:04}", t), None),
+        Instruction::JumpIfFalse(t) => ("JUMP_IF_FALSE", format!("{:04}", t), None),
+        Instruction::Gosub(t) => ("GOSUB", format!("{:04}", t), None),
+        Instruction::Return => ("RETURN", String::new(), None),
+        Instruction::Call(i) => (
+            "CALL",
+            i.to_string(),
+            chunk
+                .functions
+                .get(i as usize)
+                .map(|function| function.name.clone()),
+        ),
+        Instruction::Builtin(builtin) => ("BUILTIN", builtin.name().to_string(), None),
+        Instruction::ReturnValue => ("RETURN_VALUE", String::new(), None),
+        Instruction::PrintString(i) => ("PRINT_STRING", i.to_string(), string(chunk, i)),
+        Instruction::PrintNumber => ("PRINT_NUMBER", String::new(), None),
+        Instruction::PrintText => ("PRINT_TEXT", String::new(), None),
+        Instruction::Input(i) => ("INPUT", i.to_string(), variable(variables, i)),
+        Instruction::Halt => ("HALT", String::new(), None),
+    };
+    let text = format!("{:<14}{:>4}", name, operand);
+    match comment {
+        Some(comment) => format!("{}  ; {}", text, comment),
+        None => text.trim_end().to_string(),
+    }
+}
+
+fn variable(variables: &[String], slot: u16) -> Option<String> {
+    variables.get(slot as usize).cloned()
+}
+
+fn array(arrays: &[Dim], index: u16) -> Option<String> {
+    arrays.get(index as usize).map(|dim| dim.name.clone())
+}
+
+fn string(chunk: &Chunk, index: u16) -> Option<String> {
+    chunk
+        .strings
+        .get(index as usize)
+        .map(|text| format!("{:?}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    #[test]
+    fn test_disassemble() {
+        let source = "LET a = 1.5\nLABEL top\nPRINT \"hi\"\nIF a > 0 THEN\nGOTO top\nENDIF\n";
+        let chunk = compile(&Parser::new(Lexer::new(source)).program().unwrap()).unwrap();
+        assert_eq!(
+            disassemble(&chunk),
+            "numbers:
+     0  double 1.5
+     1  int 0
+strings:
+     0  \"hi\"
+variables:
+     0  a
+arrays:
+functions:
+code:
+ line  addr  instruction
+    1  0000  NUMBER           0  ; double 1.5
+       0001  STORE            0  ; a
+top:
+    3  0002  PRINT_STRING     0  ; \"hi\"
+    4  0003  LOAD             0  ; a
+       0004  NUMBER           1  ; int 0
+       0005  COMPARE          >
+       0006  JUMP_IF_FALSE 0008
+    5  0007  JUMP          0002
+       0008  HALT
+"
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        let source = "FUNCTION f(x)\nLET y = x * 2\nRETURN y\nENDFUNCTION\nPRINT f(2)\n";
+        let chunk = compile(&Parser::new(Lexer::new(source)).program().unwrap()).unwrap();
+        let listing = disassemble(&chunk);
+        assert!(
+            listing.contains("functions:\n     0  f(x) at 0004\n         0  x\n         1  y\n")
+        );
+        assert!(listing.contains("       0001  CALL             0  ; f\n"));
+        // inside the function the slots are its own
+        assert!(listing.contains("f(x):\n    2  0004  LOAD             0  ; x\n"));
+        assert!(listing.contains("       0007  STORE            1  ; y\n"));
+    }
+
+    #[test]
+    fn test_strings() {
+        let source = "LET a$ = \"hi\"\nPRINT a$ + \"!\"\n";
+        let chunk = compile(&Parser::new(Lexer::new(source)).program().unwrap()).unwrap();
+        assert!(disassemble(&chunk).ends_with(
+            "    1  0000  STRING           0  ; \"hi\"
+       0001  STORE            0  ; a$
+    2  0002  LOAD             0  ; a$
+       0003  STRING           1  ; \"!\"
+       0004  BINARY           +
+       0005  PRINT_TEXT
+       0006  HALT
+"
+        ));
+    }
+
+    #[test]
+    fn test_builtins() {
+        let source = "PRINT LEFT$(\"abc\", 2)\n";
+        let chunk = compile(&Parser::new(Lexer::new(source)).program().unwrap()).unwrap();
+        assert!(disassemble(&chunk).contains("       0002  BUILTIN       LEFT$\n"));
+    }
+
+    #[test]
+    fn test_arrays() {
+        let source = "DIM grid(2, 3)\nLET grid(1, 0) = 4\nFUNCTION f$()\nDIM a$(5)\n\
+                      RETURN a$(0)\nENDFUNCTION\n";
+        let chunk = compile(&Parser::new(Lexer::new(source)).program().unwrap()).unwrap();
+        let listing = disassemble(&chunk);
+        assert!(listing.contains(
+            "arrays:\n     0  grid(2, 3)\nfunctions:\n     0  f$() at 0005\n         0  a$(5)\n"
+        ));
+        assert!(listing.contains("       0003  STORE_ELEMENT    0  ; grid\n"));
+        assert!(listing.contains("       0006  LOAD_ELEMENT     0  ; a$\n"));
+    }
+}
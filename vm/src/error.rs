@@ -0,0 +1,107 @@
+use std::{error::Error, fmt};
+
+/// Programs the bytecode can't hold. The parser has already caught everything else.
+#[derive(Clone, PartialEq, Debug)]
+pub enum CompileError {
+    /// more than 65536 distinct numbers, strings or variables
+    TooLarge { what: &'static str },
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::TooLarge { what } => {
+                write!(f, "Program has too many {} to compile to bytecode", what)
+            }
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+/// Why a .ttc file can't be run. Everything is checked up front so the VM never has to
+/// deal with a bad jump or an empty stack.
+#[derive(Clone, PartialEq, Debug)]
+pub enum LoadError {
+    /// doesn't start with the .ttc magic number, so probably isn't one
+    NotBytecode,
+    /// written by a newer (or much older) compiler
+    UnsupportedVersion { version: u16 },
+    /// the file stops in the middle of something
+    Truncated { offset: usize },
+    /// a byte that doesn't mean anything where it is
+    Invalid { offset: usize, what: &'static str },
+    /// an instruction refers to something that doesn't exist
+    BadOperand { at: u32, what: &'static str },
+    /// a jump to somewhere outside the code
+    BadTarget { at: u32, target: u32 },
+    /// an instruction would pop from an empty stack
+    StackUnderflow { at: u32 },
+    /// two paths reach an instruction with different values on the stack
+    StackMismatch { at: u32 },
+    /// an instruction would pop a string where it needs a number or the other way round
+    WrongType { at: u32 },
+    /// a GOSUB, RETURN or RETURN_VALUE with values left on the stack
+    StackNotEmpty { at: u32 },
+    /// an instruction that can't be used in a function, or can only be used in one
+    Misplaced { at: u32 },
+    /// an instruction that is part of more than one function, or of a function and the
+    /// code outside them
+    SharedCode { at: u32 },
+    /// the code can run off its end without a HALT
+    MissingHalt,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::NotBytecode => write!(f, "Not a Teeny Tiny bytecode file"),
+            LoadError::UnsupportedVersion { version } => {
+                write!(f, "Unsupported bytecode version {}", version)
+            }
+            LoadError::Truncated { offset } => {
+                write!(f, "Bytecode file ends early, at byte {}", offset)
+            }
+            LoadError::Invalid { offset, what } => {
+                write!(f, "Invalid {} at byte {}", what, offset)
+            }
+            LoadError::BadOperand { at, what } => {
+                write!(f, "Instruction {} refers to a missing {}", at, what)
+            }
+            LoadError::BadTarget { at, target } => {
+                write!(
+                    f,
+                    "Instruction {} jumps to {}, outside the code",
+                    at, target
+                )
+            }
+            LoadError::StackUnderflow { at } => {
+                write!(f, "Instruction {} pops from an empty stack", at)
+            }
+            LoadError::StackMismatch { at } => write!(
+                f,
+                "Instruction {} is reached with different values on the stack",
+                at
+            ),
+            LoadError::WrongType { at } => {
+                write!(f, "Instruction {} is given a value of the wrong type", at)
+            }
+            LoadError::StackNotEmpty { at } => {
+                write!(f, "Instruction {} leaves values on the stack", at)
+            }
+            LoadError::Misplaced { at } => {
+                write!(f, "Instruction {} can't be used where it is", at)
+            }
+            LoadError::SharedCode { at } => {
+                write!(
+                    f,
+                    "Instruction {} is reached from more than one function",
+                    at
+                )
+            }
+            LoadError::MissingHalt => write!(f, "Code can run past its end without a HALT"),
+        }
+    }
+}
+
+impl Error for LoadError {}
@@ -0,0 +1,674 @@
+pub mod chunk;
+pub mod compiler;
+pub mod disassemble;
+pub mod error;
+pub mod ttc;
+
+use chunk::{Chunk, Dim, Instruction};
+use interpreter::builtins;
+use interpreter::error::RuntimeError;
+use interpreter::scan::{self, Scanned};
+use interpreter::value::{self, Array, Number, Value};
+use parser::ast::{Type, DEFAULT_MAX_DEPTH};
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+/// Runs compiled bytecode on a stack of values. Prints exactly what the generated C
+/// would, like the tree-walking interpreter, without walking a tree.
+pub struct Vm<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    stack: Vec<Value>,
+    // every variable is a float or a string in the generated C, and reads as 0 or "" before
+    // it's written. These are the running function's, or the ones outside any
+    slots: Vec<Value>,
+    // the running function's arrays, or the ones outside any
+    arrays: Vec<Array>,
+    // the running function's number, for the names of its arrays
+    function: Option<usize>,
+    // where each RETURN goes back to
+    returns: Vec<usize>,
+    // the functions that have been called and not returned yet, innermost last
+    frames: Vec<Frame>,
+    max_depth: usize,
+}
+
+// What a function call has to put back when it returns.
+struct Frame {
+    return_to: usize,
+    slots: Vec<Value>,
+    arrays: Vec<Array>,
+    function: Option<usize>,
+}
+
+impl<R: BufRead, W: Write> Vm<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Vm {
+            input,
+            output,
+            stack: Vec::new(),
+            slots: Vec::new(),
+            arrays: Vec::new(),
+            function: None,
+            returns: Vec::new(),
+            frames: Vec::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+
+    /// Stop with an error when GOSUBs, or function calls, nest deeper than this.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Run a chunk until it halts.
+    pub fn run(&mut self, chunk: &Chunk) -> Result<(), RuntimeError> {
+        let result = self.execute(chunk);
+        // whatever was printed comes out before the error is reported, like it does from the C
+        let flushed = self.output.flush();
+        result?;
+        Ok(flushed?)
+    }
+
+    fn execute(&mut self, chunk: &Chunk) -> Result<(), RuntimeError> {
+        self.stack.clear();
+        self.returns.clear();
+        self.frames.clear();
+        self.slots = empty_slots(&chunk.variables);
+        self.arrays = empty_arrays(&chunk.arrays);
+        self.function = None;
+        // shared rather than copied every time they're pushed
+        let strings: Vec<Rc<str>> = chunk
+            .strings
+            .iter()
+            .map(|text| text.as_str().into())
+            .collect();
+
+        let mut pc = 0;
+        loop {
+            let instruction = chunk.code[pc];
+            pc += 1;
+            match instruction {
+                Instruction::Number(index) => {
+                    self.stack
+                        .push(Value::Number(chunk.numbers[index as usize]));
+                }
+                Instruction::String(index) => {
+                    self.stack
+                        .push(Value::String(Rc::clone(&strings[index as usize])));
+                }
+                Instruction::Load(slot) => {
+                    self.stack.push(self.slots[slot as usize].clone());
+                }
+                Instruction::Store(slot) => {
+                    self.slots[slot as usize] = self.pop().stored();
+                }
+                Instruction::LoadElement(index) => {
+                    let offset = self.offset(chunk, index as usize, pc)?;
+                    let array = &self.arrays[index as usize];
+                    self.stack.push(array.values[offset].clone());
+                }
+                Instruction::StoreElement(index) => {
+                    let value = self.pop().stored();
+                    let offset = self.offset(chunk, index as usize, pc)?;
+                    self.arrays[index as usize].values[offset] = value;
+                }
+                Instruction::Unary(op) => {
+                    let operand = self.pop().as_number();
+                    self.stack.push(Value::Number(operand.unary(op)));
+                }
+                Instruction::Binary(op) => {
+                    let (lhs, rhs) = self.pop_pair();
+                    let result = lhs.binary(op, &rhs).ok_or(RuntimeError::DivisionByZero {
+                        line: chunk.lines[pc - 1] as usize,
+                    })?;
+                    self.stack.push(result);
+                }
+                Instruction::Compare(op) => {
+                    let (lhs, rhs) = self.pop_pair();
+                    self.stack.push(Value::Number(lhs.compare(op, &rhs)));
+                }
+                Instruction::CompareChain(op) => {
+                    let (lhs, rhs) = self.pop_pair();
+                    let result = lhs.compare(op, &rhs);
+                    self.stack.push(rhs);
+                    self.stack.push(Value::Number(result));
+                }
+                Instruction::Pop => {
+                    self.pop();
+                }
+                Instruction::Jump(target) => pc = target as usize,
+                Instruction::Gosub(target) => {
+                    if self.returns.len() >= self.max_depth {
+                        return Err(RuntimeError::TooDeep {
+                            limit: self.max_depth,
+                            line: chunk.lines[pc - 1] as usize,
+                        });
+                    }
+                    self.returns.push(pc);
+                    pc = target as usize;
+                }
+                Instruction::Return => match self.returns.pop() {
+                    Some(to) => pc = to,
+                    None => {
+                        return Err(RuntimeError::ReturnWithoutGosub {
+                            line: chunk.lines[pc - 1] as usize,
+                        })
+                    }
+                },
+                Instruction::Call(index) => {
+                    let function = &chunk.functions[index as usize];
+                    if self.frames.len() >= self.max_depth {
+                        return Err(RuntimeError::CallsTooDeep {
+                            limit: self.max_depth,
+                            line: function.line as usize,
+                        });
+                    }
+                    // the arguments become the first of the function's own variables
+                    let mut slots = empty_slots(&function.variables);
+                    let args = self.stack.len() - function.params as usize;
+                    for (slot, arg) in slots.iter_mut().zip(self.stack.drain(args..)) {
+                        *slot = arg.stored();
+                    }
+                    let slots = std::mem::replace(&mut self.slots, slots);
+                    let arrays = empty_arrays(&function.arrays);
+                    let arrays = std::mem::replace(&mut self.arrays, arrays);
+                    self.frames.push(Frame {
+                        return_to: pc,
+                        slots,
+                        arrays,
+                        function: self.function.replace(index as usize),
+                    });
+                    pc = function.start as usize;
+                }
+                Instruction::Builtin(builtin) => {
+                    let args = self.stack.len() - builtin.params().len();
+                    let result = builtins::call(builtin, &self.stack[args..]);
+                    self.stack.truncate(args);
+                    self.stack.push(result);
+                }
+                Instruction::ReturnValue => {
+                    let value = self.pop().stored();
+                    let frame = self
+                        .frames
+                        .pop()
+                        .expect("bytecode returned from no function");
+                    self.slots = frame.slots;
+                    self.arrays = frame.arrays;
+                    self.function = frame.function;
+                    pc = frame.return_to;
+                    self.stack.push(value);
+                }
+                Instruction::JumpIfFalse(target) => {
+                    if !self.pop().as_number().is_true() {
+                        pc = target as usize;
+                    }
+                }
+                Instruction::PrintString(index) => {
+                    writeln!(self.output, "{}", chunk.strings[index as usize])?;
+                }
+                Instruction::PrintNumber => {
+                    let value = self.pop().as_number().as_f32();
+                    writeln!(self.output, "{}", value::format_float(value))?;
+                }
+                Instruction::PrintText => {
+                    let text = self.pop();
+                    writeln!(self.output, "{}", text)?;
+                }
+                Instruction::Input(slot) => {
+                    // anything printed so far should be visible before we block on input
+                    self.output.flush()?;
+                    let slot = &mut self.slots[slot as usize];
+                    if let Value::String(_) = slot {
+                        if let Some(line) = scan::scan_line(&mut self.input)? {
+                            *slot = Value::String(line.into());
+                        }
+                        continue;
+                    }
+                    match scan::scan_float(&mut self.input)? {
+                        Scanned::Value(value) => *slot = Value::Number(Number::Float(value)),
+                        Scanned::NoMatch => {
+                            *slot = Value::empty(Type::Number);
+                            scan::skip_word(&mut self.input)?;
+                        }
+                        Scanned::Eof => {}
+                    }
+                }
+                Instruction::Halt => return Ok(()),
+            }
+        }
+    }
+
+    // the compiler never pops more than it pushed
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("bytecode popped an empty stack")
+    }
+
+    fn pop_pair(&mut self) -> (Value, Value) {
+        let rhs = self.pop();
+        (self.pop(), rhs)
+    }
+
+    // pop an index for each of the array's sizes, and find where that element is. `pc`
+    // is just past the instruction, for the line if it's out of bounds
+    fn offset(&mut self, chunk: &Chunk, index: usize, pc: usize) -> Result<usize, RuntimeError> {
+        let array = &self.arrays[index];
+        let first = self.stack.len() - array.sizes.len();
+        let indices: Vec<f64> = self
+            .stack
+            .drain(first..)
+            .map(|index| index.as_number().as_f64())
+            .collect();
+        array.offset(&indices).ok_or_else(|| {
+            let dims = match self.function {
+                Some(function) => &chunk.functions[function].arrays,
+                None => &chunk.arrays,
+            };
+            RuntimeError::IndexOutOfBounds {
+                name: dims[index].name.clone(),
+                sizes: array.sizes.clone(),
+                line: chunk.lines[pc - 1] as usize,
+            }
+        })
+    }
+}
+
+// 0 or "" for each variable, depending on its name
+fn empty_slots(variables: &[String]) -> Vec<Value> {
+    variables
+        .iter()
+        .map(|name| Value::empty(Type::of_name(name)))
+        .collect()
+}
+
+fn empty_arrays(dims: &[Dim]) -> Vec<Array> {
+    dims.iter()
+        .map(|dim| Array::new(&dim.name, &dim.sizes))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interpreter::Interpreter;
+    use lexer::Lexer;
+    use parser::Parser;
+    use std::io::BufWriter;
+
+    // run a program on the VM and the interpreter and check they agree
+    fn run(source: &str, input: &str) -> String {
+        let program = Parser::new(Lexer::new(source)).program().unwrap();
+        let mut output = Vec::new();
+        Vm::new(input.as_bytes(), &mut output)
+            .run(&compiler::compile(&program).unwrap())
+            .unwrap();
+        let mut expected = Vec::new();
+        Interpreter::new(input.as_bytes(), &mut expected)
+            .run(&program)
+            .unwrap();
+        assert_eq!(output, expected);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_fibonacci() {
+        let source = std::fs::read_to_string("../tt_snippets/fibonacci.tt").unwrap();
+        assert_eq!(
+            run(&source, "5\n"),
+            "How many fibonacci numbers do you want?\n\n0.00\n1.00\n1.00\n2.00\n3.00\n"
+        );
+    }
+
+    #[test]
+    fn test_c_arithmetic() {
+        assert_eq!(
+            run(
+                "PRINT 7 / 2\nLET a = 7\nPRINT a / 2\nIF 3 == 7 / 2 < 3.5 THEN\nPRINT 1.5\nENDIF\n",
+                ""
+            ),
+            "3.00\n3.50\n1.50\n"
+        );
+    }
+
+    #[test]
+    fn test_mod_and_pow() {
+        assert_eq!(
+            run(
+                "LET a = 7.5\nPRINT a % 2\nPRINT -17 % 5\nPRINT -2 ^ 2\nPRINT 2 ^ 3 ^ 2\n",
+                ""
+            ),
+            "1.50\n-2.00\n-4.00\n512.00\n"
+        );
+    }
+
+    #[test]
+    fn test_comparison_chains() {
+        let source = "LET a = 0
+WHILE 0 <= a < 6 REPEAT
+    IF 1 < a <= 4 != a + 1 THEN
+        PRINT a
+    ENDIF
+    IF a < 0 < 1 / 0 THEN
+    ENDIF
+    LET a = a + 1
+ENDWHILE
+";
+        assert_eq!(run(source, ""), "2.00\n4.00\n");
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        let source = "LET a = 0
+WHILE NOT a >= 6 REPEAT
+    IF a == 1 OR a == 3 AND NOT a > 3 OR a > 4 AND 1 / a > 0.1 THEN
+        PRINT a
+    ENDIF
+    IF a >= 0 OR 1 / 0 == 0 THEN
+        PRINT 0
+    ENDIF
+    LET a = a + 1
+ENDWHILE
+";
+        assert_eq!(
+            run(source, ""),
+            "0.00\n1.00\n0.00\n0.00\n3.00\n0.00\n0.00\n5.00\n0.00\n"
+        );
+
+        let source = "LET a = 0
+WHILE (a + 1) * 2 <= 12 REPEAT
+    IF (a == 1 OR a > 3) AND NOT (a == 5 OR (a == 4)) THEN
+        PRINT a
+    ENDIF
+    LET a = a + 1
+ENDWHILE
+";
+        assert_eq!(run(source, ""), "1.00\n");
+    }
+
+    #[test]
+    fn test_else() {
+        let source = "LET a = 0
+WHILE a < 4 REPEAT
+    IF a == 0 THEN
+        PRINT \"zero\"
+    ELSEIF a == 1 OR a == 2 THEN
+        PRINT a
+        IF a == 2 THEN
+            GOTO next
+        ELSE
+            PRINT \"not two\"
+        ENDIF
+    ELSE
+        LABEL next
+        PRINT \"else\"
+    ENDIF
+    LET a = a + 1
+ENDWHILE
+";
+        assert_eq!(run(source, ""), "zero\n1.00\nnot two\n2.00\nelse\nelse\n");
+    }
+
+    #[test]
+    fn test_for() {
+        let source = "FOR i = 1 TO 3
+    FOR j = i TO 0 STEP -0.75
+        PRINT j
+    NEXT
+NEXT
+FOR k = 0 TO 1 STEP 0.1
+NEXT
+PRINT k
+LET n = 3
+FOR i = 1 TO n STEP n - 2
+    LET n = n - 1
+    GOTO inside
+    PRINT \"skipped\"
+    LABEL inside
+NEXT
+PRINT i
+";
+        assert_eq!(
+            run(source, ""),
+            "1.00\n0.25\n2.00\n1.25\n0.50\n3.00\n2.25\n1.50\n0.75\n0.00\n1.00\n0.00\n"
+        );
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let source = "LET i = 0
+GOTO inside
+WHILE i < 10 REPEAT
+    LET i = i + 1
+    IF i % 2 == 0 THEN
+        CONTINUE
+    ENDIF
+    FOR j = 1 TO 9 STEP 2
+        IF j > i THEN
+            BREAK
+        ELSEIF j == 3 THEN
+            CONTINUE
+        ENDIF
+        PRINT j
+    NEXT
+    LABEL inside
+    IF i > 4 AND 1 < i THEN
+        BREAK
+    ENDIF
+ENDWHILE
+PRINT i
+";
+        assert_eq!(run(source, ""), "1.00\n1.00\n1.00\n5.00\n5.00\n");
+    }
+
+    #[test]
+    fn test_gosub() {
+        let source = "LET n = 0
+WHILE n < 3 REPEAT
+    GOSUB count
+    IF n == 2 THEN
+        GOSUB twice
+    ENDIF
+ENDWHILE
+GOTO end
+LABEL twice
+GOSUB count
+LABEL count
+LET n = n + 1
+PRINT n
+RETURN
+LABEL end
+";
+        assert_eq!(run(source, ""), "1.00\n2.00\n3.00\n4.00\n");
+
+        // past the limit, and a RETURN that runs on after the last GOSUB has returned
+        let error = |source: &str| {
+            let program = Parser::new(Lexer::new(source)).program().unwrap();
+            Vm::new("".as_bytes(), Vec::new())
+                .with_max_depth(3)
+                .run(&compiler::compile(&program).unwrap())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("LABEL again\nGOSUB again\n"),
+            "GOSUB nested more than 3 deep at line 2"
+        );
+        assert_eq!(
+            error("GOSUB sub\nLABEL sub\nRETURN\n"),
+            "RETURN without GOSUB at line 3"
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        let source = "LET n = 5
+PRINT fact(n) + twice(n)
+PRINT n
+FUNCTION fact(n)
+    IF n <= 1 THEN
+        RETURN 1
+    ENDIF
+    RETURN n * fact(n - 1)
+ENDFUNCTION
+FUNCTION twice(x)
+    LET n = 0
+    FOR i = 1 TO 2
+        LET n = n + x
+    NEXT
+    GOTO done
+    LET n = 1
+    LABEL done
+    RETURN n
+ENDFUNCTION
+FUNCTION nothing()
+ENDFUNCTION
+PRINT nothing() + 7 / 2
+";
+        assert_eq!(run(source, ""), "130.00\n5.00\n3.00\n");
+
+        let program = Parser::new(Lexer::new(
+            "FUNCTION f(n)\nRETURN f(n + 1)\nENDFUNCTION\nPRINT f(0)\n",
+        ))
+        .program()
+        .unwrap();
+        let error = Vm::new("".as_bytes(), Vec::new())
+            .with_max_depth(3)
+            .run(&compiler::compile(&program).unwrap())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Function calls nested more than 3 deep at line 1"
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        let source = "INPUT name$
+LET hi$ = \"Hi \" + name$
+PRINT hi$ + \"!\"
+IF hi$ == \"Hi \" + name$ != \"\" AND name$ != \"Bob\" THEN
+    PRINT twice$(name$)
+ENDIF
+INPUT n
+INPUT rest$
+PRINT \"[\" + rest$ + \"]\"
+PRINT n + length(rest$)
+FUNCTION twice$(x$)
+    LET x$ = x$ + x$
+    RETURN x$
+ENDFUNCTION
+FUNCTION length(x$)
+    IF x$ == \"\" THEN
+        RETURN 0
+    ENDIF
+ENDFUNCTION
+PRINT nothing$()
+FUNCTION nothing$()
+ENDFUNCTION
+";
+        assert_eq!(
+            run(source, "\n  Ann\r\n2.5 and the rest\n"),
+            "Hi Ann!\nAnnAnn\n[and the rest]\n2.50\n\n"
+        );
+        // at the end of the input a string variable keeps what it had
+        assert_eq!(run("LET a$ = \"x\"\nINPUT a$\nPRINT a$\n", "  \n"), "x\n");
+    }
+
+    #[test]
+    fn test_builtins() {
+        let source = "INPUT word$
+LET n = 0
+WHILE n < LEN(word$) REPEAT
+    LET n = n + 1
+    PRINT UPPER$(MID$(word$, n, 1)) + LOWER$(RIGHT$(word$, LEN(word$) - n))
+ENDWHILE
+PRINT VAL(LEFT$(word$, 1)) + VAL(STR$(2.0 / 3))
+";
+        assert_eq!(run(source, "5Öl\n"), "5öl\nÖl\nL\n5.67\n");
+
+        let source = "LET x = -7.5
+PRINT SQRT(2) + ABS(x) / 2
+PRINT INT(x) + FLOOR(x) + CEIL(x) + INT(7.5) / 2
+PRINT ATN(1) * 4 + SIN(1) * COS(1) - TAN(0.5)
+PRINT LOG(EXP(2)) + MIN(3, x) * MAX(2, 9)
+PRINT SQRT(-1)
+";
+        assert_eq!(run(source, ""), "5.16\n-18.50\n3.05\n-65.50\n-nan\n");
+    }
+
+    #[test]
+    fn test_arrays() {
+        let source = "DIM grid(3, 4)
+DIM names$(2)
+FOR i = 0 TO 2
+    FOR j = 0 TO 3
+        LET grid(i, j) = i * 10 + j
+    NEXT
+NEXT
+LET names$(1.9) = \"b\" + names$(0)
+PRINT grid(2, 3) + grid(1, 0.5)
+PRINT \"[\" + names$(0) + \"]\" + names$(1)
+PRINT count(2) + count(3)
+FUNCTION count(n)
+    DIM seen(5)
+    LET seen(n) = seen(n) + 1
+    RETURN seen(n) + n
+ENDFUNCTION
+PRINT grid(0, 3)
+";
+        assert_eq!(run(source, ""), "33.00\n[]b\n7.00\n3.00\n");
+
+        let program = Parser::new(Lexer::new(
+            "DIM grid(3, 4)\nFOR i = 0 TO 3\n    PRINT i\n    LET grid(i, 0) = i\nNEXT\n",
+        ))
+        .program()
+        .unwrap();
+        let error = Vm::new("".as_bytes(), Vec::new())
+            .run(&compiler::compile(&program).unwrap())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Index out of bounds for array grid(3, 4) at line 4"
+        );
+    }
+
+    #[test]
+    fn test_goto_into_loop() {
+        let source = "LET i = 0
+GOTO inside
+WHILE i < 3 REPEAT
+    PRINT \"top\"
+    LABEL inside
+    LET i = i + 1
+    PRINT i
+ENDWHILE
+";
+        assert_eq!(run(source, ""), "1.00\ntop\n2.00\ntop\n3.00\n");
+    }
+
+    #[test]
+    fn test_input() {
+        let source = "INPUT a\nINPUT b\nINPUT c\nPRINT a + b + c\n";
+        assert_eq!(run(source, "2.5 nope"), "2.50\n");
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let program = Parser::new(Lexer::new("PRINT \"before\"\nPRINT 1 / 0\n"))
+            .program()
+            .unwrap();
+        let mut output = Vec::new();
+        let error = Vm::new("".as_bytes(), &mut output)
+            .run(&compiler::compile(&program).unwrap())
+            .unwrap_err();
+        assert!(matches!(error, RuntimeError::DivisionByZero { line: 2 }));
+
+        // what was printed before the error is out before it's reported
+        let mut output = BufWriter::new(Vec::new());
+        Vm::new("".as_bytes(), &mut output)
+            .run(&compiler::compile(&program).unwrap())
+            .unwrap_err();
+        assert_eq!(output.get_ref(), b"before\n");
+    }
+}
@@ -0,0 +1,801 @@
+//! The .ttc file format, compiled bytecode ready to ship and run without the source.
+//!
+//! Everything is little endian. After the header come the sections, always all of them
+//! and always in this order, each starting with a u32 count:
+//!
+//! ```text
+//! header     "TTC\0", u16 version, u16 flags (0)
+//! numbers    u8 type (0 int, 1 long, 2 float, 3 double) then the value
+//! strings    u32 length then UTF-8
+//! variables  names by slot, as strings
+//! arrays     name, then the sizes, each a u32
+//! functions  name, u32 start, u32 line, u16 parameters, then variables and arrays like
+//!            above
+//! labels     name then u32 instruction
+//! lines      u32 instruction then u32 line, one entry wherever the line changes
+//! code       u8 opcode then its operand, if any
+//! ```
+
+use crate::chunk::{Chunk, Dim, Function, Instruction};
+use crate::error::LoadError;
+use interpreter::value::Number;
+use parser::ast::{BinaryOp, ComparisonOp, Type, UnaryOp, BUILTINS, MAX_ARRAY_SIZE};
+
+pub const MAGIC: &[u8; 4] = b"TTC\0";
+pub const VERSION: u16 = 5;
+
+const UNARY_OPS: [UnaryOp; 2] = [UnaryOp::Plus, UnaryOp::Minus];
+// new operators go on the end so older files keep their meaning
+const BINARY_OPS: [BinaryOp; 6] = [
+    BinaryOp::Add,
+    BinaryOp::Sub,
+    BinaryOp::Mul,
+    BinaryOp::Div,
+    BinaryOp::Mod,
+    BinaryOp::Pow,
+];
+const COMPARISON_OPS: [ComparisonOp; 6] = [
+    ComparisonOp::Eq,
+    ComparisonOp::NotEq,
+    ComparisonOp::Lt,
+    ComparisonOp::LtEq,
+    ComparisonOp::Gt,
+    ComparisonOp::GtEq,
+];
+
+// the position of an operator in its table above, or of a built in function in BUILTINS,
+// is its byte in the file
+fn op_byte<T: PartialEq>(ops: &[T], op: &T) -> u8 {
+    ops.iter().position(|o| o == op).unwrap() as u8
+}
+
+/// Serialize a chunk.
+pub fn write(chunk: &Chunk) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+
+    write_u32(&mut out, chunk.numbers.len());
+    for number in &chunk.numbers {
+        match number {
+            Number::Int(i) => {
+                out.push(0);
+                out.extend_from_slice(&i.to_le_bytes());
+            }
+            Number::Long(l) => {
+                out.push(1);
+                out.extend_from_slice(&l.to_le_bytes());
+            }
+            Number::Float(f) => {
+                out.push(2);
+                out.extend_from_slice(&f.to_le_bytes());
+            }
+            Number::Double(d) => {
+                out.push(3);
+                out.extend_from_slice(&d.to_le_bytes());
+            }
+        }
+    }
+
+    for table in [&chunk.strings, &chunk.variables] {
+        write_strings(&mut out, table);
+    }
+    write_dims(&mut out, &chunk.arrays);
+
+    write_u32(&mut out, chunk.functions.len());
+    for function in &chunk.functions {
+        write_string(&mut out, &function.name);
+        out.extend_from_slice(&function.start.to_le_bytes());
+        out.extend_from_slice(&function.line.to_le_bytes());
+        out.extend_from_slice(&function.params.to_le_bytes());
+        write_strings(&mut out, &function.variables);
+        write_dims(&mut out, &function.arrays);
+    }
+
+    write_u32(&mut out, chunk.labels.len());
+    for (name, target) in &chunk.labels {
+        write_string(&mut out, name);
+        out.extend_from_slice(&target.to_le_bytes());
+    }
+
+    // only where the line changes, most instructions share one with their neighbours
+    let mut lines = Vec::new();
+    for (index, line) in chunk.lines.iter().enumerate() {
+        if index == 0 || chunk.lines[index - 1] != *line {
+            lines.push((index as u32, *line));
+        }
+    }
+    write_u32(&mut out, lines.len());
+    for (index, line) in lines {
+        out.extend_from_slice(&index.to_le_bytes());
+        out.extend_from_slice(&line.to_le_bytes());
+    }
+
+    write_u32(&mut out, chunk.code.len());
+    for instruction in &chunk.code {
+        match instruction {
+            Instruction::Number(i) => write_op(&mut out, 0x01, &i.to_le_bytes()),
+            Instruction::Load(i) => write_op(&mut out, 0x02, &i.to_le_bytes()),
+            Instruction::Store(i) => write_op(&mut out, 0x03, &i.to_le_bytes()),
+            Instruction::Unary(op) => write_op(&mut out, 0x04, &[op_byte(&UNARY_OPS, op)]),
+            Instruction::Binary(op) => write_op(&mut out, 0x05, &[op_byte(&BINARY_OPS, op)]),
+            Instruction::Compare(op) => write_op(&mut out, 0x06, &[op_byte(&COMPARISON_OPS, op)]),
+            Instruction::Jump(t) => write_op(&mut out, 0x07, &t.to_le_bytes()),
+            Instruction::JumpIfFalse(t) => write_op(&mut out, 0x08, &t.to_le_bytes()),
+            Instruction::PrintString(i) => write_op(&mut out, 0x09, &i.to_le_bytes()),
+            Instruction::PrintNumber => write_op(&mut out, 0x0a, &[]),
+            Instruction::Input(i) => write_op(&mut out, 0x0b, &i.to_le_bytes()),
+            Instruction::Halt => write_op(&mut out, 0x0c, &[]),
+            Instruction::CompareChain(op) => {
+                write_op(&mut out, 0x0d, &[op_byte(&COMPARISON_OPS, op)])
+            }
+            Instruction::Pop => write_op(&mut out, 0x0e, &[]),
+            Instruction::Gosub(t) => write_op(&mut out, 0x0f, &t.to_le_bytes()),
+            Instruction::Return => write_op(&mut out, 0x10, &[]),
+            Instruction::Call(i) => write_op(&mut out, 0x11, &i.to_le_bytes()),
+            Instruction::ReturnValue => write_op(&mut out, 0x12, &[]),
+            Instruction::String(i) => write_op(&mut out, 0x13, &i.to_le_bytes()),
+            Instruction::PrintText => write_op(&mut out, 0x14, &[]),
+            Instruction::Builtin(builtin) => {
+                write_op(&mut out, 0x15, &[op_byte(&BUILTINS, builtin)])
+            }
+            Instruction::LoadElement(i) => write_op(&mut out, 0x16, &i.to_le_bytes()),
+            Instruction::StoreElement(i) => write_op(&mut out, 0x17, &i.to_le_bytes()),
+        }
+    }
+    out
+}
+
+fn write_u32(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+fn write_string(out: &mut Vec<u8>, text: &str) {
+    write_u32(out, text.len());
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn write_strings(out: &mut Vec<u8>, table: &[String]) {
+    write_u32(out, table.len());
+    for text in table {
+        write_string(out, text);
+    }
+}
+
+fn write_dims(out: &mut Vec<u8>, dims: &[Dim]) {
+    write_u32(out, dims.len());
+    for dim in dims {
+        write_string(out, &dim.name);
+        write_u32(out, dim.sizes.len());
+        for size in &dim.sizes {
+            write_u32(out, *size);
+        }
+    }
+}
+
+fn write_op(out: &mut Vec<u8>, opcode: u8, operand: &[u8]) {
+    out.push(opcode);
+    out.extend_from_slice(operand);
+}
+
+/// Load a chunk and check it is safe to run: every operand and jump is in range, the stack
+/// never underflows, every instruction gets the numbers or strings it works on and finds
+/// the stack the same whichever way it's reached, GOSUB
+/// and RETURN leave nothing on it, functions keep to their own code and the code can't run
+/// off the end.
+pub fn read(bytes: &[u8]) -> Result<Chunk, LoadError> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(LoadError::NotBytecode);
+    }
+    let mut reader = Reader {
+        bytes,
+        offset: MAGIC.len(),
+    };
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(LoadError::UnsupportedVersion { version });
+    }
+    reader.u16()?;
+
+    let mut chunk = Chunk::default();
+    for _ in 0..reader.u32()? {
+        let offset = reader.offset;
+        chunk.numbers.push(match reader.u8()? {
+            0 => Number::Int(i32::from_le_bytes(reader.array()?)),
+            1 => Number::Long(i64::from_le_bytes(reader.array()?)),
+            2 => Number::Float(f32::from_le_bytes(reader.array()?)),
+            3 => Number::Double(f64::from_le_bytes(reader.array()?)),
+            _ => {
+                return Err(LoadError::Invalid {
+                    offset,
+                    what: "number type",
+                })
+            }
+        });
+    }
+    for _ in 0..reader.u32()? {
+        chunk.strings.push(reader.string()?);
+    }
+    for _ in 0..reader.u32()? {
+        chunk.variables.push(reader.string()?);
+    }
+    chunk.arrays = reader.dims()?;
+    let mut functions = Vec::new();
+    for _ in 0..reader.u32()? {
+        let offset = reader.offset;
+        let mut function = Function {
+            name: reader.string()?,
+            start: reader.u32()?,
+            line: reader.u32()?,
+            params: reader.u16()?,
+            variables: Vec::new(),
+            arrays: Vec::new(),
+        };
+        for _ in 0..reader.u32()? {
+            function.variables.push(reader.string()?);
+        }
+        function.arrays = reader.dims()?;
+        functions.push((offset, function));
+    }
+    let mut labels = Vec::new();
+    for _ in 0..reader.u32()? {
+        let name = reader.string()?;
+        labels.push((reader.offset, name, reader.u32()?));
+    }
+    let mut lines = Vec::new();
+    for _ in 0..reader.u32()? {
+        lines.push((reader.offset, reader.u32()?, reader.u32()?));
+    }
+    for _ in 0..reader.u32()? {
+        let offset = reader.offset;
+        let instruction = match reader.u8()? {
+            0x01 => Instruction::Number(reader.u16()?),
+            0x02 => Instruction::Load(reader.u16()?),
+            0x03 => Instruction::Store(reader.u16()?),
+            0x04 => Instruction::Unary(reader.op(&UNARY_OPS)?),
+            0x05 => Instruction::Binary(reader.op(&BINARY_OPS)?),
+            0x06 => Instruction::Compare(reader.op(&COMPARISON_OPS)?),
+            0x07 => Instruction::Jump(reader.u32()?),
+            0x08 => Instruction::JumpIfFalse(reader.u32()?),
+            0x09 => Instruction::PrintString(reader.u16()?),
+            0x0a => Instruction::PrintNumber,
+            0x0b => Instruction::Input(reader.u16()?),
+            0x0c => Instruction::Halt,
+            0x0d => Instruction::CompareChain(reader.op(&COMPARISON_OPS)?),
+            0x0e => Instruction::Pop,
+            0x0f => Instruction::Gosub(reader.u32()?),
+            0x10 => Instruction::Return,
+            0x11 => Instruction::Call(reader.u16()?),
+            0x12 => Instruction::ReturnValue,
+            0x13 => Instruction::String(reader.u16()?),
+            0x14 => Instruction::PrintText,
+            0x15 => Instruction::Builtin(reader.op(&BUILTINS)?),
+            0x16 => Instruction::LoadElement(reader.u16()?),
+            0x17 => Instruction::StoreElement(reader.u16()?),
+            _ => {
+                return Err(LoadError::Invalid {
+                    offset,
+                    what: "opcode",
+                })
+            }
+        };
+        chunk.code.push(instruction);
+    }
+    if reader.offset != bytes.len() {
+        return Err(LoadError::Invalid {
+            offset: reader.offset,
+            what: "data after the code",
+        });
+    }
+
+    let length = chunk.code.len() as u32;
+    for (offset, name, target) in labels {
+        if target >= length {
+            return Err(LoadError::Invalid {
+                offset,
+                what: "label",
+            });
+        }
+        chunk.labels.push((name, target));
+    }
+    for (offset, function) in functions {
+        if function.start >= length || function.params as usize > function.variables.len() {
+            return Err(LoadError::Invalid {
+                offset,
+                what: "function",
+            });
+        }
+        chunk.functions.push(function);
+    }
+
+    // spread the line table back out over every instruction
+    let mut previous = None;
+    for (index, (offset, start, line)) in lines.iter().enumerate() {
+        if *start >= length || previous.is_some_and(|previous| *start <= previous) {
+            return Err(LoadError::Invalid {
+                offset: *offset,
+                what: "line table entry",
+            });
+        }
+        previous = Some(*start);
+        let end = lines
+            .get(index + 1)
+            .map_or(length, |next| next.1.min(length));
+        for _ in *start..end.max(*start) {
+            chunk.lines.push(*line);
+        }
+    }
+    if chunk.lines.len() != chunk.code.len() {
+        return Err(LoadError::Invalid {
+            offset: reader.offset,
+            what: "line table",
+        });
+    }
+
+    verify(&chunk)?;
+    Ok(chunk)
+}
+
+// Walk every path through the code tracking what's on the stack, and which function (if
+// any) the code belongs to. Each function starts from its own empty stack. A variable,
+// function or string of the file's is a string when its name ends in `$`, like in the
+// source.
+fn verify(chunk: &Chunk) -> Result<(), LoadError> {
+    let length = chunk.code.len() as u32;
+    let mut seen: Vec<Option<(Vec<Type>, Option<usize>)>> = vec![None; chunk.code.len()];
+    let mut pending = vec![(0u32, Vec::new(), None)];
+    for (index, function) in chunk.functions.iter().enumerate() {
+        pending.push((function.start, Vec::new(), Some(index)));
+    }
+    while let Some((at, mut stack, function)) = pending.pop() {
+        if at >= length {
+            return Err(LoadError::MissingHalt);
+        }
+        match &seen[at as usize] {
+            Some((_, other)) if *other != function => return Err(LoadError::SharedCode { at }),
+            Some((seen, _)) if *seen == stack => continue,
+            Some(_) => return Err(LoadError::StackMismatch { at }),
+            None => seen[at as usize] = Some((stack.clone(), function)),
+        }
+        let (variables, arrays) = match function {
+            Some(index) => (
+                &chunk.functions[index].variables,
+                &chunk.functions[index].arrays,
+            ),
+            None => (&chunk.variables, &chunk.arrays),
+        };
+
+        let instruction = chunk.code[at as usize];
+        let operand = |index: u16, table: usize, what| {
+            if (index as usize) < table {
+                Ok(())
+            } else {
+                Err(LoadError::BadOperand { at, what })
+            }
+        };
+        let target = |target: u32| {
+            if target < length {
+                Ok(target)
+            } else {
+                Err(LoadError::BadTarget { at, target })
+            }
+        };
+        // pop a value of the given type, or of any type
+        let pop = |stack: &mut Vec<Type>, expected: Option<Type>| {
+            let found = stack.pop().ok_or(LoadError::StackUnderflow { at })?;
+            match expected {
+                Some(expected) if expected != found => Err(LoadError::WrongType { at }),
+                _ => Ok(found),
+            }
+        };
+        let number = Some(Type::Number);
+        // where it can go next
+        let (next, jump) = match instruction {
+            Instruction::Number(i) => {
+                operand(i, chunk.numbers.len(), "number")?;
+                stack.push(Type::Number);
+                (true, None)
+            }
+            Instruction::String(i) => {
+                operand(i, chunk.strings.len(), "string")?;
+                stack.push(Type::String);
+                (true, None)
+            }
+            Instruction::Load(i) => {
+                operand(i, variables.len(), "variable")?;
+                stack.push(Type::of_name(&variables[i as usize]));
+                (true, None)
+            }
+            Instruction::Store(i) => {
+                operand(i, variables.len(), "variable")?;
+                pop(&mut stack, Some(Type::of_name(&variables[i as usize])))?;
+                (true, None)
+            }
+            Instruction::LoadElement(i) => {
+                operand(i, arrays.len(), "array")?;
+                let dim = &arrays[i as usize];
+                for _ in &dim.sizes {
+                    pop(&mut stack, number)?;
+                }
+                stack.push(Type::of_name(&dim.name));
+                (true, None)
+            }
+            Instruction::StoreElement(i) => {
+                operand(i, arrays.len(), "array")?;
+                let dim = &arrays[i as usize];
+                pop(&mut stack, Some(Type::of_name(&dim.name)))?;
+                for _ in &dim.sizes {
+                    pop(&mut stack, number)?;
+                }
+                (true, None)
+            }
+            Instruction::Unary(_) => {
+                pop(&mut stack, number)?;
+                stack.push(Type::Number);
+                (true, None)
+            }
+            // ADD joins strings, anything else is only for numbers
+            Instruction::Binary(BinaryOp::Add) => {
+                let rhs = pop(&mut stack, None)?;
+                pop(&mut stack, Some(rhs))?;
+                stack.push(rhs);
+                (true, None)
+            }
+            Instruction::Binary(_) => {
+                pop(&mut stack, number)?;
+                pop(&mut stack, number)?;
+                stack.push(Type::Number);
+                (true, None)
+            }
+            Instruction::Compare(op) | Instruction::CompareChain(op) => {
+                // strings can only be told apart, not put in order
+                let rhs = match op {
+                    ComparisonOp::Eq | ComparisonOp::NotEq => pop(&mut stack, None)?,
+                    _ => pop(&mut stack, number)?,
+                };
+                pop(&mut stack, Some(rhs))?;
+                if let Instruction::CompareChain(_) = instruction {
+                    stack.push(rhs);
+                }
+                stack.push(Type::Number);
+                (true, None)
+            }
+            Instruction::Pop => {
+                pop(&mut stack, None)?;
+                (true, None)
+            }
+            Instruction::Jump(t) => (false, Some(target(t)?)),
+            Instruction::JumpIfFalse(t) => {
+                pop(&mut stack, number)?;
+                (true, Some(target(t)?))
+            }
+            // the return stack isn't saved across calls, so GOSUB stays outside functions
+            Instruction::Gosub(_) | Instruction::Return if function.is_some() => {
+                return Err(LoadError::Misplaced { at })
+            }
+            // a RETURN can come back to any GOSUB, so both have to be where the stack is
+            // empty for it to be the same when it gets there
+            Instruction::Gosub(_) | Instruction::Return if !stack.is_empty() => {
+                return Err(LoadError::StackNotEmpty { at })
+            }
+            Instruction::Gosub(t) => (true, Some(target(t)?)),
+            Instruction::Return => (false, None),
+            Instruction::Call(i) => {
+                operand(i, chunk.functions.len(), "function")?;
+                let called = &chunk.functions[i as usize];
+                for param in called.variables[..called.params as usize].iter().rev() {
+                    pop(&mut stack, Some(Type::of_name(param)))?;
+                }
+                stack.push(Type::of_name(&called.name));
+                (true, None)
+            }
+            Instruction::Builtin(builtin) => {
+                for param in builtin.params().iter().rev() {
+                    pop(&mut stack, Some(*param))?;
+                }
+                stack.push(builtin.ty());
+                (true, None)
+            }
+            Instruction::ReturnValue => match function {
+                None => return Err(LoadError::Misplaced { at }),
+                // the value it returns has to be all that's left
+                Some(_) if stack.len() > 1 => return Err(LoadError::StackNotEmpty { at }),
+                Some(index) => {
+                    pop(
+                        &mut stack,
+                        Some(Type::of_name(&chunk.functions[index].name)),
+                    )?;
+                    (false, None)
+                }
+            },
+            Instruction::PrintString(i) => {
+                operand(i, chunk.strings.len(), "string")?;
+                (true, None)
+            }
+            Instruction::PrintNumber => {
+                pop(&mut stack, number)?;
+                (true, None)
+            }
+            Instruction::PrintText => {
+                pop(&mut stack, Some(Type::String))?;
+                (true, None)
+            }
+            Instruction::Input(i) => {
+                operand(i, variables.len(), "variable")?;
+                (true, None)
+            }
+            Instruction::Halt => (false, None),
+        };
+        if next {
+            pending.push((at + 1, stack.clone(), function));
+        }
+        if let Some(jump) = jump {
+            pending.push((jump, stack, function));
+        }
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], LoadError> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + N)
+            .ok_or(LoadError::Truncated {
+                offset: self.bytes.len(),
+            })?;
+        self.offset += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let length = self.u32()? as usize;
+        let start = self.offset;
+        let bytes =
+            self.bytes
+                .get(start..start.saturating_add(length))
+                .ok_or(LoadError::Truncated {
+                    offset: self.bytes.len(),
+                })?;
+        self.offset += length;
+        String::from_utf8(bytes.to_vec()).map_err(|_| LoadError::Invalid {
+            offset: start,
+            what: "UTF-8 string",
+        })
+    }
+
+    // the arrays, which have to fit within the limits the parser keeps to, so a file
+    // can't ask for more memory than a program could
+    fn dims(&mut self) -> Result<Vec<Dim>, LoadError> {
+        let mut dims = Vec::new();
+        for _ in 0..self.u32()? {
+            let offset = self.offset;
+            let name = self.string()?;
+            let mut sizes = Vec::new();
+            let mut total = 1usize;
+            for _ in 0..self.u32()? {
+                let size = self.u32()? as usize;
+                total = total.saturating_mul(size);
+                sizes.push(size);
+            }
+            if sizes.is_empty() || total == 0 || total > MAX_ARRAY_SIZE {
+                return Err(LoadError::Invalid {
+                    offset,
+                    what: "array",
+                });
+            }
+            dims.push(Dim { name, sizes });
+        }
+        Ok(dims)
+    }
+
+    fn op<T: Copy>(&mut self, ops: &[T]) -> Result<T, LoadError> {
+        let offset = self.offset;
+        let byte = self.u8()?;
+        ops.get(byte as usize).copied().ok_or(LoadError::Invalid {
+            offset,
+            what: "operator",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn chunk(source: &str) -> Chunk {
+        compile(&Parser::new(Lexer::new(source)).program().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let builtins = chunk("PRINT MID$(UPPER$(\"abc\"), LEN(\"a\"), VAL(STR$(2)))\n");
+        assert_eq!(read(&write(&builtins)), Ok(builtins));
+
+        let arrays = chunk_with_arrays();
+        assert_eq!(read(&write(&arrays)), Ok(arrays));
+
+        let source = std::fs::read_to_string("../tt_snippets/fibonacci.tt").unwrap();
+        let chunk = chunk(&(source + "GOTO end\nPRINT 1.5 + 2 * -a\nLABEL end\n"));
+        assert_eq!(read(&write(&chunk)), Ok(chunk));
+
+        let chunk = chunk_with_functions();
+        assert_eq!(read(&write(&chunk)), Ok(chunk));
+
+        let chunk = chunk_with_strings();
+        assert_eq!(read(&write(&chunk)), Ok(chunk));
+    }
+
+    // LET s$ = 0000-0001, PRINT at 0002-0005, f$ at 0007
+    fn chunk_with_strings() -> Chunk {
+        chunk("LET s$ = \"a\"\nPRINT f$(s$) + \"b\"\nFUNCTION f$(x$)\nRETURN x$\nENDFUNCTION\n")
+    }
+
+    // main calls f, which returns at 0009 and again off its end at 0011
+    fn chunk_with_functions() -> Chunk {
+        chunk("FUNCTION f(x)\nLET y = x * 2\nRETURN y\nENDFUNCTION\nPRINT f(2)\n")
+    }
+
+    // grid(1, 2) = 3 at 0000-0003, PRINT grid(0, 0) at 0004-0007, and f's a$ at 0009
+    fn chunk_with_arrays() -> Chunk {
+        chunk(
+            "DIM grid(2, 3)\nLET grid(1, 2) = 3\nPRINT grid(0, 0)\n\
+             FUNCTION f$(i)\nDIM a$(4)\nRETURN a$(i)\nENDFUNCTION\n",
+        )
+    }
+
+    #[test]
+    fn test_bad_files() {
+        let bytes = write(&chunk("LET a = 1\nPRINT a\n"));
+        assert_eq!(read(b"#!/bin/sh"), Err(LoadError::NotBytecode));
+        assert_eq!(
+            read(&bytes[..bytes.len() - 1]),
+            Err(LoadError::Truncated {
+                offset: bytes.len() - 1
+            })
+        );
+
+        let mut newer = bytes.clone();
+        newer[4] = 6;
+        assert_eq!(
+            read(&newer),
+            Err(LoadError::UnsupportedVersion { version: 6 })
+        );
+
+        // the last byte is the HALT
+        let mut unknown = bytes.clone();
+        *unknown.last_mut().unwrap() = 0xff;
+        assert!(matches!(
+            read(&unknown),
+            Err(LoadError::Invalid { what: "opcode", .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut bad = chunk("LET a = 1\nPRINT a\n");
+        bad.code[0] = Instruction::Jump(99);
+        assert_eq!(
+            read(&write(&bad)),
+            Err(LoadError::BadTarget { at: 0, target: 99 })
+        );
+
+        let mut bad = chunk("LET a = 1\nPRINT a\n");
+        bad.code[2] = Instruction::Load(7);
+        assert_eq!(
+            read(&write(&bad)),
+            Err(LoadError::BadOperand {
+                at: 2,
+                what: "variable"
+            })
+        );
+
+        // PRINT with nothing to print
+        let mut bad = chunk("LET a = 1\nPRINT a\n");
+        bad.code.remove(2);
+        bad.lines.remove(2);
+        assert_eq!(read(&write(&bad)), Err(LoadError::StackUnderflow { at: 2 }));
+
+        // a GOSUB with something still on the stack
+        let mut bad = chunk("GOSUB sub\nLABEL sub\nPRINT 1\nRETURN\n");
+        bad.code.insert(0, Instruction::Number(0));
+        bad.lines.insert(0, 1);
+        bad.code[1] = Instruction::Gosub(2);
+        assert_eq!(read(&write(&bad)), Err(LoadError::StackNotEmpty { at: 1 }));
+
+        // the loop leaves a value behind every time round
+        let mut bad = chunk("WHILE 1 < 2 REPEAT\nPRINT 3\nENDWHILE\n");
+        bad.code[5] = Instruction::Number(0);
+        assert_eq!(read(&write(&bad)), Err(LoadError::StackMismatch { at: 0 }));
+
+        let mut bad = chunk("PRINT 1\n");
+        bad.code.pop();
+        bad.lines.pop();
+        assert_eq!(read(&write(&bad)), Err(LoadError::MissingHalt));
+
+        // RETURN_VALUE outside a function, and RETURN inside one
+        let mut bad = chunk_with_functions();
+        bad.code[3] = Instruction::ReturnValue;
+        assert_eq!(read(&write(&bad)), Err(LoadError::Misplaced { at: 3 }));
+        let mut bad = chunk_with_functions();
+        bad.code[9] = Instruction::Return;
+        assert_eq!(read(&write(&bad)), Err(LoadError::Misplaced { at: 9 }));
+
+        // main jumping into the function's code
+        let mut bad = chunk_with_functions();
+        bad.code[3] = Instruction::Jump(8);
+        assert_eq!(read(&write(&bad)), Err(LoadError::SharedCode { at: 8 }));
+
+        // a number where a string goes, and the other way round
+        let mut bad = chunk_with_strings();
+        bad.code[0] = Instruction::Number(0);
+        bad.numbers.push(Number::Int(1));
+        assert_eq!(read(&write(&bad)), Err(LoadError::WrongType { at: 1 }));
+        let mut bad = chunk_with_strings();
+        bad.code[5] = Instruction::PrintNumber;
+        assert_eq!(read(&write(&bad)), Err(LoadError::WrongType { at: 5 }));
+        // strings can't be put in order
+        let mut bad = chunk("IF \"a\" == \"b\" THEN\nENDIF\n");
+        bad.code[2] = Instruction::Compare(ComparisonOp::Lt);
+        assert_eq!(read(&write(&bad)), Err(LoadError::WrongType { at: 2 }));
+        // LEN of a number
+        let mut bad = chunk("PRINT LEN(\"a\")\n");
+        bad.code[0] = Instruction::Number(0);
+        bad.numbers.push(Number::Int(1));
+        assert_eq!(read(&write(&bad)), Err(LoadError::WrongType { at: 1 }));
+
+        // an element of an array that isn't there, or with a string for an index
+        let mut bad = chunk_with_arrays();
+        bad.code[7] = Instruction::LoadElement(1);
+        assert_eq!(
+            read(&write(&bad)),
+            Err(LoadError::BadOperand {
+                at: 7,
+                what: "array"
+            })
+        );
+        let mut bad = chunk_with_arrays();
+        bad.code[0] = Instruction::String(0);
+        bad.strings.push("x".to_string());
+        assert_eq!(read(&write(&bad)), Err(LoadError::WrongType { at: 3 }));
+        // too big to make
+        let mut bad = chunk_with_arrays();
+        bad.arrays[0].sizes = vec![1 << 16, 1 << 16];
+        assert!(matches!(
+            read(&write(&bad)),
+            Err(LoadError::Invalid { what: "array", .. })
+        ));
+
+        // a function that doesn't start anywhere
+        let mut bad = chunk_with_functions();
+        bad.functions[0].start = 99;
+        assert!(matches!(
+            read(&write(&bad)),
+            Err(LoadError::Invalid {
+                what: "function",
+                ..
+            })
+        ));
+    }
+}